@@ -0,0 +1,121 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Benchmark for profcollectd trace processing and report packing throughput.
+
+use anyhow::Result;
+use criterion::{BatchSize, Criterion, Throughput};
+use libprofcollectd::benchmark::Pipeline;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// ETM recording from the simpleperf test data, replicated into the synthetic trace fixture.
+static ETM_TRACE: &[u8] = include_bytes!("../../../simpleperf/testdata/etm/perf_etm.data");
+
+const BENCHMARK_DIR: &str = "/data/local/tmp/profcollectd_benchmark";
+const TRACE_COUNT: usize = 16;
+const PROFILE_COUNT: usize = 64;
+const PROFILE_SIZE: usize = 256 * 1024;
+
+fn new_criterion() -> Criterion {
+    let path = Path::new(BENCHMARK_DIR).join("criterion");
+    fs::create_dir_all(&path).unwrap_or_else(|e| {
+        panic!("The criterion folder should be possible to create at {}: {}", path.display(), e)
+    });
+    std::env::set_var("CRITERION_HOME", path);
+    Criterion::default()
+}
+
+/// Creates an empty directory for the benchmark to work in.
+fn create_empty_dir(name: &str) -> PathBuf {
+    let dir = Path::new(BENCHMARK_DIR).join(name);
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn write_trace_fixtures(trace_dir: &Path) {
+    for i in 0..TRACE_COUNT {
+        fs::write(trace_dir.join(format!("fixture_{}.etmtrace", i)), ETM_TRACE).unwrap();
+    }
+}
+
+/// Writes synthetic profiles. The content is pseudo-random but low entropy, so that compression
+/// does a similar amount of work as for real branch lists.
+fn write_profile_fixtures(profile_dir: &Path) {
+    let mut state: u32 = 0x9e37_79b9;
+    for i in 0..PROFILE_COUNT {
+        let content: Vec<u8> = (0..PROFILE_SIZE)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state % 16) as u8
+            })
+            .collect();
+        fs::write(profile_dir.join(format!("fixture_{}.data", i)), content).unwrap();
+    }
+}
+
+fn bench_process(c: &mut Criterion, pipeline: &Pipeline) {
+    if pipeline.provider_name() != "simpleperf_etm" {
+        println!("Skipping processing benchmark, trace fixtures require ETM.");
+        return;
+    }
+    let trace_dir = create_empty_dir("trace");
+    let profile_dir = create_empty_dir("profile");
+
+    let mut group = c.benchmark_group("process");
+    group.throughput(Throughput::Bytes((ETM_TRACE.len() * TRACE_COUNT) as u64));
+    group.bench_function("etm_traces", |b| {
+        b.iter_batched(
+            || write_trace_fixtures(&trace_dir),
+            // Do not filter any binaries, the fixture was not recorded from a system binary.
+            |_| pipeline.process(&trace_dir, &profile_dir, ".*").unwrap(),
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
+
+fn bench_pack(c: &mut Criterion, pipeline: &Pipeline) {
+    let profile_dir = create_empty_dir("profile");
+    let report_dir = create_empty_dir("report");
+
+    let mut group = c.benchmark_group("pack");
+    group.throughput(Throughput::Bytes((PROFILE_SIZE * PROFILE_COUNT) as u64));
+    group.bench_function("profiles", |b| {
+        b.iter_batched(
+            || write_profile_fixtures(&profile_dir),
+            |_| {
+                let report = pipeline.pack(&profile_dir, &report_dir).unwrap();
+                fs::remove_file(report_dir.join(report).with_extension("zip")).unwrap();
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
+
+fn main() -> Result<()> {
+    let mut criterion = new_criterion();
+    let pipeline = Pipeline::new()?;
+
+    bench_process(&mut criterion, &pipeline);
+    bench_pack(&mut criterion, &pipeline);
+
+    Ok(())
+}
@@ -0,0 +1,131 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! End-to-end test of the profcollectd binder service. The service is started in the test process,
+//! in place of the system daemon, with the logging trace provider, and driven through the client
+//! from a one-shot trace to a report, so that regressions in the service wiring are caught.
+
+use libprofcollectd::client::{
+    Client, TraceRequest, FEATURE_TAG_REPORTS, FEATURE_TRACE_ONCE, NO_USAGE_SETTING,
+};
+use rustutils::system_properties;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use zip::ZipArchive;
+
+const FORCE_LOGGING_PROVIDER_PROPERTY: &str = "debug.profcollectd.logging_provider";
+const DAEMON_STATE_PROPERTY: &str = "init.svc.profcollectd";
+const TRACE_DIR: &str = "/data/misc/profcollectd/trace/logging";
+const REPORT_DIR: &str = "/data/misc/profcollectd/report";
+const TAG: &str = "integration";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Wait until `condition` holds, failing the test after TIMEOUT.
+fn wait_for(what: &str, condition: impl Fn() -> bool) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(start.elapsed() < TIMEOUT, "Timed out waiting for {}.", what);
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+fn list_dir(dir: &str) -> Vec<PathBuf> {
+    fs::read_dir(dir).unwrap().map(|e| e.unwrap().path()).collect()
+}
+
+/// The service started in this process in place of the system daemon. When dropped, even by a
+/// failing test, the data of the test is cleared and the system daemon is started again if it was
+/// running.
+struct TestService {
+    daemon_was_running: bool,
+}
+
+impl TestService {
+    /// Stop the system daemon and start the service in this process.
+    fn start() -> (Self, Client) {
+        let service = TestService {
+            daemon_was_running: system_properties::read(DAEMON_STATE_PROPERTY).unwrap().as_deref()
+                == Some("running"),
+        };
+        system_properties::write("ctl.stop", "profcollectd").unwrap();
+        wait_for("the system daemon to stop", || Client::try_connect().is_err());
+        system_properties::write(FORCE_LOGGING_PROVIDER_PROPERTY, "true").unwrap();
+        libprofcollectd::reset().unwrap();
+        thread::spawn(|| libprofcollectd::init_service(false).unwrap());
+        (service, Client::connect().unwrap())
+    }
+}
+
+impl Drop for TestService {
+    fn drop(&mut self) {
+        // Errors are only logged, panicking while a failed test unwinds would abort the test run.
+        if let Err(e) = libprofcollectd::reset() {
+            eprintln!("Failed to clear the test data: {:?}", e);
+        }
+        if let Err(e) = system_properties::write(FORCE_LOGGING_PROVIDER_PROPERTY, "") {
+            eprintln!("Failed to clear {}: {:?}", FORCE_LOGGING_PROVIDER_PROPERTY, e);
+        }
+        if self.daemon_was_running {
+            if let Err(e) = system_properties::write("ctl.start", "profcollectd") {
+                eprintln!("Failed to restart the system daemon: {:?}", e);
+            }
+        }
+    }
+}
+
+#[test]
+fn trace_process_report() {
+    let (_service, client) = TestService::start();
+    assert!(client.supports(FEATURE_TRACE_ONCE).unwrap());
+    assert!(client.get_status().unwrap().contains("provider: logging\n"));
+
+    client
+        .trace_once(&TraceRequest {
+            tag: TAG.to_string(),
+            samplingPeriodMs: 100,
+            ..Default::default()
+        })
+        .unwrap();
+    let is_trace = |f: &PathBuf| f.extension().is_some_and(|ext| ext == "loggingtrace");
+    let traces: Vec<PathBuf> = list_dir(TRACE_DIR).into_iter().filter(is_trace).collect();
+    assert_eq!(traces.len(), 1, "{:?}", traces);
+    let trace_name = traces[0].file_stem().unwrap().to_str().unwrap().to_string();
+    assert!(trace_name.contains(TAG), "{}", trace_name);
+    assert!(Path::new(TRACE_DIR).join(&trace_name).with_extension("json").exists());
+
+    client.process().unwrap();
+    wait_for("the trace to be processed", || list_dir(TRACE_DIR).is_empty());
+
+    // A report scoped to other tags leaves the profile for the next report.
+    assert!(client.supports(FEATURE_TAG_REPORTS).unwrap());
+    let other_report = client.report_for_tags(&["other".to_string()]).unwrap();
+    assert_eq!(other_report.profileCount, 0);
+
+    let report = client.report(NO_USAGE_SETTING).unwrap();
+    assert_eq!(report.profileCount, 1);
+    let report_file = Path::new(REPORT_DIR).join(&report.name).with_extension("zip");
+    let mut archive = ZipArchive::new(File::open(report_file).unwrap()).unwrap();
+    let profile = format!("logging/{}", trace_name);
+    let names: Vec<&str> = archive.file_names().collect();
+    assert!(names.contains(&format!("{}.data", profile).as_str()), "{:?}", names);
+    assert!(names.contains(&format!("{}.json", profile).as_str()), "{:?}", names);
+    let manifest: serde_json::Value =
+        serde_json::from_reader(archive.by_name("manifest.json").unwrap()).unwrap();
+    assert!(manifest["correlation_ids"].get(&profile).is_some(), "{}", manifest);
+    assert!(manifest.get("install_id").is_some(), "{}", manifest);
+}
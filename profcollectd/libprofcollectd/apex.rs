@@ -0,0 +1,58 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Versions of the active APEXes, so that profiles of APEX-delivered binaries are not merged across
+//! APEX updates.
+
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+
+/// List of the APEXes on the device, written by apexd.
+const APEX_INFO_LIST: &str = "/apex/apex-info-list.xml";
+
+/// Version codes of the active APEXes, by module name.
+pub type ApexVersions = BTreeMap<String, u64>;
+
+/// Get the versions of the active APEXes, none if the APEX list cannot be read.
+pub fn get_apex_versions() -> ApexVersions {
+    match read_to_string(APEX_INFO_LIST) {
+        Ok(list) => parse_apex_info_list(&list),
+        Err(e) => {
+            log::error!("Failed to read {}: {}", APEX_INFO_LIST, e);
+            ApexVersions::new()
+        }
+    }
+}
+
+/// Parse the `<apex-info>` elements of an APEX list, e.g.
+/// `<apex-info moduleName="com.android.art" versionCode="341011000" isActive="true" ...>`.
+fn parse_apex_info_list(list: &str) -> ApexVersions {
+    list.split("<apex-info ")
+        .skip(1)
+        .filter_map(|element| {
+            // Keep the separator before the first attribute, so that all attributes are found alike.
+            let element = format!(" {}", &element[..element.find('>')?]);
+            let attribute = |name: &str| {
+                let start = element.find(&format!(" {}=\"", name))? + name.len() + 3;
+                element[start..].split('"').next()
+            };
+            if attribute("isActive")? != "true" {
+                return None;
+            }
+            Some((attribute("moduleName")?.to_string(), attribute("versionCode")?.parse().ok()?))
+        })
+        .collect()
+}
@@ -0,0 +1,217 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Integrity audit of the output directories. Unexpected files and directories are moved to the
+//! quarantine directory, and reports with wrong permissions are repaired.
+
+use anyhow::Result;
+use std::fmt::Write;
+use std::fs::{self, Permissions};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::config::{CONFIG_FILE, LOG_FILE, PROCESSING_STATS_FILE, RESOLUTION_CACHE_FILE};
+use crate::report::is_report_name;
+use crate::storage::get_tag;
+use crate::trace_metadata::is_metadata;
+use crate::trace_provider::PROVIDER_NAMES;
+
+/// Number of quarantined files and directories kept, the oldest are removed beyond.
+const QUARANTINE_SIZE: usize = 16;
+/// Permissions of reports, so that they can be shared to uploaders, see `pack_report`.
+const REPORT_MODE: u32 = 0o644;
+
+/// Outcome of an audit.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AuditSummary {
+    /// Files whose permissions were repaired.
+    pub repaired: u32,
+    /// Unexpected files moved to quarantine.
+    pub quarantined: u32,
+}
+
+/// Audit the output directories, quarantining unexpected files into `quarantine_dir`. Traces and
+/// profiles are expected in the directories of `provider` under `trace_root` and `profile_root`.
+/// The directories of the other providers are left alone, they may be used again after a provider
+/// change.
+pub fn audit(
+    trace_root: &Path,
+    profile_root: &Path,
+    report_dir: &Path,
+    quarantine_dir: &Path,
+    provider: &str,
+) -> Result<AuditSummary> {
+    fs::create_dir_all(quarantine_dir)?;
+    let mut summary = AuditSummary::default();
+    let mut quarantine = |file: &Path, why: &str| -> Result<()> {
+        log::warn!("Quarantining {}: {}", file.display(), why);
+        let name = file.to_string_lossy().trim_start_matches('/').replace('/', "_");
+        fs::rename(file, quarantine_dir.join(name))?;
+        summary.quarantined += 1;
+        Ok(())
+    };
+
+    let is_provider_dir = |file: &Path| {
+        file.is_dir()
+            && file
+                .file_name()
+                .and_then(|f| f.to_str())
+                .is_some_and(|f| PROVIDER_NAMES.contains(&f))
+    };
+    let trace_dir = trace_root.join(provider);
+    let profile_dir = profile_root.join(provider);
+    for file in list_dir(trace_root)? {
+        if !is_provider_dir(&file) {
+            quarantine(&file, "not in a provider directory")?;
+        }
+    }
+    for file in list_dir(profile_root)? {
+        let expected = [*CONFIG_FILE, *LOG_FILE, *PROCESSING_STATS_FILE, *RESOLUTION_CACHE_FILE];
+        if !is_provider_dir(&file) && !expected.contains(&file.as_path()) {
+            quarantine(&file, "not in a provider directory")?;
+        }
+    }
+    for file in list_dir(&trace_dir)? {
+        if !file.is_file() || get_tag(&file).is_none() {
+            quarantine(&file, "not a trace")?;
+        }
+    }
+    for file in list_dir(&profile_dir)? {
+        if !file.is_file() || get_tag(&file).is_none() {
+            quarantine(&file, "not a profile")?;
+        } else if is_metadata(&file) && !has_data(&file)? {
+            quarantine(&file, "orphaned metadata")?;
+        }
+    }
+    for file in list_dir(report_dir)? {
+        let is_report = file.is_file()
+            && file.extension().is_some_and(|ext| ext == "zip")
+            && file.file_stem().and_then(|f| f.to_str()).is_some_and(is_report_name);
+        if !is_report {
+            quarantine(&file, "not a report")?;
+            continue;
+        }
+        if fs::metadata(&file)?.permissions().mode() & 0o777 != REPORT_MODE {
+            log::warn!("Repairing permissions of {}", file.display());
+            fs::set_permissions(&file, Permissions::from_mode(REPORT_MODE))?;
+            summary.repaired += 1;
+        }
+    }
+
+    // Trim the quarantine, names do not sort by age so use the modification time.
+    let mut quarantined: Vec<_> = list_dir(quarantine_dir)?
+        .into_iter()
+        .filter_map(|f| Some((f.metadata().and_then(|m| m.modified()).ok()?, f)))
+        .collect();
+    quarantined.sort();
+    let excess = quarantined.len().saturating_sub(QUARANTINE_SIZE);
+    for (_, file) in quarantined.into_iter().take(excess) {
+        if file.is_dir() {
+            fs::remove_dir_all(file)?;
+        } else {
+            fs::remove_file(file)?;
+        }
+    }
+    Ok(summary)
+}
+
+fn list_dir(dir: &Path) -> Result<Vec<PathBuf>> {
+    Ok(fs::read_dir(dir)?.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+}
+
+/// Whether the profile of a metadata sidecar exists.
+fn has_data(metadata: &Path) -> Result<bool> {
+    let dir = metadata.parent().unwrap_or(Path::new("/"));
+    Ok(list_dir(dir)?.iter().any(|f| !is_metadata(f) && f.file_stem() == metadata.file_stem()))
+}
+
+/// Results of the audits since profcollectd started.
+#[derive(Default)]
+pub struct Audits {
+    last: Mutex<Option<(Instant, Result<AuditSummary, String>)>>,
+    total: Mutex<AuditSummary>,
+}
+
+impl Audits {
+    pub fn record(&self, result: Result<AuditSummary>) {
+        if let Ok(summary) = &result {
+            let mut total = self.total.lock().unwrap();
+            total.repaired += summary.repaired;
+            total.quarantined += summary.quarantined;
+        }
+        let result = result.map_err(|e| format!("{:?}", e));
+        *self.last.lock().unwrap() = Some((Instant::now(), result));
+    }
+
+    pub fn status(&self) -> String {
+        let Some((time, result)) = self.last.lock().unwrap().clone() else {
+            return String::new();
+        };
+        let total = self.total.lock().unwrap();
+        let mut status = String::new();
+        match result {
+            Ok(_) => writeln!(
+                status,
+                "last audit: {}s ago, files repaired: {}, files quarantined: {}",
+                time.elapsed().as_secs(),
+                total.repaired,
+                total.quarantined
+            ),
+            Err(e) => {
+                writeln!(status, "last audit: {}s ago, failed: {}", time.elapsed().as_secs(), e)
+            }
+        }
+        .unwrap();
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs;
+
+    use super::audit;
+    use crate::trace_metadata::new_correlation_id;
+
+    #[test]
+    fn keeps_provider_dirs_and_quarantines_strays() {
+        let root = temp_dir().join(format!("profcollectd_audit_{}", new_correlation_id()));
+        let (traces, profiles, reports, quarantine) =
+            (root.join("trace"), root.join("output"), root.join("report"), root.join("quarantine"));
+        for dir in [&traces, &profiles, &reports] {
+            fs::create_dir_all(dir.join("simpleperf_etm")).unwrap();
+        }
+        fs::remove_dir(reports.join("simpleperf_etm")).unwrap();
+        // The traces of a previous provider, and a directory of unknown origin.
+        fs::create_dir_all(traces.join("simpleperf_lbr")).unwrap();
+        fs::write(traces.join("simpleperf_lbr/20260101T000000Z_periodic.lbr"), "").unwrap();
+        fs::create_dir_all(traces.join("stray")).unwrap();
+        fs::write(traces.join("stray/file"), "kept").unwrap();
+
+        let summary = audit(&traces, &profiles, &reports, &quarantine, "simpleperf_etm").unwrap();
+        assert_eq!(summary.quarantined, 1);
+        assert!(traces.join("simpleperf_lbr/20260101T000000Z_periodic.lbr").exists());
+        let quarantined: Vec<_> =
+            fs::read_dir(&quarantine).unwrap().map(|e| e.unwrap().path()).collect();
+        assert_eq!(quarantined.len(), 1);
+        assert!(quarantined[0].to_string_lossy().ends_with("trace_stray"));
+        assert_eq!(fs::read_to_string(quarantined[0].join("file")).unwrap(), "kept");
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
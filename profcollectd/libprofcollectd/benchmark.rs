@@ -0,0 +1,67 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Entry points into the processing and packing pipeline for benchmarks.
+
+use anyhow::{anyhow, Result};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::config::Config;
+use crate::processing::{ProcessingPipeline, StageContext};
+use crate::provider_chain::ProviderChain;
+use crate::report::{pack_report_with_identifiers, ReportIdentifiers};
+use crate::trace_provider::{self, TraceProvider};
+
+/// The pipeline stages of profcollectd, operating on caller provided directories instead of the
+/// daemon's output directories.
+pub struct Pipeline {
+    config: Config,
+    trace_provider: Mutex<Box<dyn TraceProvider + Send>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Result<Self> {
+        let config = Config::from_env()?;
+        let trace_provider = trace_provider::get_trace_provider(&ProviderChain::load(&config));
+        Ok(Pipeline { config, trace_provider: Mutex::new(trace_provider) })
+    }
+
+    /// Name of the trace provider used for processing.
+    pub fn provider_name(&self) -> &'static str {
+        self.trace_provider.lock().unwrap().get_name()
+    }
+
+    /// Process all traces in `trace_dir` into profiles in `profile_dir`.
+    pub fn process(&self, trace_dir: &Path, profile_dir: &Path, binary_filter: &str) -> Result<()> {
+        let provider = self.trace_provider.lock().map_err(|e| anyhow!(e.to_string()))?;
+        ProcessingPipeline::new(&self.config)?.run(&mut StageContext::new(
+            &**provider,
+            &self.config,
+            trace_dir,
+            profile_dir,
+            binary_filter,
+            None,
+        ))
+    }
+
+    /// Pack all profiles in `profile_dir` into a report in `report_dir`. The report records scratch
+    /// identifiers, so that benchmarks leave the identifiers of the device alone.
+    pub fn pack(&self, profile_dir: &Path, report_dir: &Path) -> Result<String> {
+        Ok(pack_report_with_identifiers(profile_dir, report_dir, &ReportIdentifiers::scratch())?
+            .name)
+    }
+}
@@ -0,0 +1,159 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Blackout windows declared by the system, during which all trace collection is suppressed.
+
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::trace_metadata::time_since_boot;
+
+/// Reason of the blackout window held while a backup or restore operation is in progress.
+pub const BACKUP_REASON: &str = "backup or restore";
+/// Longest a backup blackout lasts unless it is notified again, so that collection resumes if the
+/// end of the backup is never notified, e.g. because the backup service crashed.
+pub const MAX_BACKUP_DURATION: Duration = Duration::from_secs(4 * 60 * 60);
+/// Reason of the blackout window held while the backlog of unprocessed traces is over its limit.
+pub const BACKLOG_REASON: &str = "unprocessed backlog";
+
+#[derive(Default)]
+pub struct Blackouts {
+    /// Active blackout windows by reason, with their expiry time since boot, counting suspended
+    /// time so that windows expire after their duration of wall time. None if the window lasts
+    /// until it is explicitly ended.
+    windows: Mutex<HashMap<String, Option<Duration>>>,
+    /// Number of traces suppressed by blackout windows.
+    suppressed: AtomicU64,
+}
+
+impl Blackouts {
+    /// Begin a blackout window, or extend it if there is already one for `reason`.
+    pub fn begin(&self, reason: &str, duration: Option<Duration>) {
+        log::info!("Blackout {} begins.", reason);
+        let expiry = duration.map(|d| time_since_boot() + d);
+        self.windows.lock().unwrap().insert(reason.to_string(), expiry);
+    }
+
+    pub fn end(&self, reason: &str) -> Result<()> {
+        log::info!("Blackout {} ends.", reason);
+        self.windows
+            .lock()
+            .unwrap()
+            .remove(reason)
+            .map(|_| ())
+            .ok_or_else(|| anyhow!("No blackout for {}", reason))
+    }
+
+    /// Whether there is an active blackout window for `reason`.
+    pub fn is_active(&self, reason: &str) -> bool {
+        self.active_windows().iter().any(|(r, _)| r == reason)
+    }
+
+    /// Returns whether a trace should be suppressed, counting it as such.
+    pub fn suppress(&self, tag: &str) -> bool {
+        match self.active_reasons() {
+            Some(reasons) => {
+                self.count_suppressed(tag, &reasons);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Count a trace as suppressed by the blackouts of `reasons`.
+    pub fn count_suppressed(&self, tag: &str, reasons: &str) {
+        log::info!("Trace {} suppressed by blackout: {}", tag, reasons);
+        self.suppressed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Reasons of the active windows, None if there is no blackout.
+    pub fn active_reasons(&self) -> Option<String> {
+        let windows = self.active_windows();
+        let reasons: Vec<&str> = windows.iter().map(|(reason, _)| reason.as_str()).collect();
+        (!reasons.is_empty()).then(|| reasons.join(", "))
+    }
+
+    /// Reasons of the active windows, with the time remaining until they expire.
+    pub fn active_windows(&self) -> Vec<(String, Option<Duration>)> {
+        let mut windows = self.windows.lock().unwrap();
+        let now = time_since_boot();
+        windows.retain(|_, expiry| expiry.is_none_or(|e| e > now));
+        windows.iter().map(|(reason, expiry)| (reason.clone(), expiry.map(|e| e - now))).collect()
+    }
+
+    pub fn status(&self) -> String {
+        let mut status = String::new();
+        for (reason, remaining) in self.active_windows() {
+            match remaining {
+                Some(d) => writeln!(status, "blackout: {} (expires in {}s)", reason, d.as_secs()),
+                None => writeln!(status, "blackout: {}", reason),
+            }
+            .unwrap();
+        }
+        writeln!(
+            status,
+            "traces suppressed by blackouts: {}",
+            self.suppressed.load(Ordering::Relaxed)
+        )
+        .unwrap();
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Blackouts, BACKUP_REASON};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn windows_expire() {
+        let blackouts = Blackouts::default();
+        blackouts.begin(BACKUP_REASON, Some(Duration::from_millis(20)));
+        assert!(blackouts.suppress("test"));
+        sleep(Duration::from_millis(40));
+        assert!(!blackouts.is_active(BACKUP_REASON));
+        assert!(!blackouts.suppress("test"));
+        assert!(blackouts.status().contains("traces suppressed by blackouts: 1"));
+    }
+
+    #[test]
+    fn suppresses_until_every_window_ends() {
+        let blackouts = Blackouts::default();
+        assert!(!blackouts.suppress("test"));
+        blackouts.begin("setup wizard", None);
+        blackouts.begin(BACKUP_REASON, Some(Duration::from_secs(60)));
+        let status = blackouts.status();
+        assert!(status.contains("blackout: setup wizard\n"), "{}", status);
+        assert!(status.contains("blackout: backup or restore (expires in"), "{}", status);
+
+        blackouts.end(BACKUP_REASON).unwrap();
+        assert_eq!(blackouts.active_reasons().as_deref(), Some("setup wizard"));
+        assert!(blackouts.suppress("test"));
+        // Ending a window twice is an error, the other window is untouched.
+        assert!(blackouts.end(BACKUP_REASON).is_err());
+        assert!(blackouts.is_active("setup wizard"));
+
+        blackouts.end("setup wizard").unwrap();
+        assert_eq!(blackouts.active_reasons(), None);
+        assert!(!blackouts.suppress("test"));
+        assert!(blackouts.status().contains("traces suppressed by blackouts: 1"));
+    }
+}
@@ -0,0 +1,308 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Budget of tracing and processing time per battery charge cycle, bounding the energy cost of
+//! profcollect on power-sensitive products.
+
+use anyhow::Result;
+use std::fmt::{self, Write};
+use std::fs::read_to_string;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::config::{get_spent_charge_budget, set_spent_charge_budget, Config};
+
+const CYCLE_COUNT_FILE: &str = "/sys/class/power_supply/battery/cycle_count";
+const STATUS_FILE: &str = "/sys/class/power_supply/battery/status";
+
+/// An activity drawing from the budget.
+#[derive(Clone, Copy, Debug)]
+pub enum Activity {
+    Tracing,
+    Processing,
+}
+
+/// The budget spent in a charge cycle. It is saved as
+/// `<cycle>,<charging>,<tracing ms>,<processing ms>`, so that restarting profcollectd does not
+/// reset it.
+#[derive(Default, Debug, PartialEq, Eq)]
+struct Spent {
+    /// Charge cycle count reported by the battery, None if it is not reported.
+    cycle: Option<u64>,
+    /// Whether the device was charging when last checked.
+    charging: bool,
+    tracing: Duration,
+    processing: Duration,
+}
+
+impl fmt::Display for Spent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cycle = self.cycle.map(|c| c.to_string()).unwrap_or_default();
+        write!(
+            f,
+            "{},{},{},{}",
+            cycle,
+            self.charging,
+            self.tracing.as_millis(),
+            self.processing.as_millis()
+        )
+    }
+}
+
+impl FromStr for Spent {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split(',').collect();
+        let [cycle, charging, tracing, processing] = fields[..] else {
+            anyhow::bail!("Malformed spent charge budget: {}", s);
+        };
+        Ok(Spent {
+            cycle: (!cycle.is_empty()).then(|| cycle.parse()).transpose()?,
+            charging: charging.parse()?,
+            tracing: Duration::from_millis(tracing.parse()?),
+            processing: Duration::from_millis(processing.parse()?),
+        })
+    }
+}
+
+/// Where the spent budget is saved, so that it survives restarts.
+pub trait SpentStore: Send + Sync {
+    /// The saved budget, empty if none was saved.
+    fn load(&self) -> String;
+    fn save(&self, spent: &str) -> Result<()>;
+}
+
+/// Saves the spent budget in a persistent system property.
+pub struct PropertyStore;
+
+impl SpentStore for PropertyStore {
+    fn load(&self) -> String {
+        get_spent_charge_budget()
+    }
+
+    fn save(&self, spent: &str) -> Result<()> {
+        set_spent_charge_budget(spent)
+    }
+}
+
+pub struct ChargeBudget {
+    spent: Mutex<Spent>,
+    store: Box<dyn SpentStore>,
+}
+
+impl ChargeBudget {
+    /// The budget with what was spent in the charge cycle before profcollectd last exited, as
+    /// saved in `store`.
+    pub fn load(store: Box<dyn SpentStore>) -> Self {
+        let saved = store.load();
+        let spent = match saved.as_str() {
+            "" => Spent::default(),
+            saved => saved.parse().unwrap_or_else(|e| {
+                log::error!("Failed to load the spent charge budget: {:?}", e);
+                Spent::default()
+            }),
+        };
+        ChargeBudget { spent: Mutex::new(spent), store }
+    }
+
+    /// Returns why `activity` cannot be performed if its budget for the current charge cycle is
+    /// spent, None otherwise.
+    pub fn exhausted(&self, config: &Config, activity: Activity) -> Option<String> {
+        let budget = budget(config, activity);
+        (self.remaining(config, activity) == Some(Duration::ZERO)).then(|| {
+            format!("{:?} budget of {}s per charge cycle spent", activity, budget.as_secs())
+        })
+    }
+
+    /// Clamp `duration` of `activity` to what is left of its budget for the current charge cycle,
+    /// so that a long trace does not overdraw it.
+    pub fn clamp(&self, config: &Config, activity: Activity, duration: Duration) -> Duration {
+        self.remaining(config, activity).map_or(duration, |remaining| duration.min(remaining))
+    }
+
+    /// What is left of the budget of `activity` for the current charge cycle, None if unlimited.
+    fn remaining(&self, config: &Config, activity: Activity) -> Option<Duration> {
+        let budget = budget(config, activity);
+        if budget.is_zero() {
+            return None;
+        }
+        let mut spent = self.spent.lock().unwrap();
+        self.start_new_cycle(&mut spent);
+        let spent = match activity {
+            Activity::Tracing => spent.tracing,
+            Activity::Processing => spent.processing,
+        };
+        Some(budget.saturating_sub(spent))
+    }
+
+    /// Draw `duration` of `activity` from the budget of the current charge cycle.
+    pub fn spend(&self, activity: Activity, duration: Duration) {
+        let mut spent = self.spent.lock().unwrap();
+        self.start_new_cycle(&mut spent);
+        match activity {
+            Activity::Tracing => spent.tracing += duration,
+            Activity::Processing => spent.processing += duration,
+        }
+        self.save(&spent);
+    }
+
+    fn save(&self, spent: &Spent) {
+        if let Err(e) = self.store.save(&spent.to_string()) {
+            log::error!("Failed to save the spent charge budget: {:?}", e);
+        }
+    }
+
+    /// Reset the spent budget if a new charge cycle started. Cycles are counted by the battery if
+    /// it reports them, otherwise a new cycle starts whenever the device starts charging.
+    fn start_new_cycle(&self, spent: &mut Spent) {
+        let read = |path: &str| read_to_string(path).ok().map(|s| s.trim().to_string());
+        let cycle = read(CYCLE_COUNT_FILE).and_then(|s| s.parse().ok());
+        let charging = read(STATUS_FILE).is_some_and(|s| s == "Charging" || s == "Full");
+        if update_cycle(spent, cycle, charging) {
+            self.save(spent);
+        }
+    }
+
+    pub fn status(&self) -> String {
+        let spent = self.spent.lock().unwrap();
+        let mut status = String::new();
+        writeln!(
+            status,
+            "spent this charge cycle: tracing {}s, processing {}s",
+            spent.tracing.as_secs(),
+            spent.processing.as_secs()
+        )
+        .unwrap();
+        status
+    }
+}
+
+fn budget(config: &Config, activity: Activity) -> Duration {
+    match activity {
+        Activity::Tracing => config.max_tracing_per_charge_cycle,
+        Activity::Processing => config.max_processing_per_charge_cycle,
+    }
+}
+
+/// Update `spent` to the battery `cycle` and `charging` state, returns whether it changed.
+fn update_cycle(spent: &mut Spent, cycle: Option<u64>, charging: bool) -> bool {
+    let new_cycle = match cycle {
+        Some(_) => cycle != spent.cycle,
+        None => charging && !spent.charging,
+    };
+    if new_cycle {
+        log::info!("New charge cycle, resetting the tracing and processing budget.");
+        spent.tracing = Duration::ZERO;
+        spent.processing = Duration::ZERO;
+    }
+    let changed = new_cycle || charging != spent.charging;
+    spent.cycle = cycle;
+    spent.charging = charging;
+    changed
+}
+
+/// Keeps the spent budget in memory, leaving the one of the device alone.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryStore(Mutex<String>);
+
+#[cfg(test)]
+impl SpentStore for MemoryStore {
+    fn load(&self) -> String {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn save(&self, spent: &str) -> Result<()> {
+        *self.0.lock().unwrap() = spent.to_string();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{update_cycle, Activity, ChargeBudget, MemoryStore, Spent, SpentStore};
+    use crate::config::Config;
+
+    /// The store is shared by the budgets of successive runs of profcollectd.
+    impl SpentStore for Arc<MemoryStore> {
+        fn load(&self) -> String {
+            self.as_ref().load()
+        }
+
+        fn save(&self, spent: &str) -> anyhow::Result<()> {
+            self.as_ref().save(spent)
+        }
+    }
+
+    #[test]
+    fn saves_the_spent_budget() {
+        let spent = Spent {
+            cycle: Some(12),
+            charging: false,
+            tracing: Duration::from_millis(1500),
+            processing: Duration::from_secs(60),
+        };
+        assert_eq!(spent.to_string(), "12,false,1500,60000");
+        assert_eq!(spent.to_string().parse::<Spent>().unwrap(), spent);
+        let unknown_cycle: Spent = ",true,0,0".parse().unwrap();
+        assert_eq!(unknown_cycle, Spent { charging: true, ..Default::default() });
+        assert!("12,false,1500".parse::<Spent>().is_err());
+    }
+
+    #[test]
+    fn spent_budget_survives_restarts() {
+        let mut config = Config::from_env().unwrap();
+        config.max_tracing_per_charge_cycle = Duration::from_secs(120);
+        let store = Arc::new(MemoryStore::default());
+        let budget = ChargeBudget::load(Box::new(store.clone()));
+        budget.spend(Activity::Tracing, Duration::from_secs(100));
+
+        // A restarted profcollectd only has 20s left, and clamps traces to them.
+        let budget = ChargeBudget::load(Box::new(store));
+        let requested = Duration::from_secs(60);
+        assert_eq!(budget.clamp(&config, Activity::Tracing, requested), Duration::from_secs(20));
+        assert_eq!(budget.exhausted(&config, Activity::Tracing), None);
+        budget.spend(Activity::Tracing, Duration::from_secs(20));
+        assert_eq!(budget.clamp(&config, Activity::Tracing, requested), Duration::ZERO);
+        assert!(budget.exhausted(&config, Activity::Tracing).is_some());
+
+        // Unlimited budgets never clamp.
+        config.max_tracing_per_charge_cycle = Duration::ZERO;
+        assert_eq!(budget.clamp(&config, Activity::Tracing, requested), requested);
+    }
+
+    #[test]
+    fn resets_on_new_cycles() {
+        let mut spent = Spent { tracing: Duration::from_secs(10), ..Default::default() };
+        assert!(!update_cycle(&mut spent, None, false));
+        assert_eq!(spent.tracing, Duration::from_secs(10));
+        // Without a cycle count, a cycle starts when charging starts.
+        assert!(update_cycle(&mut spent, None, true));
+        assert_eq!(spent.tracing, Duration::ZERO);
+
+        spent.tracing = Duration::from_secs(10);
+        assert!(update_cycle(&mut spent, Some(3), false));
+        assert_eq!(spent.tracing, Duration::ZERO);
+        spent.tracing = Duration::from_secs(10);
+        assert!(update_cycle(&mut spent, Some(3), true));
+        assert_eq!(spent.tracing, Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,362 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Binder client of profcollectd, for system components which talk to the daemon directly.
+//!
+//! ```ignore
+//! let client = Client::connect()?;
+//! let report = client.report(NO_USAGE_SETTING)?;
+//! ```
+//!
+//! Every call blocks until profcollectd replies. The `*_async` variants perform the call on a
+//! separate thread and return a future, which can be awaited from any executor.
+
+use profcollectd_aidl_interface::aidl::com::android::server::profcollect::IProfCollectd::{
+    IProfCollectd, ERROR_BUSY,
+};
+use profcollectd_aidl_interface::binder::{self, ExceptionCode, StatusCode, Strong};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Duration;
+
+pub use crate::report::{MAX_REPORT_CHUNK_BYTES, NO_USAGE_SETTING};
+pub use profcollectd_aidl_interface::aidl::com::android::server::profcollect::IProfCollectd::{
+    FEATURE_ARM_BOOT_TRACE, FEATURE_BACKUP_STATE, FEATURE_BLACKOUT, FEATURE_CALL_STATE,
+    FEATURE_CLEAR_PRIVATE_DATA, FEATURE_COMMAND_QUEUE, FEATURE_DIAGNOSTICS_BUNDLE,
+    FEATURE_HARDWARE_CAPABILITIES, FEATURE_INTENSIVE_MODE, FEATURE_PROFILE_SUMMARY,
+    FEATURE_PROVIDER_STATUS_CALLBACK, FEATURE_REPORT_CHUNKS, FEATURE_RUN_CLEANUP,
+    FEATURE_SCHEDULE_SIMULATION, FEATURE_STORAGE_USAGE, FEATURE_TAG_REPORTS, FEATURE_TRACE_ONCE,
+    FEATURE_TRACE_PRIORITY, FEATURE_ZYGOTE_PHASE,
+};
+pub use profcollectd_aidl_interface::aidl::com::android::server::profcollect::{
+    HardwareCapabilities::HardwareCapabilities, ProfileFreshness::ProfileFreshness,
+    ReportResult::ReportResult, TagStorageUsage::TagStorageUsage, TraceAnnotation::TraceAnnotation,
+    TraceRequest::TraceRequest, ZygotePhase::ZygotePhase,
+};
+
+/// Name profcollectd is registered under with servicemanager.
+pub const SERVICE_NAME: &str = "profcollectd";
+
+/// Errors returned by [`Client`].
+#[derive(Debug)]
+pub enum Error {
+    /// profcollectd is not running, or could not be connected to.
+    ServiceUnavailable(binder::StatusCode),
+    /// profcollectd rejected the request, or failed to perform it.
+    Rejected(String),
+    /// profcollectd was too busy with other requests changing the collection state, the request can
+    /// be retried later.
+    Busy(String),
+    /// The binder transaction itself failed, e.g. because profcollectd died.
+    Binder(binder::Status),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ServiceUnavailable(code) => {
+                write!(f, "Failed to connect to profcollectd, is it running? ({})", code)
+            }
+            Error::Rejected(msg) => write!(f, "profcollectd failed the request: {}", msg),
+            Error::Busy(msg) => write!(f, "profcollectd is busy: {}", msg),
+            Error::Binder(status) => write!(f, "profcollectd transaction failed: {}", status),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<binder::Status> for Error {
+    fn from(status: binder::Status) -> Self {
+        // The service reports all of its failures as service-specific errors, see
+        // `err_to_binder_status`.
+        if status.exception_code() != ExceptionCode::SERVICE_SPECIFIC {
+            Error::Binder(status)
+        } else if status.service_specific_error() == ERROR_BUSY {
+            Error::Busy(status.get_description())
+        } else {
+            Error::Rejected(status.get_description())
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A connection to profcollectd.
+#[derive(Clone)]
+pub struct Client {
+    service: Strong<dyn IProfCollectd>,
+}
+
+impl Client {
+    /// Connect to profcollectd, waiting for it to be started if needed.
+    pub fn connect() -> Result<Self> {
+        let service =
+            binder::wait_for_interface(SERVICE_NAME).map_err(Error::ServiceUnavailable)?;
+        Ok(Self { service })
+    }
+
+    /// Connect to profcollectd, failing immediately if it is not running.
+    pub fn try_connect() -> Result<Self> {
+        let service = binder::check_interface(SERVICE_NAME).map_err(Error::ServiceUnavailable)?;
+        Ok(Self { service })
+    }
+
+    /// The underlying binder interface, for calls not covered by this client.
+    pub fn service(&self) -> &Strong<dyn IProfCollectd> {
+        &self.service
+    }
+
+    /// Schedule periodic profile collection.
+    pub fn schedule(&self) -> Result<()> {
+        Ok(self.service.schedule()?)
+    }
+
+    /// Terminate periodic profile collection.
+    pub fn terminate(&self) -> Result<()> {
+        Ok(self.service.terminate()?)
+    }
+
+    /// Immediately perform a system-wide trace.
+    pub fn trace_system(&self, tag: &str) -> Result<()> {
+        Ok(self.service.trace_system(tag)?)
+    }
+
+    /// Perform a one-shot trace, which is queued for up to `request.expirySecs` if it cannot be
+    /// performed immediately.
+    pub fn trace_once(&self, request: &TraceRequest) -> Result<()> {
+        Ok(self.service.trace_once(request)?)
+    }
+
+    /// Process traces.
+    pub fn process(&self) -> Result<()> {
+        Ok(self.service.process()?)
+    }
+
+    /// Process traces and report profile.
+    pub fn report(&self, usage_setting: i32) -> Result<ReportResult> {
+        Ok(self.service.report(usage_setting)?)
+    }
+
+    /// Process traces and report only the profiles of `tags`, keeping the others for the next
+    /// report.
+    pub fn report_for_tags(&self, tags: &[String]) -> Result<ReportResult> {
+        Ok(self.service.report_for_tags(tags)?)
+    }
+
+    /// Get the age of the newest processed profile and of the newest report, None if there is none.
+    pub fn get_profile_freshness(&self) -> Result<(Option<Duration>, Option<Duration>)> {
+        let freshness: ProfileFreshness = self.service.get_profile_freshness()?;
+        let to_duration = |secs: i64| u64::try_from(secs).ok().map(Duration::from_secs);
+        Ok((to_duration(freshness.profileAgeSecs), to_duration(freshness.reportAgeSecs)))
+    }
+
+    /// Suppress all trace collection until the blackout for `reason` ends, or `duration` passes.
+    pub fn begin_blackout(&self, reason: &str, duration: Option<Duration>) -> Result<()> {
+        let duration_secs = duration.map_or(0, |d| d.as_secs().clamp(1, i32::MAX as u64) as i32);
+        Ok(self.service.begin_blackout(reason, duration_secs)?)
+    }
+
+    /// End the blackout for `reason`.
+    pub fn end_blackout(&self, reason: &str) -> Result<()> {
+        Ok(self.service.end_blackout(reason)?)
+    }
+
+    /// Notify profcollectd of the start and end of backup and restore operations.
+    pub fn notify_backup_state(&self, in_progress: bool) -> Result<()> {
+        Ok(self.service.notify_backup_state(in_progress)?)
+    }
+
+    /// Hint that `pid` enters the zygote `phase`: `pid` is zygote for `ZygotePhase::PRELOAD`, or
+    /// the child it just forked for `ZygotePhase::FORK`. The call does not wait for the trace.
+    pub fn notify_zygote_phase(&self, phase: ZygotePhase, pid: u32) -> Result<()> {
+        Ok(self.service.notify_zygote_phase(phase, pid as i32)?)
+    }
+
+    /// Collect more often and for longer for `duration`, then return to the normal schedule. A
+    /// zero `duration` returns to the normal schedule now.
+    pub fn set_intensive_mode(&self, duration: Duration) -> Result<()> {
+        let duration_secs = duration.as_secs().min(i32::MAX as u64) as i32;
+        Ok(self.service.set_intensive_mode(duration_secs)?)
+    }
+
+    /// Get the name of the trace provider in use.
+    pub fn get_supported_provider(&self) -> Result<String> {
+        Ok(self.service.get_supported_provider()?)
+    }
+
+    /// Trace the boot on the next start of profcollectd if `enable`, or skip it otherwise,
+    /// whatever the boot_trace config. Only the next start is affected.
+    pub fn arm_boot_trace(&self, enable: bool) -> Result<()> {
+        Ok(self.service.arm_boot_trace(enable)?)
+    }
+
+    /// Get a human-readable status of profcollectd.
+    pub fn get_status(&self) -> Result<String> {
+        Ok(self.service.get_status()?)
+    }
+
+    /// Simulate the periodic collection of the next 24 hours from the current state and config, as
+    /// a human-readable timeline, to check a config before rolling it out.
+    pub fn simulate_schedule(&self) -> Result<String> {
+        Ok(self.service.simulate_schedule()?)
+    }
+
+    /// Summarize the processed profiles, as the binaries and functions executed the most. Lists up
+    /// to `max_entries` binaries and functions per binary, or a default number.
+    pub fn summarize_profiles(&self, max_entries: Option<usize>) -> Result<String> {
+        let max_entries = max_entries.map_or(0, |n| i32::try_from(n).unwrap_or(i32::MAX));
+        Ok(self.service.summarize_profiles(max_entries)?)
+    }
+
+    /// Get a zip of the redacted status, config and trace log of profcollectd, with no trace or
+    /// profile content, suitable for attaching to bug reports.
+    pub fn get_diagnostics_bundle(&self) -> Result<Vec<u8>> {
+        Ok(self.service.get_diagnostics_bundle()?)
+    }
+
+    /// Remove stale files and expired reports, and evict traces, profiles and reports over their
+    /// storage limits. Returns the number of bytes freed.
+    pub fn run_cleanup(&self) -> Result<u64> {
+        Ok(self.service.run_cleanup()?.try_into().unwrap_or(0))
+    }
+
+    /// Delete all traces, profiles and reports, and reset the install ID and device salt recorded
+    /// in report manifests.
+    pub fn clear_private_data(&self) -> Result<()> {
+        Ok(self.service.clear_private_data()?)
+    }
+
+    /// Get the storage used by traces and profiles, by tag.
+    pub fn get_storage_usage(&self) -> Result<Vec<TagStorageUsage>> {
+        Ok(self.service.get_storage_usage()?)
+    }
+
+    /// Get the PMU events, ETM and SPE capabilities exposed by the hardware.
+    pub fn get_hardware_capabilities(&self) -> Result<HardwareCapabilities> {
+        Ok(self.service.get_hardware_capabilities()?)
+    }
+
+    /// Get the bitmask of the optional `FEATURE_*` the daemon supports. Daemons which predate
+    /// feature discovery support none of them.
+    pub fn get_interface_capabilities(&self) -> Result<i64> {
+        match self.service.get_interface_capabilities() {
+            Err(status)
+                if status.exception_code() == ExceptionCode::TRANSACTION_FAILED
+                    && status.transaction_error() == StatusCode::UNKNOWN_TRANSACTION =>
+            {
+                Ok(0)
+            }
+            result => Ok(result?),
+        }
+    }
+
+    /// Whether the daemon supports all of `features`, so that callers can skip calls it does not
+    /// implement, e.g. `client.supports(FEATURE_REPORT_CHUNKS)?`.
+    pub fn supports(&self, features: i64) -> Result<bool> {
+        Ok(self.get_interface_capabilities()? & features == features)
+    }
+
+    /// Read up to `len` bytes of the report `name`, starting at `offset`. At most
+    /// [`MAX_REPORT_CHUNK_BYTES`] are returned per call, and none past the end of the report, so a
+    /// report can be transferred in resumable chunks:
+    ///
+    /// ```ignore
+    /// let mut offset = 0;
+    /// loop {
+    ///     let chunk = client.read_report_chunk(&name, offset, MAX_REPORT_CHUNK_BYTES)?;
+    ///     if chunk.is_empty() {
+    ///         break;
+    ///     }
+    ///     upload(&chunk)?;
+    ///     offset += chunk.len() as u64;
+    /// }
+    /// ```
+    pub fn read_report_chunk(&self, name: &str, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let offset = offset.try_into().unwrap_or(i64::MAX);
+        let len = len.min(MAX_REPORT_CHUNK_BYTES) as i32;
+        Ok(self.service.read_report_chunk(name, offset, len)?)
+    }
+
+    /// Asynchronous variant of [`Client::trace_once`].
+    pub fn trace_once_async(&self, request: TraceRequest) -> Call<Result<()>> {
+        self.call(move |client| client.trace_once(&request))
+    }
+
+    /// Asynchronous variant of [`Client::process`].
+    pub fn process_async(&self) -> Call<Result<()>> {
+        self.call(Client::process)
+    }
+
+    /// Asynchronous variant of [`Client::report`].
+    pub fn report_async(&self, usage_setting: i32) -> Call<Result<ReportResult>> {
+        self.call(move |client| client.report(usage_setting))
+    }
+
+    /// Asynchronous variant of [`Client::get_status`].
+    pub fn get_status_async(&self) -> Call<Result<String>> {
+        self.call(Client::get_status)
+    }
+
+    /// Perform `f` on a separate thread, the returned future completes with its result.
+    pub fn call<T, F>(&self, f: F) -> Call<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Client) -> T + Send + 'static,
+    {
+        let shared = Arc::new(Mutex::new(CallState { result: None, waker: None }));
+        let client = self.clone();
+        let thread_shared = shared.clone();
+        thread::spawn(move || {
+            let result = f(&client);
+            let mut state = thread_shared.lock().unwrap();
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+        Call { shared }
+    }
+}
+
+struct CallState<T> {
+    result: Option<T>,
+    waker: Option<Waker>,
+}
+
+/// Future of a call performed by [`Client::call`].
+pub struct Call<T> {
+    shared: Arc<Mutex<CallState<T>>>,
+}
+
+impl<T> Future for Call<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.shared.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
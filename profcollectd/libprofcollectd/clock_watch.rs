@@ -0,0 +1,128 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Detection of the clock discontinuities breaking the periodic schedule: suspends, during which
+//! the monotonic clock the schedule runs on stops, and wall clock jumps, e.g. from time sync.
+
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::trace_metadata::ClockSnapshot;
+
+/// Difference between the wall clock and boottime progress reported as a wall clock jump.
+const WALL_CLOCK_JUMP_THRESHOLD: Duration = Duration::from_secs(60);
+
+/// Clock discontinuities seen since profcollectd started.
+#[derive(Default)]
+pub struct ClockWatch {
+    last: Mutex<Option<ClockSnapshot>>,
+    suspends: Mutex<u32>,
+    wall_clock_jumps: Mutex<u32>,
+}
+
+impl ClockWatch {
+    /// Take a reading of the clocks, against which the next check is made.
+    pub fn anchor(&self) {
+        *self.last.lock().unwrap() = Some(ClockSnapshot::now());
+    }
+
+    /// Check for discontinuities since the last anchor or check, and re-anchor. Returns how long
+    /// the device was suspended, if longer than `min_suspend`. Wall clock jumps are only logged,
+    /// as the schedule does not depend on the wall clock.
+    pub fn check(&self, min_suspend: Duration) -> Option<Duration> {
+        self.check_at(ClockSnapshot::now(), min_suspend)
+    }
+
+    fn check_at(&self, now: ClockSnapshot, min_suspend: Duration) -> Option<Duration> {
+        let last = self.last.lock().unwrap().replace(now)?;
+        let elapsed = |from: u64, to: u64| to as i64 - from as i64;
+        let monotonic = elapsed(last.monotonic_ns, now.monotonic_ns);
+        let boottime = elapsed(last.boottime_ns, now.boottime_ns);
+        let realtime = elapsed(last.realtime_ns, now.realtime_ns);
+
+        // The wall clock moves further than boottime on a forward jump, less on a backward one.
+        let jump_secs = (realtime - boottime) / 1_000_000_000;
+        if jump_secs.unsigned_abs() > WALL_CLOCK_JUMP_THRESHOLD.as_secs() {
+            log::info!("Wall clock jumped by {}s.", jump_secs);
+            *self.wall_clock_jumps.lock().unwrap() += 1;
+        }
+        let suspended = Duration::from_nanos((boottime - monotonic).max(0) as u64);
+        if suspended <= min_suspend {
+            return None;
+        }
+        log::info!("Device was suspended for {}s.", suspended.as_secs());
+        *self.suspends.lock().unwrap() += 1;
+        Some(suspended)
+    }
+
+    pub fn status(&self) -> String {
+        let suspends = *self.suspends.lock().unwrap();
+        let jumps = *self.wall_clock_jumps.lock().unwrap();
+        let mut status = String::new();
+        if suspends > 0 || jumps > 0 {
+            writeln!(status, "clock: {} long suspends, {} wall clock jumps", suspends, jumps)
+                .unwrap();
+        }
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ClockWatch;
+    use crate::trace_metadata::ClockSnapshot;
+    use std::time::Duration;
+
+    const SEC: u64 = 1_000_000_000;
+
+    fn snapshot(awake_secs: u64, suspended_secs: u64) -> ClockSnapshot {
+        let boottime_ns = (awake_secs + suspended_secs) * SEC;
+        ClockSnapshot {
+            realtime_ns: 1_700_000_000 * SEC + boottime_ns,
+            monotonic_ns: awake_secs * SEC,
+            boottime_ns,
+        }
+    }
+
+    #[test]
+    fn reports_suspends_longer_than_interval_and_re_anchors() {
+        let interval = Duration::from_secs(600);
+        let watch = ClockWatch::default();
+        *watch.last.lock().unwrap() = Some(snapshot(0, 0));
+
+        // Mostly suspended: 10 minutes awake over 5 hours.
+        assert_eq!(
+            watch.check_at(snapshot(600, 17400), interval),
+            Some(Duration::from_secs(17400))
+        );
+        // The next check is against the new anchor, and an awake interval is no suspend.
+        assert_eq!(watch.check_at(snapshot(1200, 17400), interval), None);
+        // Short suspends are not reported.
+        assert_eq!(watch.check_at(snapshot(1800, 17700), interval), None);
+        assert_eq!(watch.status(), "clock: 1 long suspends, 0 wall clock jumps\n");
+    }
+
+    #[test]
+    fn counts_wall_clock_jumps() {
+        let watch = ClockWatch::default();
+        *watch.last.lock().unwrap() = Some(snapshot(0, 0));
+        let mut jumped = snapshot(600, 0);
+        jumped.realtime_ns += 3600 * SEC;
+        assert_eq!(watch.check_at(jumped, Duration::from_secs(600)), None);
+        assert_eq!(watch.status(), "clock: 0 long suspends, 1 wall clock jumps\n");
+    }
+}
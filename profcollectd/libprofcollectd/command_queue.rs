@@ -0,0 +1,211 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Serialization of the binder calls which change the collection state. The calls are run one at a
+//! time in arrival order, and are rejected as busy when too many are already waiting, or when they
+//! waited too long for those ahead of them, instead of piling up on the service lock.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Most commands waiting behind the running one. Further commands are rejected.
+pub const MAX_QUEUED_COMMANDS: usize = 8;
+/// Longest wait of a command for the commands ahead of it, after which it is rejected.
+pub const MAX_COMMAND_WAIT: Duration = Duration::from_secs(30);
+
+/// A command rejected because the queue was full, or it waited too long.
+#[derive(Debug)]
+pub struct Busy {
+    pub command: &'static str,
+    /// The command running when it was rejected.
+    pub running: Option<&'static str>,
+    pub queued: usize,
+}
+
+impl fmt::Display for Busy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} rejected, busy running {} with {} commands queued",
+            self.command,
+            self.running.unwrap_or("nothing"),
+            self.queued
+        )
+    }
+}
+
+impl std::error::Error for Busy {}
+
+struct QueuedCommand {
+    ticket: u64,
+    command: &'static str,
+}
+
+/// Commands in arrival order, the running one first.
+#[derive(Default)]
+struct Commands {
+    next_ticket: u64,
+    queue: VecDeque<QueuedCommand>,
+}
+
+impl Commands {
+    fn busy(&self, command: &'static str) -> Busy {
+        Busy {
+            command,
+            running: self.queue.front().map(|c| c.command),
+            queued: self.queue.len().saturating_sub(1),
+        }
+    }
+}
+
+pub struct CommandQueue {
+    commands: Mutex<Commands>,
+    turn: Condvar,
+    max_queued: usize,
+    max_wait: Duration,
+}
+
+impl Default for CommandQueue {
+    fn default() -> Self {
+        CommandQueue::new(MAX_QUEUED_COMMANDS, MAX_COMMAND_WAIT)
+    }
+}
+
+/// The turn of a command, which ends when it is dropped.
+pub struct CommandGuard<'a> {
+    queue: &'a CommandQueue,
+    ticket: u64,
+}
+
+impl Drop for CommandGuard<'_> {
+    fn drop(&mut self) {
+        let mut commands = self.queue.commands.lock().unwrap();
+        commands.queue.retain(|c| c.ticket != self.ticket);
+        self.queue.turn.notify_all();
+    }
+}
+
+impl CommandQueue {
+    pub fn new(max_queued: usize, max_wait: Duration) -> Self {
+        CommandQueue {
+            commands: Mutex::new(Commands::default()),
+            turn: Condvar::new(),
+            max_queued,
+            max_wait,
+        }
+    }
+
+    /// Wait for the turn of `command`, which lasts until the returned guard is dropped.
+    pub fn admit(&self, command: &'static str) -> Result<CommandGuard<'_>, Busy> {
+        let mut commands = self.commands.lock().unwrap();
+        if commands.queue.len() > self.max_queued {
+            return Err(commands.busy(command));
+        }
+        let ticket = commands.next_ticket;
+        commands.next_ticket += 1;
+        commands.queue.push_back(QueuedCommand { ticket, command });
+        let deadline = Instant::now() + self.max_wait;
+        while commands.queue.front().map(|c| c.ticket) != Some(ticket) {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                commands.queue.retain(|c| c.ticket != ticket);
+                log::info!("Command {} rejected after waiting {:?}.", command, self.max_wait);
+                return Err(commands.busy(command));
+            }
+            commands = self.turn.wait_timeout(commands, timeout).unwrap().0;
+        }
+        Ok(CommandGuard { queue: self, ticket })
+    }
+
+    pub fn status(&self) -> String {
+        let commands = self.commands.lock().unwrap();
+        match commands.queue.front() {
+            Some(running) => {
+                format!("command: {} ({} queued)\n", running.command, commands.queue.len() - 1)
+            }
+            None => String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::CommandQueue;
+
+    /// Wait until `queued` commands wait behind `running`.
+    fn wait_queued(queue: &CommandQueue, running: &str, queued: usize) {
+        let status = format!("command: {} ({} queued)\n", running, queued);
+        while queue.status() != status {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn runs_commands_in_arrival_order() {
+        let queue = Arc::new(CommandQueue::new(4, Duration::from_secs(10)));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let running = queue.admit("process").unwrap();
+        let mut waiters = Vec::new();
+        for (queued, command) in ["schedule", "trace_once", "terminate"].into_iter().enumerate() {
+            let (queue_ref, order) = (queue.clone(), order.clone());
+            waiters.push(thread::spawn(move || {
+                let _command = queue_ref.admit(command).unwrap();
+                order.lock().unwrap().push(command);
+            }));
+            wait_queued(&queue, "process", queued + 1);
+        }
+        drop(running);
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), vec!["schedule", "trace_once", "terminate"]);
+        assert_eq!(queue.status(), "");
+    }
+
+    #[test]
+    fn rejects_commands_over_the_queue_limit() {
+        let queue = Arc::new(CommandQueue::new(1, Duration::from_secs(10)));
+        let running = queue.admit("process").unwrap();
+        let queue_ref = queue.clone();
+        let waiter = thread::spawn(move || queue_ref.admit("schedule").map(drop).is_ok());
+        wait_queued(&queue, "process", 1);
+
+        let busy = queue.admit("terminate").err().unwrap();
+        assert_eq!(busy.command, "terminate");
+        assert_eq!(busy.running, Some("process"));
+        assert_eq!(busy.queued, 1);
+        drop(running);
+        assert!(waiter.join().unwrap());
+    }
+
+    #[test]
+    fn rejects_commands_waiting_too_long() {
+        let queue = CommandQueue::new(4, Duration::from_millis(10));
+        let running = queue.admit("report").unwrap();
+        let busy = queue.admit("schedule").err().unwrap();
+        assert_eq!(busy.running, Some("report"));
+        // The rejected command leaves the queue.
+        assert_eq!(queue.status(), "command: report (0 queued)\n");
+        drop(running);
+        assert!(queue.admit("schedule").is_ok());
+    }
+}
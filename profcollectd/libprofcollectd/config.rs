@@ -17,22 +17,42 @@
 //! ProfCollect configurations.
 
 use anyhow::Result;
-use macaddr::MacAddr6;
+use hmac::{Hmac, Mac};
 use once_cell::sync::Lazy;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fs::{read_dir, remove_file};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
+
+use crate::processing::DEFAULT_PROCESSING_STAGES;
+use crate::trace_provider::ProfileFormat;
 
 const PROFCOLLECT_CONFIG_NAMESPACE: &str = "aconfig_flags.profcollect_native_boot";
-const PROFCOLLECT_NODE_ID_PROPERTY: &str = "persist.profcollectd.node_id";
+const PROFCOLLECT_POST_OTA_PROPERTY: &str = "persist.profcollectd.post_ota";
+const PROFCOLLECT_BOOT_TRACE_PROPERTY: &str = "persist.profcollectd.boot_trace";
+const PROFCOLLECT_SALT_PROPERTY: &str = "persist.profcollectd.salt";
+const PROFCOLLECT_INSTALL_ID_PROPERTY: &str = "persist.profcollectd.install_id";
+const PROFCOLLECT_ETM_AUX_BUFFER_PROPERTY: &str = "persist.profcollectd.etm_aux_buffer_kb";
+const PROFCOLLECT_FAILED_PROVIDERS_PROPERTY: &str = "persist.profcollectd.failed_providers";
+const PROFCOLLECT_CHARGE_BUDGET_PROPERTY: &str = "persist.profcollectd.charge_budget";
 
 const DEFAULT_BINARY_FILTER: &str = "(^/(system|apex/.+|vendor)/(bin|lib64)/.+)|\
     (^/data/app/.+\\.so$)|kernel.kallsyms";
+/// 32-bit libraries, including those of preinstalled apps, which are not matched by the default
+/// binary filter.
+const DEFAULT_BINARY_FILTER_32: &str = "(^/(system|apex/.+|vendor)/lib/.+)|\
+    (^/(system|system_ext|product)/(app|priv-app)/.+/lib/arm/.+\\.so$)";
+/// Trace providers tried in order, see `provider_chain`.
+const DEFAULT_PROVIDER_CHAIN: &str = "simpleperf_etm,simpleperf_lbr";
+/// Safety bounds of the intensive mode collection interval and sampling period.
+const MIN_INTENSIVE_COLLECTION_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_INTENSIVE_SAMPLING_PERIOD: Duration = Duration::from_secs(5);
 pub const REPORT_RETENTION_SECS: u64 = 14 * 24 * 60 * 60; // 14 days.
 
 // Static configs that cannot be changed.
@@ -42,47 +62,428 @@ pub static PROFILE_OUTPUT_DIR: Lazy<&'static Path> =
     Lazy::new(|| Path::new("/data/misc/profcollectd/output/"));
 pub static REPORT_OUTPUT_DIR: Lazy<&'static Path> =
     Lazy::new(|| Path::new("/data/misc/profcollectd/report/"));
+pub static QUARANTINE_DIR: Lazy<&'static Path> =
+    Lazy::new(|| Path::new("/data/misc/profcollectd/quarantine/"));
 pub static CONFIG_FILE: Lazy<&'static Path> =
     Lazy::new(|| Path::new("/data/misc/profcollectd/output/config.json"));
 pub static LOG_FILE: Lazy<&'static Path> =
     Lazy::new(|| Path::new("/data/misc/profcollectd/output/trace.log"));
+pub static PROCESSING_STATS_FILE: Lazy<&'static Path> =
+    Lazy::new(|| Path::new("/data/misc/profcollectd/output/processing_stats.json"));
+pub static RESOLUTION_CACHE_FILE: Lazy<&'static Path> =
+    Lazy::new(|| Path::new("/data/misc/profcollectd/output/resolution_cache.json"));
+
+/// Directory of the traces of `provider` under TRACE_OUTPUT_DIR, so that the outputs of different
+/// providers never collide.
+pub fn get_trace_dir(provider: &str) -> PathBuf {
+    TRACE_OUTPUT_DIR.join(provider)
+}
+
+/// Directory of the profiles of `provider` under PROFILE_OUTPUT_DIR. Its structure is kept in
+/// reports, so that profiles can be routed by provider.
+pub fn get_profile_dir(provider: &str) -> PathBuf {
+    PROFILE_OUTPUT_DIR.join(provider)
+}
+
+/// What to do with a trace request arriving while another trace is being recorded.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub enum ConcurrentTracePolicy {
+    /// Record the trace after the running one finishes.
+    Queue,
+    /// Add the tag to the running trace instead, if it is system-wide. Otherwise, queue.
+    Merge,
+}
+
+/// Clock used to timestamp the samples of traces.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub enum TraceClock {
+    /// The default clock of perf events.
+    Perf,
+    Realtime,
+    Monotonic,
+    MonotonicRaw,
+    Boottime,
+}
+
+impl TraceClock {
+    /// Value of the simpleperf record --clockid option, None for the default clock.
+    pub fn clockid(&self) -> Option<&'static str> {
+        match self {
+            TraceClock::Perf => None,
+            TraceClock::Realtime => Some("realtime"),
+            TraceClock::Monotonic => Some("monotonic"),
+            TraceClock::MonotonicRaw => Some("monotonic_raw"),
+            TraceClock::Boottime => Some("boottime"),
+        }
+    }
+}
+
+/// Whether raw kernel addresses are scrubbed from profiles, see `kernel_addresses`.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub enum KernelAddressPolicy {
+    Keep,
+    Scrub,
+    /// Scrub unless /proc/sys/kernel/kptr_restrict is 0.
+    KptrRestrict,
+}
+
+/// Set of configuration defaults, chosen from the build type. Device config overrides either.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub enum BuildTier {
+    /// User builds: less frequent collection within tighter bounds, and no simpleperf trace log.
+    User,
+    /// Userdebug and eng builds: more frequent collection, and the simpleperf trace log is kept
+    /// for debugging.
+    Userdebug,
+}
+
+impl BuildTier {
+    fn current() -> Self {
+        match get_property("ro.build.type", "user".to_string()).as_deref() {
+            Ok("userdebug") | Ok("eng") => BuildTier::Userdebug,
+            _ => BuildTier::User,
+        }
+    }
+
+    /// The default for this tier, between `user` and `userdebug`.
+    fn pick<T>(self, user: T, userdebug: T) -> T {
+        match self {
+            BuildTier::User => user,
+            BuildTier::Userdebug => userdebug,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BuildTier::User => "user",
+            BuildTier::Userdebug => "userdebug",
+        }
+    }
+}
+
+/// Weights of the eviction score of traces, profiles and reports, see `eviction::select`. The
+/// files with the highest score are evicted first.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct EvictionWeights {
+    /// Score per hour of age.
+    pub age: u32,
+    /// Score per MB.
+    pub size: u32,
+    /// Score per percent of trace data lost during the capture.
+    pub data_loss: u32,
+    /// Score subtracted from the files of a tag, so that they are kept for longer.
+    pub tag_priorities: BTreeMap<String, u32>,
+}
+
+impl EvictionWeights {
+    /// Parse priorities formatted as `tag:priority,...`, ignoring malformed entries.
+    pub fn parse_tag_priorities(priorities: &str) -> BTreeMap<String, u32> {
+        priorities
+            .split(',')
+            .filter_map(|entry| {
+                let (tag, priority) = entry.split_once(':')?;
+                Some((tag.trim().to_string(), priority.trim().parse().ok()?))
+            })
+            .collect()
+    }
+}
 
 /// Dynamic configs, stored in config.json.
 #[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
 pub struct Config {
     /// Version of config file scheme, always equals to 1.
     version: u32,
-    /// Application specific node ID.
-    pub node_id: MacAddr6,
     /// Device build fingerprint.
     pub build_fingerprint: String,
+    /// Set of defaults the configuration was derived from.
+    pub build_tier: BuildTier,
     /// Interval between collections.
     pub collection_interval: Duration,
     /// An optional filter to limit which binaries to or not to profile.
     pub binary_filter: String,
+    /// Filter of the binaries of 32-bit processes, added to `binary_filter` on devices which run
+    /// 32-bit processes.
+    pub binary_filter_32: String,
+    /// Whether the device runs 32-bit processes alongside 64-bit ones.
+    pub mixed_abi: bool,
     /// Maximum size of the trace directory.
     pub max_trace_limit_mb: u64,
+    /// Maximum size of the profile and report directories, 0 to not limit.
+    pub max_profile_limit_mb: u64,
+    pub max_report_limit_mb: u64,
+    /// Maximum size of the profiles in a report, the rest are left for the next report, 0 to not
+    /// limit.
+    pub max_report_size_mb: u64,
     /// The kernel release version
     pub kernel_release: String,
+    /// How long the post-OTA profiling window lasts after the first boot of an update.
+    pub post_ota_window: Duration,
+    /// Interval between collections while the post-OTA profiling window is open.
+    pub post_ota_collection_interval: Duration,
+    /// Whether collection and processing threads take the background task profiles.
+    pub background_worker_threads: bool,
+    /// What to do with a trace request arriving while another trace is being recorded.
+    pub concurrent_trace_policy: ConcurrentTracePolicy,
+    /// How long a one-shot trace request which cannot run immediately is kept queued.
+    pub oneshot_expiry: Duration,
+    /// Data loss rate of ETM captures the aux buffer size is tuned to stay under, in percent.
+    pub etm_target_data_loss_percent: u32,
+    /// Bounds of the tuned ETM aux buffer size, in KB. Both should be powers of 2.
+    pub etm_aux_buffer_min_kb: u64,
+    pub etm_aux_buffer_max_kb: u64,
+    /// Percentage of periodic collections tracing system_server and zygote-forked system
+    /// processes instead of the whole system.
+    pub system_server_trace_freq: u32,
+    /// Sampling period of the trace of zygote preloading, 0 to not trace it.
+    pub zygote_preload_trace_period: Duration,
+    /// Percentage of the children forked from zygote traced while they link, and for how long.
+    pub zygote_fork_trace_freq: u32,
+    pub zygote_fork_trace_period: Duration,
+    /// How long the device salt included in report manifests is kept before it is rotated.
+    pub salt_rotation_period: Duration,
+    /// Collection is disabled once kernel warnings were logged during this many traces, 0 to
+    /// not watch the kernel log.
+    pub max_traces_with_kernel_warnings: u32,
+    /// Trace providers in order of preference, see `provider_chain`.
+    pub provider_chain: Vec<String>,
+    /// A provider is marked failed, and collection disabled until the next start, once this many
+    /// traces in a row failed with it. 0 to never mark providers failed.
+    pub provider_failures_before_downgrade: u32,
+    /// Whether no trace starts while perfetto records a perf session, see `perf_sessions`.
+    pub defer_to_perfetto: bool,
+    /// Collection is paused while there are more unprocessed traces than this, or while they
+    /// take more space than `max_unprocessed_trace_mb`, until processing catches up. 0 to not
+    /// limit.
+    pub max_unprocessed_traces: u32,
+    pub max_unprocessed_trace_mb: u64,
+    /// Clock used to timestamp the samples of traces.
+    pub trace_clock: TraceClock,
+    /// How long after boot periodic collection is held off, to not profile the atypical
+    /// post-boot activity. The boot trace and the post-OTA window are not affected.
+    pub boot_warmup_period: Duration,
+    /// Maximum time spent tracing and processing traces per battery charge cycle, 0 to not
+    /// limit. Collection is suppressed, and traces are left unprocessed, once it is spent.
+    pub max_tracing_per_charge_cycle: Duration,
+    pub max_processing_per_charge_cycle: Duration,
+    /// Whether simpleperf logs to LOG_FILE.
+    pub trace_log: bool,
+    /// Whether the boot is traced on the starts the boot trace was not armed or disarmed for.
+    pub boot_trace: bool,
+    /// Interval between integrity audits of the output directories, 0 to not audit.
+    pub audit_interval: Duration,
+    /// Interval between collections and sampling period while the intensive mode is on, bounded
+    /// to at least 1 minute and at most 5 seconds respectively.
+    pub intensive_collection_interval: Duration,
+    pub intensive_sampling_period: Duration,
+    /// Longest period the intensive mode can be turned on for.
+    pub max_intensive_mode_duration: Duration,
+    /// Minimum interval between the end of a trace and the start of the next one, whatever
+    /// triggered them, so that bursts of triggers do not result in back-to-back traces.
+    pub min_trace_interval: Duration,
+    /// One-shot traces sampled for at most this long are recorded in memory, and only written to
+    /// the trace directory if they are retained. 0 to always record to the trace directory.
+    pub in_memory_trace_max_sampling_period: Duration,
+    /// Whether raw kernel addresses are scrubbed from profiles.
+    pub kernel_address_policy: KernelAddressPolicy,
+    /// Whether traces are also decoded into BOLT profiles, next to the branch-list profiles.
+    pub bolt_profiles: bool,
+    /// Stages traces are processed through, in order, see `processing::ProcessingPipeline`.
+    pub processing_stages: Vec<String>,
+    /// Order in which traces, profiles and reports are evicted once over their limits. By default,
+    /// the oldest are evicted first.
+    pub eviction_weights: EvictionWeights,
 }
 
 impl Config {
     pub fn from_env() -> Result<Self> {
+        let tier = BuildTier::current();
         Ok(Config {
             version: 1,
-            node_id: get_or_initialise_node_id()?,
             build_fingerprint: get_build_fingerprint()?,
+            build_tier: tier,
             collection_interval: Duration::from_secs(get_device_config(
                 "collection_interval",
-                600,
+                tier.pick(1800, 600),
             )?),
             binary_filter: get_device_config("binary_filter", DEFAULT_BINARY_FILTER.to_string())?,
-            max_trace_limit_mb: get_device_config("max_trace_limit_mb", 768)?,
+            binary_filter_32: get_device_config(
+                "binary_filter_32",
+                DEFAULT_BINARY_FILTER_32.to_string(),
+            )?,
+            mixed_abi: is_mixed_abi_device(),
+            max_trace_limit_mb: get_device_config("max_trace_limit_mb", tier.pick(256, 768))?,
+            max_profile_limit_mb: get_device_config("max_profile_limit_mb", 0)?,
+            max_report_limit_mb: get_device_config("max_report_limit_mb", 0)?,
+            max_report_size_mb: get_device_config("max_report_size_mb", 0)?,
             kernel_release: get_kernel_release(),
+            post_ota_window: Duration::from_secs(get_device_config("post_ota_window", 3600)?),
+            post_ota_collection_interval: Duration::from_secs(get_device_config(
+                "post_ota_collection_interval",
+                120,
+            )?),
+            background_worker_threads: get_device_config("background_worker_threads", true)?,
+            concurrent_trace_policy: match get_device_config(
+                "concurrent_trace_policy",
+                "queue".to_string(),
+            )?
+            .as_str()
+            {
+                "merge" => ConcurrentTracePolicy::Merge,
+                _ => ConcurrentTracePolicy::Queue,
+            },
+            oneshot_expiry: Duration::from_secs(get_device_config("oneshot_expiry", 60)?),
+            etm_target_data_loss_percent: get_device_config("etm_target_data_loss_percent", 2)?,
+            etm_aux_buffer_min_kb: get_device_config("etm_aux_buffer_min_kb", 4096)?,
+            etm_aux_buffer_max_kb: get_device_config("etm_aux_buffer_max_kb", 16384)?,
+            system_server_trace_freq: get_device_config("system_server_trace_freq", 10)?,
+            zygote_preload_trace_period: Duration::from_millis(get_device_config(
+                "zygote_preload_trace_period",
+                3000,
+            )?),
+            zygote_fork_trace_freq: get_device_config("zygote_fork_trace_freq", 1)?,
+            zygote_fork_trace_period: Duration::from_millis(get_device_config(
+                "zygote_fork_trace_period",
+                300,
+            )?),
+            salt_rotation_period: Duration::from_secs(get_device_config(
+                "salt_rotation_period",
+                24 * 60 * 60,
+            )?),
+            max_traces_with_kernel_warnings: get_device_config(
+                "max_traces_with_kernel_warnings",
+                3,
+            )?,
+            provider_chain: get_device_config(
+                "provider_chain",
+                DEFAULT_PROVIDER_CHAIN.to_string(),
+            )?
+            .split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(str::to_string)
+            .collect(),
+            provider_failures_before_downgrade: get_device_config(
+                "provider_failures_before_downgrade",
+                5,
+            )?,
+            defer_to_perfetto: get_device_config("defer_to_perfetto", true)?,
+            max_unprocessed_traces: get_device_config("max_unprocessed_traces", 50)?,
+            max_unprocessed_trace_mb: get_device_config("max_unprocessed_trace_mb", 512)?,
+            trace_clock: match get_device_config("trace_clock", "perf".to_string())?.as_str() {
+                "realtime" => TraceClock::Realtime,
+                "monotonic" => TraceClock::Monotonic,
+                "monotonic_raw" => TraceClock::MonotonicRaw,
+                "boottime" => TraceClock::Boottime,
+                _ => TraceClock::Perf,
+            },
+            boot_warmup_period: Duration::from_secs(get_device_config("boot_warmup_period", 600)?),
+            max_tracing_per_charge_cycle: Duration::from_secs(get_device_config(
+                "max_tracing_per_charge_cycle",
+                tier.pick(120, 0),
+            )?),
+            max_processing_per_charge_cycle: Duration::from_secs(get_device_config(
+                "max_processing_per_charge_cycle",
+                tier.pick(600, 0),
+            )?),
+            trace_log: get_device_config("trace_log", tier.pick(false, true))?,
+            boot_trace: get_device_config("boot_trace", true)?,
+            audit_interval: Duration::from_secs(get_device_config("audit_interval", 24 * 60 * 60)?),
+            intensive_collection_interval: Duration::from_secs(get_device_config(
+                "intensive_collection_interval",
+                120,
+            )?)
+            .max(MIN_INTENSIVE_COLLECTION_INTERVAL),
+            intensive_sampling_period: Duration::from_millis(get_device_config(
+                "intensive_sampling_period",
+                3000,
+            )?)
+            .min(MAX_INTENSIVE_SAMPLING_PERIOD),
+            max_intensive_mode_duration: Duration::from_secs(get_device_config(
+                "max_intensive_mode_duration",
+                24 * 60 * 60,
+            )?),
+            min_trace_interval: Duration::from_secs(get_device_config("min_trace_interval", 10)?),
+            in_memory_trace_max_sampling_period: Duration::from_millis(get_device_config(
+                "in_memory_trace_max_sampling_period",
+                1000,
+            )?),
+            kernel_address_policy: match get_device_config(
+                "kernel_address_policy",
+                "keep".to_string(),
+            )?
+            .as_str()
+            {
+                "scrub" => KernelAddressPolicy::Scrub,
+                "kptr_restrict" => KernelAddressPolicy::KptrRestrict,
+                _ => KernelAddressPolicy::Keep,
+            },
+            bolt_profiles: get_device_config("bolt_profiles", false)?,
+            processing_stages: get_device_config(
+                "processing_stages",
+                DEFAULT_PROCESSING_STAGES.to_string(),
+            )?
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect(),
+            eviction_weights: EvictionWeights {
+                age: get_device_config("eviction_age_weight", 1)?,
+                size: get_device_config("eviction_size_weight", 0)?,
+                data_loss: get_device_config("eviction_data_loss_weight", 0)?,
+                tag_priorities: EvictionWeights::parse_tag_priorities(&get_device_config(
+                    "eviction_tag_priorities",
+                    String::new(),
+                )?),
+            },
         })
     }
 }
 
+impl Config {
+    /// Formats traces are decoded into.
+    pub fn profile_formats(&self) -> Vec<ProfileFormat> {
+        let mut formats = vec![ProfileFormat::BranchList];
+        if self.bolt_profiles {
+            formats.push(ProfileFormat::Bolt);
+        }
+        formats
+    }
+
+    /// The filter of the binaries to profile. It also matches 32-bit binaries on mixed-ABI
+    /// devices, otherwise 32-bit processes would produce empty profiles.
+    pub fn get_binary_filter(&self) -> String {
+        if self.mixed_abi && !self.binary_filter_32.is_empty() {
+            format!("({})|({})", self.binary_filter, self.binary_filter_32)
+        } else {
+            self.binary_filter.clone()
+        }
+    }
+
+    /// A stable digest of the configuration, identifying the config a trace was collected under.
+    pub fn digest(&self) -> String {
+        digest(self.to_string().as_bytes())
+    }
+}
+
+/// Digest of `data` keyed with the device `salt`, HMAC-SHA-256 in hex. It cannot be computed, nor
+/// the salt recovered from it, without the salt.
+pub fn salted_digest(salt: &str, data: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(salt.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A stable digest of `data`.
+pub fn digest(data: &[u8]) -> String {
+    // 64-bit FNV-1a, so that the digest does not change between releases.
+    let digest = data.iter().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", digest)
+}
+
 impl std::fmt::Display for Config {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", serde_json::to_string(self).expect("Failed to deserialise configuration."))
@@ -96,14 +497,135 @@ impl FromStr for Config {
     }
 }
 
-fn get_or_initialise_node_id() -> Result<MacAddr6> {
-    let mut node_id = get_property(PROFCOLLECT_NODE_ID_PROPERTY, MacAddr6::nil())?;
-    if node_id.is_nil() {
-        node_id = generate_random_node_id();
-        set_property(PROFCOLLECT_NODE_ID_PROPERTY, node_id)?;
+/// Persistent store of the identifiers recorded in report manifests.
+pub trait IdentifierStore {
+    /// The value of `key`, empty if it has none.
+    fn get(&self, key: &str) -> Result<String>;
+    fn set(&self, key: &str, value: &str) -> Result<()>;
+}
+
+/// The identifiers of the device, kept in persistent system properties.
+pub struct DeviceIdentifiers;
+
+impl IdentifierStore for DeviceIdentifiers {
+    fn get(&self, key: &str) -> Result<String> {
+        get_property(key, String::new())
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<()> {
+        set_property(key, value)
+    }
+}
+
+/// Returns the random device salt in `store`, rotating it once it is older than
+/// `rotation_period`. The salt lets reports from the same device be correlated over a short
+/// window, without a stable device identifier.
+pub fn get_or_rotate_device_salt(
+    store: &dyn IdentifierStore,
+    rotation_period: Duration,
+) -> Result<String> {
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
+    let stored = store.get(PROFCOLLECT_SALT_PROPERTY)?;
+    if let Some((salt, issued)) = stored.split_once(',') {
+        let fresh = issued
+            .parse::<u64>()
+            .is_ok_and(|issued| now.saturating_sub(issued) < rotation_period.as_secs());
+        if fresh && !salt.is_empty() {
+            return Ok(salt.to_string());
+        }
+    }
+
+    let salt = new_random_id();
+    store.set(PROFCOLLECT_SALT_PROPERTY, &format!("{},{}", salt, now))?;
+    Ok(salt)
+}
+
+/// A random 128-bit ID in hex, as used for the device salt and the install ID.
+pub fn new_random_id() -> String {
+    rand::thread_rng().gen::<[u8; 16]>().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Returns the random ID of this install of profcollect in `store`, creating it if there is none. It is kept
+/// across config changes and OTAs, so that report continuity can be measured per install, and is
+/// reset with the device salt by `reset_report_identifiers`, and on factory reset like all
+/// persistent properties. It is derived from no hardware identifier.
+pub fn get_or_create_install_id(store: &dyn IdentifierStore) -> Result<String> {
+    let install_id = store.get(PROFCOLLECT_INSTALL_ID_PROPERTY)?;
+    if !install_id.is_empty() {
+        return Ok(install_id);
+    }
+    let install_id = new_random_id();
+    store.set(PROFCOLLECT_INSTALL_ID_PROPERTY, &install_id)?;
+    Ok(install_id)
+}
+
+/// Reset the install ID and the device salt in `store`, so that later reports cannot be tied to
+/// earlier ones.
+pub fn reset_report_identifiers(store: &dyn IdentifierStore) -> Result<()> {
+    store.set(PROFCOLLECT_INSTALL_ID_PROPERTY, "")?;
+    store.set(PROFCOLLECT_SALT_PROPERTY, "")
+}
+
+/// Returns whether post-OTA profiling was armed during OTA finalization, disarming it so that
+/// only the first boot after an update is affected.
+pub fn take_post_ota_flag() -> Result<bool> {
+    let armed = get_property(PROFCOLLECT_POST_OTA_PROPERTY, false)?;
+    if armed {
+        set_property(PROFCOLLECT_POST_OTA_PROPERTY, false)?;
+    }
+    Ok(armed)
+}
+
+/// Arm or disarm the boot trace for the next start only, see `take_boot_trace_flag`.
+pub fn set_boot_trace_flag(enable: bool) -> Result<()> {
+    set_property(PROFCOLLECT_BOOT_TRACE_PROPERTY, if enable { "armed" } else { "disarmed" })
+}
+
+/// Returns whether the boot trace was armed or disarmed for this start, None if neither, clearing
+/// the flag so that the following starts are not affected.
+pub fn take_boot_trace_flag() -> Result<Option<bool>> {
+    let flag = match get_property(PROFCOLLECT_BOOT_TRACE_PROPERTY, String::new())?.as_str() {
+        "armed" => Some(true),
+        "disarmed" => Some(false),
+        _ => None,
+    };
+    if flag.is_some() {
+        set_property(PROFCOLLECT_BOOT_TRACE_PROPERTY, "none")?;
     }
+    Ok(flag)
+}
+
+/// Returns the ETM aux buffer size tuned on previous boots in KB, 0 if it was never tuned.
+pub fn get_etm_aux_buffer_kb() -> u64 {
+    get_property(PROFCOLLECT_ETM_AUX_BUFFER_PROPERTY, 0).unwrap_or(0)
+}
+
+pub fn set_etm_aux_buffer_kb(size_kb: u64) -> Result<()> {
+    set_property(PROFCOLLECT_ETM_AUX_BUFFER_PROPERTY, size_kb)
+}
+
+/// Trace providers marked failed on this device, see `provider_chain`.
+pub fn get_failed_providers() -> Vec<String> {
+    get_property(PROFCOLLECT_FAILED_PROVIDERS_PROPERTY, String::new())
+        .unwrap_or_default()
+        .split(',')
+        .filter(|p| !p.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+pub fn set_failed_providers(providers: &[String]) -> Result<()> {
+    set_property(PROFCOLLECT_FAILED_PROVIDERS_PROPERTY, providers.join(","))
+}
+
+/// The budget spent in the current charge cycle, as saved before profcollectd last exited, see
+/// `charge_budget`. Empty if none was saved.
+pub fn get_spent_charge_budget() -> String {
+    get_property(PROFCOLLECT_CHARGE_BUDGET_PROPERTY, String::new()).unwrap_or_default()
+}
 
-    Ok(node_id)
+pub fn set_spent_charge_budget(spent: &str) -> Result<()> {
+    set_property(PROFCOLLECT_CHARGE_BUDGET_PROPERTY, spent)
 }
 
 fn get_build_fingerprint() -> Result<String> {
@@ -146,10 +668,8 @@ where
     Ok(rustutils::system_properties::write(key, &value)?)
 }
 
-fn generate_random_node_id() -> MacAddr6 {
-    let mut node_id = rand::thread_rng().gen::<[u8; 6]>();
-    node_id[0] |= 0x1;
-    MacAddr6::from(node_id)
+fn is_mixed_abi_device() -> bool {
+    get_property("ro.product.cpu.abilist32", String::new()).is_ok_and(|abis| !abis.is_empty())
 }
 
 fn get_kernel_release() -> String {
@@ -163,24 +683,117 @@ fn get_kernel_release() -> String {
 
 pub fn clear_data() -> Result<()> {
     fn remove_files(path: &Path) -> Result<()> {
-        read_dir(path)?
-            .filter_map(|e| e.ok())
-            .map(|e| e.path())
-            .filter(|e| e.is_file() && e != *LOG_FILE)
-            .try_for_each(remove_file)?;
+        for e in read_dir(path)?.filter_map(|e| e.ok()).map(|e| e.path()) {
+            if e.is_dir() {
+                // Per-provider directory.
+                remove_files(&e)?;
+            } else if e.is_file() && e != *LOG_FILE {
+                remove_file(e)?;
+            }
+        }
         Ok(())
     }
 
     remove_files(&TRACE_OUTPUT_DIR)?;
     remove_files(&PROFILE_OUTPUT_DIR)?;
     remove_files(&REPORT_OUTPUT_DIR)?;
+    remove_files(&QUARANTINE_DIR)?;
+    set_failed_providers(&[])?;
+    // Tuned for the previous config, see `SimpleperfEtmTraceProvider::adjust_to_data_loss`.
+    set_etm_aux_buffer_kb(0)?;
     Ok(())
 }
-pub fn clear_processed_files() -> Result<()> {
-    read_dir(&PROFILE_OUTPUT_DIR as &Path)?
-        .filter_map(|e| e.ok())
-        .map(|e| e.path())
-        .filter(|e| e.is_file() && e != (&CONFIG_FILE as &Path))
-        .try_for_each(remove_file)?;
+pub fn clear_processed_files(profile: &Path) -> Result<()> {
+    for e in read_dir(profile)?.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if e.is_dir() {
+            // Per-provider directory.
+            clear_processed_files(&e)?;
+        } else if e.is_file() && e != *CONFIG_FILE && e != *RESOLUTION_CACHE_FILE {
+            remove_file(e)?;
+        }
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        get_or_create_install_id, get_or_rotate_device_salt, reset_report_identifiers,
+        salted_digest, Config, IdentifierStore,
+    };
+    use crate::trace_provider::{is_bolt_profile, ProfileFormat};
+    use anyhow::Result;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::path::Path;
+    use std::time::Duration;
+
+    /// Keeps the identifiers in memory, leaving those of the device alone.
+    #[derive(Default)]
+    struct MemoryStore(RefCell<HashMap<String, String>>);
+
+    impl IdentifierStore for MemoryStore {
+        fn get(&self, key: &str) -> Result<String> {
+            Ok(self.0.borrow().get(key).cloned().unwrap_or_default())
+        }
+
+        fn set(&self, key: &str, value: &str) -> Result<()> {
+            self.0.borrow_mut().insert(key.to_string(), value.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn salted_digest_is_hmac_sha256() {
+        // Test case 2 of RFC 4231.
+        assert_eq!(
+            salted_digest("Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_ne!(salted_digest("salt", b"report"), salted_digest("other salt", b"report"));
+    }
+
+    #[test]
+    fn reset_changes_every_report_identifier() {
+        let store = MemoryStore::default();
+        let rotation_period = Duration::from_secs(3600);
+        let salt = get_or_rotate_device_salt(&store, rotation_period).unwrap();
+        let install_id = get_or_create_install_id(&store).unwrap();
+        assert_eq!(get_or_rotate_device_salt(&store, rotation_period).unwrap(), salt);
+        assert_eq!(get_or_create_install_id(&store).unwrap(), install_id);
+
+        reset_report_identifiers(&store).unwrap();
+        let new_salt = get_or_rotate_device_salt(&store, rotation_period).unwrap();
+        assert_ne!(new_salt, salt);
+        assert_ne!(get_or_create_install_id(&store).unwrap(), install_id);
+        // The salted digests of the manifest change with the salt.
+        assert_ne!(salted_digest(&new_salt, b"report"), salted_digest(&salt, b"report"));
+    }
+
+    #[test]
+    fn bolt_profiles_are_decoded_next_to_branch_lists() {
+        let mut config = Config::from_env().unwrap();
+        config.bolt_profiles = false;
+        assert_eq!(config.profile_formats(), [ProfileFormat::BranchList]);
+        config.bolt_profiles = true;
+        assert_eq!(config.profile_formats(), [ProfileFormat::BranchList, ProfileFormat::Bolt]);
+
+        let profile = Path::new("20261014T120000Z_test");
+        assert!(is_bolt_profile(&profile.with_extension(ProfileFormat::Bolt.extension())));
+        assert!(!is_bolt_profile(&profile.with_extension(ProfileFormat::BranchList.extension())));
+        assert!(!is_bolt_profile(&profile.with_extension("json")));
+    }
+
+    #[test]
+    fn binary_filter_covers_32_bit_binaries_on_mixed_abi_devices() {
+        let mut config = Config::from_env().unwrap();
+        config.binary_filter = "^/system/lib64/.+".to_string();
+        config.binary_filter_32 = "^/system/lib/.+".to_string();
+        config.mixed_abi = false;
+        assert_eq!(config.get_binary_filter(), "^/system/lib64/.+");
+        config.mixed_abi = true;
+        assert_eq!(config.get_binary_filter(), "(^/system/lib64/.+)|(^/system/lib/.+)");
+        config.binary_filter_32.clear();
+        assert_eq!(config.get_binary_filter(), "^/system/lib64/.+");
+    }
+}
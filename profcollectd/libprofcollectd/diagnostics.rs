@@ -0,0 +1,54 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Diagnostics bundle, a small zip of the state of profcollectd for bug reports. Unlike reports,
+//! it carries no trace or profile content.
+
+use anyhow::Result;
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use zip::write::FileOptions;
+use zip::CompressionMethod::Deflated;
+use zip::ZipWriter;
+
+use crate::config::{Config, LOG_FILE};
+
+/// Bytes of the end of the trace log included in the bundle.
+const MAX_LOG_BYTES: u64 = 64 * 1024;
+
+/// Build a diagnostics bundle of `status`, `config`, which holds no device identifier, and the
+/// end of the trace log, which records the failures of the trace provider.
+pub fn build_diagnostics_bundle(status: &str, config: &Config) -> Result<Vec<u8>> {
+    let options = FileOptions::default().compression_method(Deflated);
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+
+    zip.start_file("status.txt", options)?;
+    zip.write_all(status.as_bytes())?;
+
+    zip.start_file("config.json", options)?;
+    zip.write_all(config.to_string().as_bytes())?;
+
+    if let Ok(mut log) = File::open(*LOG_FILE) {
+        let len = log.metadata()?.len();
+        log.seek(SeekFrom::Start(len.saturating_sub(MAX_LOG_BYTES)))?;
+        let mut tail = Vec::new();
+        log.read_to_end(&mut tail)?;
+        zip.start_file("trace.log", options)?;
+        zip.write_all(&tail)?;
+    }
+
+    Ok(zip.finish()?.into_inner())
+}
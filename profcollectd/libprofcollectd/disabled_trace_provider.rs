@@ -0,0 +1,98 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Trace provider used when no provider of the chain is left, so that the service stays up and
+//! reports why it does not collect. It is never ready, and fails every request.
+
+use anyhow::{bail, Result};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use trace_provider::{ProfileFormat, TraceProvider};
+
+use crate::config::{Config, TraceClock};
+use crate::in_memory_trace::InMemoryTrace;
+use crate::trace_provider;
+
+pub const DISABLED_PROVIDER_NAME: &str = "disabled";
+
+pub struct DisabledTraceProvider {}
+
+impl TraceProvider for DisabledTraceProvider {
+    fn get_name(&self) -> &'static str {
+        DISABLED_PROVIDER_NAME
+    }
+
+    fn is_ready(&self) -> bool {
+        false
+    }
+
+    fn trace_system(
+        &self,
+        _trace_dir: &Path,
+        _tag: &str,
+        _correlation_id: &str,
+        _in_memory: Option<&InMemoryTrace>,
+        _sampling_period: &Duration,
+        _binary_filter: &str,
+    ) -> Result<Box<Path>> {
+        bail!("No trace provider left.")
+    }
+
+    fn trace_process(
+        &self,
+        _trace_dir: &Path,
+        _tag: &str,
+        _correlation_id: &str,
+        _in_memory: Option<&InMemoryTrace>,
+        _sampling_period: &Duration,
+        _processes: &str,
+    ) -> Result<Box<Path>> {
+        bail!("No trace provider left.")
+    }
+
+    fn get_data_loss(&self, _trace_file: &Path) -> Option<f64> {
+        None
+    }
+
+    fn adjust_to_data_loss(&self, _config: &Config, _data_loss_percent: f64) {}
+
+    fn process(
+        &self,
+        _trace_dir: &Path,
+        _profile_dir: &Path,
+        _binary_filter: &str,
+        _hide_kernel_addresses: bool,
+        _formats: &[ProfileFormat],
+    ) -> Result<()> {
+        // There are no traces to process.
+        Ok(())
+    }
+
+    fn merge(
+        &self,
+        _profiles: &[PathBuf],
+        _binary_filter: &str,
+        _format: ProfileFormat,
+        _output: &Path,
+    ) -> Result<()> {
+        bail!("No trace provider left.")
+    }
+
+    fn set_trace_clock(&self, _clock: TraceClock) {}
+
+    fn set_log_file(&self, _filename: &Path) {}
+    fn reset_log_file(&self) {}
+}
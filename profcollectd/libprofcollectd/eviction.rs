@@ -0,0 +1,272 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Eviction of traces, profiles and reports, under the same policy for all the output directories.
+
+use anyhow::Result;
+use std::fs::{read_dir, read_to_string, remove_file};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::config::EvictionWeights;
+use crate::storage::get_tag;
+use crate::trace_metadata::{get_metadata_path, is_metadata, TraceMetadata};
+
+/// A file which can be evicted, with the files evicted along with it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    pub files: Vec<PathBuf>,
+    /// Bytes of all the files.
+    pub bytes: u64,
+    pub age: Duration,
+    /// Percentage of trace data lost during the capture, 0 if unknown.
+    pub data_loss_percent: f64,
+    pub tag: Option<String>,
+}
+
+impl Candidate {
+    /// A candidate of the single file `path`, aged from its modification time.
+    pub fn from_file(path: PathBuf) -> Result<Self> {
+        let metadata = path.metadata()?;
+        let age = SystemTime::now().duration_since(metadata.modified()?).unwrap_or_default();
+        let tag = get_tag(&path).map(str::to_string);
+        Ok(Candidate { files: vec![path], bytes: metadata.len(), age, data_loss_percent: 0.0, tag })
+    }
+
+    fn score(&self, weights: &EvictionWeights) -> f64 {
+        let priority = self.tag.as_ref().and_then(|t| weights.tag_priorities.get(t)).copied();
+        weights.age as f64 * self.age.as_secs_f64() / 3600.0
+            + weights.size as f64 * self.bytes as f64 / (1024.0 * 1024.0)
+            + weights.data_loss as f64 * self.data_loss_percent
+            - priority.unwrap_or(0) as f64
+    }
+}
+
+/// Limits of an output directory.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    /// Candidates older than this are evicted, whatever their score.
+    pub max_age: Option<Duration>,
+    /// Candidates are evicted until the rest take at most this many bytes.
+    pub max_bytes: Option<u64>,
+}
+
+impl Limits {
+    /// Limits of at most `max_mb` MB, 0 to not limit.
+    pub fn from_mb(max_mb: u64) -> Self {
+        Limits { max_age: None, max_bytes: (max_mb > 0).then_some(max_mb * 1024 * 1024) }
+    }
+}
+
+/// Select the candidates to evict, in eviction order: the candidates past the maximum age, then
+/// those with the highest score until the rest fit in the maximum size. Candidates with the same
+/// score are evicted from the oldest.
+pub fn select(
+    weights: &EvictionWeights,
+    limits: &Limits,
+    mut candidates: Vec<Candidate>,
+) -> Vec<Candidate> {
+    let expired = |c: &Candidate| limits.max_age.is_some_and(|max_age| c.age > max_age);
+    candidates.sort_by(|a, b| {
+        expired(b)
+            .cmp(&expired(a))
+            .then(b.score(weights).total_cmp(&a.score(weights)))
+            .then(b.age.cmp(&a.age))
+    });
+
+    let max_bytes = limits.max_bytes.unwrap_or(u64::MAX);
+    let mut used: u64 = candidates.iter().map(|c| c.bytes).sum();
+    let mut evicted = 0;
+    for candidate in &candidates {
+        if !expired(candidate) && used <= max_bytes {
+            break;
+        }
+        used -= candidate.bytes;
+        evicted += 1;
+    }
+    candidates.truncate(evicted);
+    candidates
+}
+
+/// Whether `candidate`, which is not stored yet, would be evicted right away if it were added to
+/// `candidates`.
+pub fn evicted_on_arrival(
+    weights: &EvictionWeights,
+    limits: &Limits,
+    mut candidates: Vec<Candidate>,
+    candidate: &Candidate,
+) -> bool {
+    candidates.push(candidate.clone());
+    select(weights, limits, candidates).iter().any(|c| c.files == candidate.files)
+}
+
+/// Remove the candidates selected by `select`. Returns the number of bytes freed.
+pub fn evict(
+    weights: &EvictionWeights,
+    limits: &Limits,
+    candidates: Vec<Candidate>,
+) -> Result<u64> {
+    let mut freed = 0;
+    for candidate in select(weights, limits, candidates) {
+        log::info!("Evicting {}", candidate.files[0].display());
+        for file in &candidate.files {
+            remove_file(file)?;
+        }
+        freed += candidate.bytes;
+    }
+    Ok(freed)
+}
+
+/// List the traces or profiles in `dir`, non-recursive, each with its metadata sidecar.
+pub fn list_candidates(dir: &Path) -> Result<Vec<Candidate>> {
+    let mut candidates = Vec::new();
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() || is_metadata(&path) {
+            continue;
+        }
+        let sidecar = get_metadata_path(&path);
+        let mut candidate = Candidate::from_file(path)?;
+        if sidecar.is_file() {
+            candidate.bytes += sidecar.metadata()?.len();
+            let metadata = read_to_string(&sidecar)
+                .ok()
+                .and_then(|m| serde_json::from_str::<TraceMetadata>(&m).ok());
+            if let Some(data_loss) = metadata.and_then(|m| m.data_loss_percent) {
+                candidate.data_loss_percent = data_loss;
+            }
+            candidate.files.push(sidecar);
+        }
+        candidates.push(candidate);
+    }
+    Ok(candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use super::{evicted_on_arrival, select, Candidate, Limits};
+    use crate::config::EvictionWeights;
+
+    const MB: u64 = 1024 * 1024;
+
+    fn candidate(name: &str, mb: u64, age_hours: u64, data_loss_percent: f64) -> Candidate {
+        Candidate {
+            files: vec![PathBuf::from(name)],
+            bytes: mb * MB,
+            age: Duration::from_secs(age_hours * 3600),
+            data_loss_percent,
+            tag: name.split_once('_').map(|(_, tag)| tag.to_string()),
+        }
+    }
+
+    fn weights(
+        age: u32,
+        size: u32,
+        data_loss: u32,
+        tag_priorities: &[(&str, u32)],
+    ) -> EvictionWeights {
+        EvictionWeights {
+            age,
+            size,
+            data_loss,
+            tag_priorities: tag_priorities.iter().map(|(t, p)| (t.to_string(), *p)).collect(),
+        }
+    }
+
+    fn names(evicted: Vec<Candidate>) -> Vec<String> {
+        evicted.iter().map(|c| c.files[0].to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn evicts_oldest_first_by_default() {
+        let candidates = vec![
+            candidate("b_periodic", 1, 2, 0.0),
+            candidate("a_periodic", 1, 3, 0.0),
+            candidate("c_periodic", 1, 1, 0.0),
+        ];
+        let evicted = select(&weights(1, 0, 0, &[]), &Limits::from_mb(1), candidates);
+        assert_eq!(names(evicted), ["a_periodic", "b_periodic"]);
+    }
+
+    #[test]
+    fn evicts_nothing_within_limits() {
+        let candidates =
+            vec![candidate("a_periodic", 1, 3, 0.0), candidate("b_periodic", 1, 2, 0.0)];
+        assert!(select(&weights(1, 0, 0, &[]), &Limits::from_mb(2), candidates.clone()).is_empty());
+        assert!(select(&weights(1, 0, 0, &[]), &Limits::from_mb(0), candidates).is_empty());
+    }
+
+    #[test]
+    fn evicts_expired_first_whatever_the_score() {
+        let limits =
+            Limits { max_age: Some(Duration::from_secs(24 * 3600)), max_bytes: Some(2 * MB) };
+        let candidates = vec![
+            candidate("a_big", 4, 1, 0.0),
+            candidate("b_periodic", 1, 48, 0.0),
+            candidate("c_periodic", 1, 2, 0.0),
+        ];
+        let evicted = select(&weights(0, 100, 0, &[]), &limits, candidates);
+        assert_eq!(names(evicted), ["b_periodic", "a_big"]);
+    }
+
+    #[test]
+    fn evicts_by_size_and_data_loss() {
+        let candidates = vec![
+            candidate("a_periodic", 1, 3, 0.0),
+            candidate("b_periodic", 3, 1, 0.0),
+            candidate("c_periodic", 1, 1, 50.0),
+        ];
+        let evicted = select(&weights(1, 10, 0, &[]), &Limits::from_mb(2), candidates.clone());
+        assert_eq!(names(evicted), ["b_periodic"]);
+        let evicted = select(&weights(1, 0, 10, &[]), &Limits::from_mb(2), candidates);
+        assert_eq!(names(evicted), ["c_periodic", "a_periodic", "b_periodic"]);
+    }
+
+    #[test]
+    fn keeps_prioritised_tags_longer() {
+        let candidates = vec![
+            candidate("a_boot", 1, 3, 0.0),
+            candidate("b_periodic", 1, 2, 0.0),
+            candidate("c_periodic", 1, 1, 0.0),
+        ];
+        let evicted = select(&weights(1, 0, 0, &[("boot", 10)]), &Limits::from_mb(1), candidates);
+        assert_eq!(names(evicted), ["b_periodic", "c_periodic"]);
+    }
+
+    #[test]
+    fn discards_new_candidates_evicted_on_arrival() {
+        let candidates =
+            vec![candidate("a_periodic", 1, 3, 0.0), candidate("b_periodic", 1, 2, 0.0)];
+        let weights = weights(1, 0, 10, &[]);
+        let clean = candidate("c_applaunch", 1, 0, 0.0);
+        let lossy = candidate("c_applaunch", 1, 0, 50.0);
+        assert!(!evicted_on_arrival(&weights, &Limits::from_mb(2), candidates.clone(), &clean));
+        assert!(evicted_on_arrival(&weights, &Limits::from_mb(2), candidates.clone(), &lossy));
+        assert!(!evicted_on_arrival(&weights, &Limits::from_mb(0), candidates, &lossy));
+    }
+
+    #[test]
+    fn parses_tag_priorities() {
+        let priorities = EvictionWeights::parse_tag_priorities("boot:10, post-ota : 5,bad,x:y");
+        let expected: BTreeMap<String, u32> =
+            [("boot".to_string(), 10), ("post-ota".to_string(), 5)].into_iter().collect();
+        assert_eq!(priorities, expected);
+    }
+}
@@ -0,0 +1,63 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Failure injection at defined points of collection and reporting, so that error handling can be
+//! exercised in integration tests. Only enabled with the "test" feature, e.g.
+//! `setprop debug.profcollectd.inject_faults trace_write,packing`.
+
+use anyhow::{bail, Result};
+
+const INJECT_FAULTS_PROPERTY: &str = "debug.profcollectd.inject_faults";
+
+/// A point at which a failure can be injected.
+#[derive(Clone, Copy, Debug)]
+pub enum FaultPoint {
+    /// Starting a trace with the trace provider.
+    ProviderStart,
+    /// Writing a recorded trace and its metadata.
+    TraceWrite,
+    /// Processing traces into profiles.
+    Processing,
+    /// Packing profiles into a report.
+    Packing,
+}
+
+impl FaultPoint {
+    fn name(&self) -> &'static str {
+        match self {
+            FaultPoint::ProviderStart => "provider_start",
+            FaultPoint::TraceWrite => "trace_write",
+            FaultPoint::Processing => "processing",
+            FaultPoint::Packing => "packing",
+        }
+    }
+}
+
+/// Fail if a failure is injected at `point`. Failures are injected while the point is listed in
+/// the inject_faults property.
+pub fn inject_fault(point: FaultPoint) -> Result<()> {
+    if !cfg!(feature = "test") {
+        return Ok(());
+    }
+    let faults = rustutils::system_properties::read(INJECT_FAULTS_PROPERTY)
+        .unwrap_or(None)
+        .unwrap_or_default();
+    if faults.split(',').any(|f| f.trim() == point.name()) {
+        log::info!("Injecting {} failure.", point.name());
+        bail!("Injected {} failure.", point.name());
+    }
+    Ok(())
+}
@@ -0,0 +1,75 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Hardware profiling capabilities, as exposed by the kernel perf event sources.
+
+use std::fs::read_dir;
+use std::path::Path;
+
+/// Directory of the perf event sources (PMUs) registered by the kernel, also read by
+/// `simpleperf list`.
+const EVENT_SOURCE_DIR: &str = "/sys/bus/event_source/devices";
+const ETM_PMU: &str = "cs_etm";
+const SPE_PMU_PREFIX: &str = "arm_spe";
+
+/// PMU events, ETM and SPE capabilities of the device.
+#[derive(Debug, Default)]
+pub struct Capabilities {
+    /// PMU events, as `<pmu>/<event>`.
+    pub pmu_events: Vec<String>,
+    pub etm: bool,
+    pub etm_features: Vec<String>,
+    pub etm_sinks: Vec<String>,
+    pub spe: bool,
+    pub spe_features: Vec<String>,
+    pub lbr: bool,
+}
+
+/// Get the capabilities of the device. Capabilities which cannot be read are reported as missing.
+pub fn get_capabilities() -> Capabilities {
+    let root = Path::new(EVENT_SOURCE_DIR);
+    let mut capabilities = Capabilities {
+        etm: simpleperf_profcollect::is_etm_device_available(),
+        lbr: simpleperf_profcollect::is_lbr_available(),
+        ..Default::default()
+    };
+    for pmu in list_names(root) {
+        let pmu_dir = root.join(&pmu);
+        capabilities.pmu_events.extend(
+            list_names(&pmu_dir.join("events")).into_iter().map(|e| format!("{}/{}", pmu, e)),
+        );
+        if pmu == ETM_PMU {
+            capabilities.etm_features = list_names(&pmu_dir.join("format"));
+            capabilities.etm_sinks = list_names(&pmu_dir.join("sinks"));
+        } else if pmu.starts_with(SPE_PMU_PREFIX) {
+            capabilities.spe = true;
+            capabilities.spe_features = list_names(&pmu_dir.join("format"));
+        }
+    }
+    capabilities
+}
+
+/// Sorted names of the entries of `dir`, none if it cannot be read.
+fn list_names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().into_string().ok())
+        .collect();
+    names.sort();
+    names
+}
@@ -0,0 +1,63 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Traces captured into an anonymous memory file, only written to the trace directory if they are
+//! retained. This avoids flash writes for short traces which would be discarded right away.
+
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{copy, Error};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+
+/// A trace captured into a memfd. The provider records it through `path`, and the memory is
+/// released when it is dropped.
+pub struct InMemoryTrace {
+    file: File,
+    path: PathBuf,
+}
+
+impl InMemoryTrace {
+    pub fn new() -> Result<Self> {
+        // SAFETY: The name is a valid C string.
+        let fd = unsafe { libc::memfd_create(c"profcollectd_trace".as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(Error::last_os_error()).context("Failed to create memfd.");
+        }
+        // SAFETY: fd was just created, and is not owned by anything else.
+        let file = File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+        let path = PathBuf::from(format!("/proc/self/fd/{}", file.as_raw_fd()));
+        Ok(InMemoryTrace { file, path })
+    }
+
+    /// Path the trace is recorded to, valid within profcollectd only.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Size of the recorded trace, in bytes.
+    pub fn size(&self) -> Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    /// Write the recorded trace to `trace_file`.
+    pub fn spill(self, trace_file: &Path) -> Result<()> {
+        // Reopen the memfd to read it from the start, whatever offset the provider left it at.
+        copy(&mut File::open(&self.path)?, &mut File::create(trace_file)?)
+            .with_context(|| format!("Failed to write {}", trace_file.display()))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Time-boxed intensive sampling mode, collecting more often and for longer than usual, e.g.
+//! during a dogfood push.
+
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::trace_metadata::time_since_boot;
+
+/// Tag given to periodic traces collected while the intensive mode is on.
+pub const INTENSIVE_TAG: &str = "intensive";
+
+/// A period the intensive mode was on for. Its bounds are times since boot, counting suspended
+/// time, so that an episode never outlasts its duration on a device which sleeps most of the time.
+#[derive(Clone, Copy)]
+struct Episode {
+    start: Duration,
+    end: Duration,
+    traces: u32,
+}
+
+/// The current or last intensive mode episode.
+#[derive(Default)]
+pub struct IntensiveMode {
+    episode: Mutex<Option<Episode>>,
+}
+
+impl IntensiveMode {
+    /// Turn the intensive mode on for `duration`, or off if `duration` is zero.
+    pub fn set(&self, duration: Duration) {
+        let mut episode = self.episode.lock().unwrap();
+        let now = time_since_boot();
+        if duration.is_zero() {
+            if let Some(episode) = episode.as_mut().filter(|e| e.end > now) {
+                log::info!("Intensive mode turned off.");
+                episode.end = now;
+            }
+            return;
+        }
+        log::info!("Intensive mode turned on for {}s.", duration.as_secs());
+        match episode.as_mut().filter(|e| e.end > now) {
+            // Extend the running episode.
+            Some(episode) => episode.end = now + duration,
+            None => *episode = Some(Episode { start: now, end: now + duration, traces: 0 }),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.episode.lock().unwrap().is_some_and(|e| time_since_boot() < e.end)
+    }
+
+    /// Time left until the intensive mode turns off, None if it is off.
+    pub fn remaining(&self) -> Option<Duration> {
+        let end = (*self.episode.lock().unwrap())?.end;
+        Some(end.saturating_sub(time_since_boot())).filter(|d| !d.is_zero())
+    }
+
+    /// Count a trace collected in the current episode.
+    pub fn record_trace(&self) {
+        if let Some(episode) = self.episode.lock().unwrap().as_mut() {
+            episode.traces += 1;
+        }
+    }
+
+    pub fn status(&self) -> String {
+        let Some(episode) = *self.episode.lock().unwrap() else {
+            return String::new();
+        };
+        let now = time_since_boot();
+        let mut status = String::new();
+        if now < episode.end {
+            writeln!(
+                status,
+                "intensive mode: on for {}s more, {} traces",
+                (episode.end - now).as_secs(),
+                episode.traces
+            )
+        } else {
+            writeln!(
+                status,
+                "intensive mode: off, last episode ended {}s ago after {}s, {} traces",
+                (now - episode.end).as_secs(),
+                (episode.end - episode.start).as_secs(),
+                episode.traces
+            )
+        }
+        .unwrap();
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::IntensiveMode;
+
+    #[test]
+    fn episodes_end_on_time_or_when_turned_off() {
+        let mode = IntensiveMode::default();
+        assert!(!mode.is_active());
+        mode.set(Duration::from_secs(3600));
+        assert!(mode.is_active());
+        assert!(mode.remaining().is_some_and(|d| d <= Duration::from_secs(3600)));
+        mode.record_trace();
+        assert!(mode.status().contains("1 traces"));
+
+        mode.set(Duration::ZERO);
+        assert!(!mode.is_active());
+        assert_eq!(mode.remaining(), None);
+        assert!(mode.status().contains("intensive mode: off"));
+    }
+}
@@ -0,0 +1,80 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Scrubbing of raw kernel addresses from profiles, for devices whose policy hides them.
+
+use serde::{Deserialize, Serialize};
+use std::fs::read_to_string;
+
+use crate::config::{Config, KernelAddressPolicy};
+
+/// Whether the raw kernel addresses are scrubbed from a profile, and why. Scrubbed profiles keep
+/// the kernel, with its addresses rebased to a fixed start address by simpleperf so that they do
+/// not reveal the KASLR offset. Recorded in the metadata of each profile when it is processed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScrubDecision {
+    pub policy: KernelAddressPolicy,
+    /// Value of /proc/sys/kernel/kptr_restrict, None if it could not be read.
+    pub kptr_restrict: Option<u32>,
+    pub scrubbed: bool,
+}
+
+impl ScrubDecision {
+    /// Decide from the policy of `config` and the current kptr_restrict setting.
+    pub fn current(config: &Config) -> Self {
+        let kptr_restrict = read_to_string("/proc/sys/kernel/kptr_restrict")
+            .ok()
+            .and_then(|s| s.trim().parse().ok());
+        Self::decide(config.kernel_address_policy, kptr_restrict)
+    }
+
+    /// Decide from `policy` and a kptr_restrict setting. Scrubbing is the default if the policy
+    /// follows kptr_restrict but it could not be read.
+    pub fn decide(policy: KernelAddressPolicy, kptr_restrict: Option<u32>) -> Self {
+        let scrubbed = match policy {
+            KernelAddressPolicy::Keep => false,
+            KernelAddressPolicy::Scrub => true,
+            KernelAddressPolicy::KptrRestrict => kptr_restrict != Some(0),
+        };
+        ScrubDecision { policy, kptr_restrict, scrubbed }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScrubDecision;
+    use crate::config::KernelAddressPolicy;
+
+    #[test]
+    fn follows_kptr_restrict() {
+        let decide = |kptr_restrict| {
+            ScrubDecision::decide(KernelAddressPolicy::KptrRestrict, kptr_restrict).scrubbed
+        };
+        assert!(!decide(Some(0)));
+        assert!(decide(Some(1)));
+        assert!(decide(Some(2)));
+        // Unreadable, scrubbed to be safe.
+        assert!(decide(None));
+    }
+
+    #[test]
+    fn explicit_policy_ignores_kptr_restrict() {
+        for kptr_restrict in [None, Some(0), Some(2)] {
+            assert!(!ScrubDecision::decide(KernelAddressPolicy::Keep, kptr_restrict).scrubbed);
+            assert!(ScrubDecision::decide(KernelAddressPolicy::Scrub, kptr_restrict).scrubbed);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Detection of kernel warnings logged while tracing. Buggy ETM drivers have caused CoreSight
+//! warnings and RCU stalls in the field, so collection is disabled if they keep recurring.
+
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::Mutex;
+
+const KMSG: &str = "/dev/kmsg";
+/// Large enough for any kernel log record, a smaller read fails.
+const KMSG_RECORD_SIZE: usize = 8192;
+/// Number of warnings kept as diagnostics for the status.
+const DIAGNOSTICS_SIZE: usize = 8;
+
+/// Watches the kernel messages logged during a trace.
+pub struct KernelLogMonitor {
+    kmsg: File,
+}
+
+impl KernelLogMonitor {
+    /// Start watching the kernel messages logged from now on.
+    pub fn start() -> Result<Self> {
+        let mut kmsg = OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(KMSG)?;
+        kmsg.seek(SeekFrom::End(0))?;
+        Ok(KernelLogMonitor { kmsg })
+    }
+
+    /// Returns the warnings logged since the monitor started which may be caused by tracing.
+    pub fn finish(mut self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let mut record = vec![0; KMSG_RECORD_SIZE];
+        loop {
+            match self.kmsg.read(&mut record) {
+                Ok(0) => break,
+                Ok(len) => {
+                    // A record is "<prefix>;<message>\n", followed by optional dictionary lines.
+                    let record = String::from_utf8_lossy(&record[..len]);
+                    let message = record.lines().next().unwrap_or_default();
+                    let message = message.split_once(';').map_or(message, |(_, m)| m);
+                    if is_trace_warning(message) {
+                        warnings.push(message.to_string());
+                    }
+                }
+                // Records were overwritten before they could be read, continue with the next one.
+                Err(e) if e.raw_os_error() == Some(libc::EPIPE) => continue,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::error!("Failed to read kernel log: {}", e);
+                    break;
+                }
+            }
+        }
+        warnings
+    }
+}
+
+fn is_trace_warning(message: &str) -> bool {
+    let message = message.to_lowercase();
+    let coresight = message.contains("coresight") || message.contains("tmc_etr");
+    let rcu_stall = message.contains("rcu") && message.contains("stall");
+    rcu_stall
+        || (coresight
+            && ["warning", "error", "timeout", "timed out"].iter().any(|w| message.contains(w)))
+}
+
+/// Kernel warnings observed during traces.
+#[derive(Default)]
+pub struct KernelWarnings {
+    /// Number of traces during which warnings were logged.
+    traces_with_warnings: Mutex<u32>,
+    /// The latest warnings, with the tag of the trace they were logged during.
+    diagnostics: Mutex<VecDeque<String>>,
+}
+
+impl KernelWarnings {
+    /// Record the warnings logged during a trace, returning the number of traces with warnings.
+    pub fn record(&self, tag: &str, warnings: Vec<String>) -> u32 {
+        let mut traces = self.traces_with_warnings.lock().unwrap();
+        if warnings.is_empty() {
+            return *traces;
+        }
+        *traces += 1;
+        let mut diagnostics = self.diagnostics.lock().unwrap();
+        for warning in warnings {
+            log::warn!("Kernel warning during {} trace: {}", tag, warning);
+            if diagnostics.len() == DIAGNOSTICS_SIZE {
+                diagnostics.pop_front();
+            }
+            diagnostics.push_back(format!("{}: {}", tag, warning));
+        }
+        *traces
+    }
+
+    pub fn status(&self) -> String {
+        let traces = *self.traces_with_warnings.lock().unwrap();
+        if traces == 0 {
+            return String::new();
+        }
+        let mut status = format!("traces with kernel warnings: {}\n", traces);
+        for warning in self.diagnostics.lock().unwrap().iter() {
+            writeln!(status, "kernel warning: {}", warning).unwrap();
+        }
+        status
+    }
+}
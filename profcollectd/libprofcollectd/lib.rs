@@ -16,30 +16,65 @@
 
 //! ProfCollect Binder client interface.
 
+mod apex;
+mod audit;
+mod blackout;
+mod charge_budget;
+pub mod client;
+mod clock_watch;
+mod command_queue;
 mod config;
+mod diagnostics;
+mod disabled_trace_provider;
+mod eviction;
+mod fault_injection;
+mod hardware;
+mod in_memory_trace;
+mod intensive_mode;
+mod kernel_addresses;
+mod kernel_log;
+mod log_budget;
+mod oneshot_queue;
+mod perf_sessions;
+mod phone_state;
+mod processing;
+mod profile_summary;
+mod provider_chain;
+mod provider_error;
 mod report;
+mod resolution_cache;
+mod sched_policy;
+mod schedule_simulation;
 mod scheduler;
+mod scheduler_state;
 mod service;
 mod simpleperf_etm_trace_provider;
 mod simpleperf_lbr_trace_provider;
+mod storage;
+mod system_server;
+mod trace_metadata;
 mod trace_provider;
+mod zygote;
 
 #[cfg(feature = "test")]
 mod logging_trace_provider;
+#[cfg(feature = "test")]
+pub mod provider_conformance;
+
+#[cfg(feature = "benchmark")]
+pub mod benchmark;
 
 use anyhow::{Context, Result};
-use profcollectd_aidl_interface::aidl::com::android::server::profcollect::IProfCollectd::{
-    self, BnProfCollectd,
-};
+use client::Client;
+use profcollectd_aidl_interface::aidl::com::android::server::profcollect::IProfCollectd::BnProfCollectd;
 use profcollectd_aidl_interface::aidl::com::android::server::profcollect::IProviderStatusCallback::{IProviderStatusCallback, BnProviderStatusCallback};
 use profcollectd_aidl_interface::binder::{self, BinderFeatures};
 use service::{err_to_binder_status, ProfcollectdBinderService};
 use std::time::{Duration, Instant};
 
-const PROFCOLLECTD_SERVICE_NAME: &str = "profcollectd";
-
 struct ProviderStatusCallback {
     service_start_time: Instant,
+    boot_trace: bool,
 }
 
 impl binder::Interface for ProviderStatusCallback {}
@@ -51,7 +86,7 @@ impl IProviderStatusCallback for ProviderStatusCallback {
         // TODO: should we check boottime instead?
         const TIMEOUT_TO_COLLECT_BOOT_PROFILE: Duration = Duration::from_secs(3);
         let elapsed = Instant::now().duration_since(self.service_start_time);
-        if elapsed < TIMEOUT_TO_COLLECT_BOOT_PROFILE {
+        if self.boot_trace && elapsed < TIMEOUT_TO_COLLECT_BOOT_PROFILE {
             trace_system("boot").map_err(err_to_binder_status)?;
         }
         schedule().map_err(err_to_binder_status)?;
@@ -65,73 +100,138 @@ pub fn init_service(schedule_now: bool) -> Result<()> {
     binder::ProcessState::start_thread_pool();
 
     let profcollect_binder_service = ProfcollectdBinderService::new()?;
+    let boot_trace = profcollect_binder_service.boot_trace();
     binder::add_service(
-        PROFCOLLECTD_SERVICE_NAME,
-        BnProfCollectd::new_binder(profcollect_binder_service, BinderFeatures::default())
-            .as_binder(),
+        client::SERVICE_NAME,
+        // The SELinux context of callers is checked for zygote hints, see `notify_zygote_phase`.
+        BnProfCollectd::new_binder(
+            profcollect_binder_service,
+            BinderFeatures { set_requesting_sid: true },
+        )
+        .as_binder(),
     )
     .context("Failed to register service.")?;
 
     if schedule_now {
         let cb = BnProviderStatusCallback::new_binder(
-            ProviderStatusCallback { service_start_time: Instant::now() },
+            ProviderStatusCallback { service_start_time: Instant::now(), boot_trace },
             BinderFeatures::default(),
         );
-        get_profcollectd_service()?.registerProviderStatusCallback(&cb)?;
+        connect()?.service().registerProviderStatusCallback(&cb)?;
     }
 
     binder::ProcessState::join_thread_pool();
     Ok(())
 }
 
-fn get_profcollectd_service() -> Result<binder::Strong<dyn IProfCollectd::IProfCollectd>> {
-    binder::wait_for_interface(PROFCOLLECTD_SERVICE_NAME)
-        .context("Failed to get profcollectd binder service, is profcollectd running?")
+fn connect() -> Result<Client> {
+    Client::connect().context("Failed to get profcollectd binder service")
 }
 
 /// Schedule periodic profile collection.
 pub fn schedule() -> Result<()> {
-    get_profcollectd_service()?.schedule()?;
-    Ok(())
+    Ok(connect()?.schedule()?)
 }
 
 /// Terminate periodic profile collection.
 pub fn terminate() -> Result<()> {
-    get_profcollectd_service()?.terminate()?;
-    Ok(())
+    Ok(connect()?.terminate()?)
 }
 
 /// Immediately schedule a one-off trace.
 pub fn trace_system(tag: &str) -> Result<()> {
-    get_profcollectd_service()?.trace_system(tag)?;
-    Ok(())
+    Ok(connect()?.trace_system(tag)?)
+}
+
+/// Suppress all trace collection until the blackout for `reason` ends, or `duration` passes.
+pub fn begin_blackout(reason: &str, duration: Option<Duration>) -> Result<()> {
+    Ok(connect()?.begin_blackout(reason, duration)?)
+}
+
+/// End the blackout for `reason`.
+pub fn end_blackout(reason: &str) -> Result<()> {
+    Ok(connect()?.end_blackout(reason)?)
+}
+
+/// Collect more often and for longer for `duration`, then return to the normal schedule.
+pub fn set_intensive_mode(duration: Duration) -> Result<()> {
+    Ok(connect()?.set_intensive_mode(duration)?)
+}
+
+/// Trace the boot on the next start if `enable`, or skip it otherwise.
+pub fn arm_boot_trace(enable: bool) -> Result<()> {
+    Ok(connect()?.arm_boot_trace(enable)?)
+}
+
+/// Get a human-readable status of profcollectd.
+pub fn get_status() -> Result<String> {
+    Ok(connect()?.get_status()?)
+}
+
+/// Simulate the periodic collection of the next 24 hours, as a human-readable timeline.
+pub fn simulate_schedule() -> Result<String> {
+    Ok(connect()?.simulate_schedule()?)
+}
+
+/// Summarize the processed profiles, listing the binaries and functions executed the most.
+pub fn summarize_profiles() -> Result<String> {
+    Ok(connect()?.summarize_profiles(None)?)
 }
 
 /// Process traces.
 pub fn process() -> Result<()> {
-    get_profcollectd_service()?.process()?;
-    Ok(())
+    Ok(connect()?.process()?)
 }
 
 /// Process traces and report profile.
 pub fn report() -> Result<String> {
-    Ok(get_profcollectd_service()?.report(report::NO_USAGE_SETTING)?)
+    Ok(connect()?.report(client::NO_USAGE_SETTING)?.name)
+}
+
+/// Process traces and report only the profiles of `tags`.
+pub fn report_for_tags(tags: &[String]) -> Result<String> {
+    Ok(connect()?.report_for_tags(tags)?.name)
+}
+
+/// Get the age of the newest processed profile and of the newest report, None if there is none.
+pub fn get_profile_freshness() -> Result<(Option<Duration>, Option<Duration>)> {
+    Ok(connect()?.get_profile_freshness()?)
+}
+
+/// Remove stale files and expired reports, and evict traces, profiles and reports over their
+/// storage limits. Returns the number of bytes freed.
+pub fn run_cleanup() -> Result<u64> {
+    Ok(connect()?.run_cleanup()?)
+}
+
+/// Get the PMU events, ETM and SPE capabilities exposed by the hardware.
+pub fn get_hardware_capabilities() -> Result<client::HardwareCapabilities> {
+    Ok(connect()?.get_hardware_capabilities()?)
+}
+
+/// Get a zip of the redacted status, config and trace log of profcollectd, for bug reports.
+pub fn get_diagnostics_bundle() -> Result<Vec<u8>> {
+    Ok(connect()?.get_diagnostics_bundle()?)
 }
 
-/// Clear all local data.
+/// Clear all local data, and reset the identifiers recorded in report manifests.
 pub fn reset() -> Result<()> {
     config::clear_data()?;
+    config::reset_report_identifiers(&config::DeviceIdentifiers)?;
     Ok(())
 }
 
-/// Inits logging for Android
+/// Inits logging for Android, within the log budget, see `log_budget`.
 pub fn init_logging() {
     let max_log_level =
         if cfg!(feature = "test") { log::LevelFilter::Info } else { log::LevelFilter::Error };
-    android_logger::init_once(
-        android_logger::Config::default()
-            .with_tag("profcollectd")
-            .with_max_level(max_log_level)
-            .with_log_buffer(android_logger::LogId::System),
+    log_budget::init(
+        android_logger::AndroidLogger::new(
+            android_logger::Config::default()
+                .with_tag("profcollectd")
+                .with_max_level(max_log_level)
+                .with_log_buffer(android_logger::LogId::System),
+        ),
+        max_log_level,
     );
 }
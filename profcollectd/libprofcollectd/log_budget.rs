@@ -0,0 +1,250 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Budget of the log output of profcollectd. Failure loops used to flood the system log buffer,
+//! rotating the logs of other services out of bug reports, so messages past the hourly budget are
+//! dropped, and summarized once the hour is over. Notices about the budget are logged at the error
+//! level, the only one logged in production.
+
+use once_cell::sync::Lazy;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Most lines and bytes of messages logged per budget period.
+pub const MAX_LINES_PER_PERIOD: u64 = 600;
+pub const MAX_BYTES_PER_PERIOD: u64 = 64 * 1024;
+pub const BUDGET_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+static LOG_BUDGET: Lazy<Mutex<LogBudget>> =
+    Lazy::new(|| Mutex::new(LogBudget::new(Instant::now())));
+
+/// Accounting of the messages logged in the current budget period.
+pub struct LogBudget {
+    period_start: Instant,
+    lines: u64,
+    bytes: u64,
+    dropped_lines: u64,
+    dropped_bytes: u64,
+    /// Lines dropped in the previous periods.
+    total_dropped_lines: u64,
+}
+
+impl LogBudget {
+    pub fn new(now: Instant) -> Self {
+        LogBudget {
+            period_start: now,
+            lines: 0,
+            bytes: 0,
+            dropped_lines: 0,
+            dropped_bytes: 0,
+            total_dropped_lines: 0,
+        }
+    }
+
+    /// End of the current budget period.
+    pub fn period_end(&self) -> Instant {
+        self.period_start + BUDGET_PERIOD
+    }
+
+    /// Start a new budget period if the current one is over at `now`. Returns the summary of the
+    /// messages dropped during the period which ended, if any.
+    pub fn roll_over(&mut self, now: Instant) -> Option<String> {
+        if now < self.period_end() {
+            return None;
+        }
+        let summary = (self.dropped_lines > 0).then(|| {
+            format!(
+                "Log budget exceeded, dropped {} lines ({} bytes) in the last {}s.",
+                self.dropped_lines,
+                self.dropped_bytes,
+                now.duration_since(self.period_start).as_secs()
+            )
+        });
+        *self = LogBudget {
+            total_dropped_lines: self.total_dropped_lines + self.dropped_lines,
+            ..LogBudget::new(now)
+        };
+        summary
+    }
+
+    /// Account for a message of `bytes` logged at `now`. Returns whether it fits in the budget,
+    /// and a notice to log before it, if any: when the budget is first exceeded, and after a
+    /// period during which messages were dropped.
+    pub fn admit(&mut self, now: Instant, bytes: u64) -> (bool, Option<String>) {
+        let mut notice = self.roll_over(now);
+        if self.lines < MAX_LINES_PER_PERIOD && self.bytes + bytes <= MAX_BYTES_PER_PERIOD {
+            self.lines += 1;
+            self.bytes += bytes;
+            return (true, notice);
+        }
+        if self.dropped_lines == 0 {
+            notice = Some(format!(
+                "Log budget of {} lines or {} bytes per {}s exceeded, dropping messages for {}s.",
+                MAX_LINES_PER_PERIOD,
+                MAX_BYTES_PER_PERIOD,
+                BUDGET_PERIOD.as_secs(),
+                self.period_end().saturating_duration_since(now).as_secs()
+            ));
+        }
+        self.dropped_lines += 1;
+        self.dropped_bytes += bytes;
+        (false, notice)
+    }
+
+    fn status(&self) -> String {
+        format!(
+            "log budget: {} lines, {} bytes logged in the current period, {} lines dropped \
+             ({} before)\n",
+            self.lines, self.bytes, self.dropped_lines, self.total_dropped_lines
+        )
+    }
+}
+
+/// Logger passing the messages within the log budget to `inner`.
+struct BudgetedLogger<L> {
+    inner: L,
+}
+
+impl<L: log::Log> log::Log for BudgetedLogger<L> {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let bytes = record.args().to_string().len() as u64;
+        let (admitted, notice) = LOG_BUDGET.lock().unwrap().admit(Instant::now(), bytes);
+        if let Some(notice) = notice {
+            self.log_notice(record.target(), &notice);
+        }
+        if admitted {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+impl<L: log::Log> BudgetedLogger<L> {
+    /// Log a notice about the budget, outside of the budget.
+    fn log_notice(&self, target: &str, notice: &str) {
+        self.inner.log(
+            &log::Record::builder()
+                .level(log::Level::Error)
+                .target(target)
+                .args(format_args!("{}", notice))
+                .build(),
+        );
+    }
+
+    /// Log the summary of the dropped messages at the end of each budget period, rather than with
+    /// the next message, which may only come much later.
+    fn summarize_periods(&self) -> ! {
+        loop {
+            let period_end = LOG_BUDGET.lock().unwrap().period_end();
+            thread::sleep(period_end.saturating_duration_since(Instant::now()));
+            if let Some(summary) = LOG_BUDGET.lock().unwrap().roll_over(Instant::now()) {
+                self.log_notice(module_path!(), &summary);
+            }
+        }
+    }
+}
+
+/// Install `logger` as the logger of the process, within the log budget. Does nothing if a logger
+/// is already installed.
+pub fn init<L: log::Log + 'static>(logger: L, max_level: log::LevelFilter) {
+    let logger: &'static BudgetedLogger<L> = Box::leak(Box::new(BudgetedLogger { inner: logger }));
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(max_level);
+        thread::spawn(|| logger.summarize_periods());
+    }
+}
+
+/// Human-readable accounting of the log budget.
+pub fn status() -> String {
+    LOG_BUDGET.lock().unwrap().status()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{LogBudget, BUDGET_PERIOD, MAX_BYTES_PER_PERIOD, MAX_LINES_PER_PERIOD};
+
+    #[test]
+    fn admits_messages_within_budget() {
+        let now = Instant::now();
+        let mut budget = LogBudget::new(now);
+        for _ in 0..MAX_LINES_PER_PERIOD {
+            assert_eq!(budget.admit(now, 10), (true, None));
+        }
+    }
+
+    #[test]
+    fn drops_messages_past_line_budget_with_one_notice() {
+        let now = Instant::now();
+        let mut budget = LogBudget::new(now);
+        for _ in 0..MAX_LINES_PER_PERIOD {
+            budget.admit(now, 10);
+        }
+        let (admitted, notice) = budget.admit(now, 10);
+        assert!(!admitted);
+        assert!(notice.is_some());
+        assert_eq!(budget.admit(now, 10), (false, None));
+    }
+
+    #[test]
+    fn drops_messages_past_byte_budget() {
+        let now = Instant::now();
+        let mut budget = LogBudget::new(now);
+        assert_eq!(budget.admit(now, MAX_BYTES_PER_PERIOD), (true, None));
+        assert!(!budget.admit(now, 1).0);
+    }
+
+    #[test]
+    fn summarizes_dropped_messages_in_next_period() {
+        let now = Instant::now();
+        let mut budget = LogBudget::new(now);
+        budget.admit(now, MAX_BYTES_PER_PERIOD);
+        budget.admit(now, 100);
+        budget.admit(now + Duration::from_secs(1), 200);
+        let (admitted, notice) = budget.admit(now + BUDGET_PERIOD, 10);
+        assert!(admitted);
+        let notice = notice.unwrap();
+        assert!(notice.contains("dropped 2 lines (300 bytes)"), "{}", notice);
+        assert_eq!(budget.admit(now + BUDGET_PERIOD, 10), (true, None));
+    }
+
+    #[test]
+    fn summarizes_dropped_messages_at_period_end() {
+        let now = Instant::now();
+        let mut budget = LogBudget::new(now);
+        assert_eq!(budget.roll_over(now + BUDGET_PERIOD), None);
+        budget.admit(now + BUDGET_PERIOD, MAX_BYTES_PER_PERIOD);
+        budget.admit(now + BUDGET_PERIOD, 100);
+        assert_eq!(budget.roll_over(now + BUDGET_PERIOD + Duration::from_secs(1)), None);
+
+        let summary = budget.roll_over(now + BUDGET_PERIOD * 2).unwrap();
+        assert!(summary.contains("dropped 1 lines (100 bytes)"), "{}", summary);
+        assert_eq!(budget.period_end(), now + BUDGET_PERIOD * 3);
+        assert_eq!(budget.admit(now + BUDGET_PERIOD * 2, 10), (true, None));
+    }
+}
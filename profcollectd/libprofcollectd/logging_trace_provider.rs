@@ -16,14 +16,21 @@
 
 //! Logging trace provider for development and testing purposes.
 
-use anyhow::Result;
-use std::path::Path;
+use anyhow::{anyhow, Result};
+use std::fs::{read_dir, remove_file, File};
+use std::path::{Path, PathBuf};
+use std::thread;
 use std::time::Duration;
-use trace_provider::TraceProvider;
+use trace_provider::{ProfileFormat, TraceProvider};
 
+use crate::config::{Config, TraceClock};
+use crate::in_memory_trace::InMemoryTrace;
 use crate::trace_provider;
 
 static LOGGING_TRACEFILE_EXTENSION: &str = "loggingtrace";
+/// Selects the logging trace provider even on devices with tracing hardware, e.g. for
+/// profcollectd_integration_test.
+const FORCE_LOGGING_PROVIDER_PROPERTY: &str = "debug.profcollectd.logging_provider";
 
 pub struct LoggingTraceProvider {}
 
@@ -40,48 +47,127 @@ impl TraceProvider for LoggingTraceProvider {
         &self,
         trace_dir: &Path,
         tag: &str,
+        correlation_id: &str,
+        in_memory: Option<&InMemoryTrace>,
         sampling_period: &Duration,
         _binary_filter: &str,
-    ) {
-        let trace_file = trace_provider::get_path(trace_dir, tag, LOGGING_TRACEFILE_EXTENSION);
+    ) -> Result<Box<Path>> {
+        let trace_file =
+            trace_provider::get_path(trace_dir, tag, correlation_id, LOGGING_TRACEFILE_EXTENSION);
 
         log::info!(
-            "Trace event triggered, tag {}, sampling for {}ms, saving to {}",
+            "Trace event triggered, tag {}, correlation id {}, sampling for {}ms, saving to {}",
             tag,
+            correlation_id,
             sampling_period.as_millis(),
             trace_file.display()
         );
+        record_empty_trace(&trace_file, in_memory, sampling_period)?;
+        Ok(trace_file)
     }
 
     fn trace_process(
         &self,
         trace_dir: &Path,
         tag: &str,
+        correlation_id: &str,
+        in_memory: Option<&InMemoryTrace>,
         sampling_period: &Duration,
         processes: &str,
-    ) {
-        let trace_file = trace_provider::get_path(trace_dir, tag, LOGGING_TRACEFILE_EXTENSION);
+    ) -> Result<Box<Path>> {
+        let trace_file =
+            trace_provider::get_path(trace_dir, tag, correlation_id, LOGGING_TRACEFILE_EXTENSION);
 
         log::info!(
-            "Trace event triggered, tag {}, processes {}, sampling for {}ms, saving to {}",
+            "Trace event triggered, tag {}, correlation id {}, processes {}, sampling for {}ms, \
+             saving to {}",
             tag,
+            correlation_id,
             processes,
             sampling_period.as_millis(),
             trace_file.display()
         );
+        record_empty_trace(&trace_file, in_memory, sampling_period)?;
+        Ok(trace_file)
     }
 
-    fn process(&self, _trace_dir: &Path, _profile_dir: &Path, _binary_filter: &str) -> Result<()> {
+    fn get_data_loss(&self, _trace_file: &Path) -> Option<f64> {
+        None
+    }
+
+    fn adjust_to_data_loss(&self, _config: &Config, _data_loss_percent: f64) {}
+
+    fn process(
+        &self,
+        trace_dir: &Path,
+        profile_dir: &Path,
+        _binary_filter: &str,
+        _hide_kernel_addresses: bool,
+        formats: &[ProfileFormat],
+    ) -> Result<()> {
         log::info!("Process event triggered");
+        // Behave like a real provider, which turns each trace into empty profiles.
+        let process_trace_file = |trace_file: PathBuf| {
+            let file_name = trace_file
+                .file_name()
+                .ok_or_else(|| anyhow!("Malformed trace path: {}", trace_file.display()))?;
+            for format in formats {
+                File::create(profile_dir.join(file_name).with_extension(format.extension()))?;
+            }
+            remove_file(&trace_file)?;
+            Ok(())
+        };
+        read_dir(trace_dir)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|e| e.extension().is_some_and(|ext| ext == LOGGING_TRACEFILE_EXTENSION))
+            .try_for_each(process_trace_file)
+    }
+
+    fn merge(
+        &self,
+        profiles: &[PathBuf],
+        binary_filter: &str,
+        format: ProfileFormat,
+        output: &Path,
+    ) -> Result<()> {
+        log::info!(
+            "Merge event triggered, {} profiles, binary filter {}, saving {} to {}",
+            profiles.len(),
+            binary_filter,
+            format.inject_output(),
+            output.display()
+        );
+        // Behave like a real provider, which writes the merged profile.
+        File::create(output)?;
         Ok(())
     }
 
+    fn set_trace_clock(&self, _clock: TraceClock) {}
+
     fn set_log_file(&self, _filename: &Path) {}
     fn reset_log_file(&self) {}
 }
 
+/// Behave like a real provider, which is held for the sampling period and writes a trace file.
+fn record_empty_trace(
+    trace_file: &Path,
+    in_memory: Option<&InMemoryTrace>,
+    sampling_period: &Duration,
+) -> Result<()> {
+    thread::sleep(*sampling_period);
+    File::create(trace_provider::output_path(trace_file, in_memory))?;
+    Ok(())
+}
+
 impl LoggingTraceProvider {
     pub fn supported() -> bool {
         true
     }
+
+    /// Whether the logging trace provider is selected over the providers of the hardware.
+    pub fn forced() -> bool {
+        rustutils::system_properties::read_bool(FORCE_LOGGING_PROVIDER_PROPERTY, false)
+            .unwrap_or(false)
+    }
 }
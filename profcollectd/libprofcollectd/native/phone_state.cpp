@@ -0,0 +1,30 @@
+/*
+ * Copyright (C) 2026 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#include <media/AudioSystem.h>
+
+// Whether the audio mode is one of a voice call, see phone_state.rs.
+extern "C" bool profcollectd_in_call() {
+  switch (android::AudioSystem::getPhoneState()) {
+    case AUDIO_MODE_IN_CALL:
+    case AUDIO_MODE_IN_COMMUNICATION:
+    case AUDIO_MODE_CALL_SCREEN:
+      return true;
+    default:
+      // Including AUDIO_MODE_INVALID, when audioserver cannot be reached.
+      return false;
+  }
+}
@@ -0,0 +1,24 @@
+/*
+ * Copyright (C) 2026 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#include <sys/types.h>
+
+#include <processgroup/processgroup.h>
+
+// Apply the background cpuset and scheduling profiles to thread `tid`, see sched_policy.rs.
+extern "C" bool profcollectd_set_background(pid_t tid) {
+  return SetTaskProfiles(tid, {"CPUSET_SP_BACKGROUND", "SCHED_SP_BACKGROUND"}, true);
+}
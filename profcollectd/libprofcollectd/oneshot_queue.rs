@@ -0,0 +1,213 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Queue of one-shot trace requests which could not run immediately.
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::trace_metadata::Annotations;
+
+/// Number of dropped requests kept for the status.
+const DROPPED_HISTORY_SIZE: usize = 16;
+
+/// Priority of a one-shot trace request. Queued requests are tried in priority order, and in
+/// request order within a priority.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    Critical,
+}
+
+impl Priority {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::Critical => "critical",
+        }
+    }
+}
+
+/// A one-shot trace request.
+#[derive(Clone, Debug)]
+pub struct OneShotTrace {
+    pub tag: String,
+    /// Processes to trace, empty for a system-wide trace.
+    pub processes: String,
+    pub sampling_period: Duration,
+    /// How long the request may wait in the queue if it cannot run immediately.
+    pub expiry: Duration,
+    pub annotations: Annotations,
+    pub priority: Priority,
+    /// ID of the trace, generated when it was requested.
+    pub correlation_id: String,
+}
+
+pub struct QueuedTrace {
+    pub trace: OneShotTrace,
+    /// The config at the time of the request.
+    pub config: Config,
+    pub deadline: Instant,
+    /// Why the request could not run the last time it was tried.
+    pub blocked_by: String,
+    /// Whether the request was counted as suppressed by a blackout, which it is once however many
+    /// times it is retried.
+    pub counted_as_suppressed: bool,
+}
+
+#[derive(Default)]
+pub struct OneShotQueue {
+    queue: Mutex<VecDeque<QueuedTrace>>,
+    cond: Condvar,
+    /// Tags and reasons of the most recently dropped requests.
+    dropped: Mutex<VecDeque<(String, String)>>,
+}
+
+impl OneShotQueue {
+    /// Queue a request behind the requests of the same or a higher priority.
+    pub fn push(&self, trace: QueuedTrace) {
+        log::info!(
+            "Queuing {} trace {}, blocked by {}",
+            trace.trace.tag,
+            trace.trace.correlation_id,
+            trace.blocked_by
+        );
+        let mut queue = self.queue.lock().unwrap();
+        let position = queue.partition_point(|t| t.trace.priority >= trace.trace.priority);
+        queue.insert(position, trace);
+        self.cond.notify_one();
+    }
+
+    /// Put back a request which is still blocked, keeping its place in the queue unless requests
+    /// of a higher priority were queued meanwhile.
+    pub fn push_front(&self, trace: QueuedTrace) {
+        let mut queue = self.queue.lock().unwrap();
+        let position = queue.partition_point(|t| t.trace.priority > trace.trace.priority);
+        queue.insert(position, trace);
+        self.cond.notify_one();
+    }
+
+    /// Wait for the next request to try. Expired requests are dropped.
+    pub fn pop(&self) -> QueuedTrace {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let (expired, pending) = queue.drain(..).partition(|t| t.deadline <= now);
+            *queue = pending;
+            for t in expired.into_iter() {
+                self.record_dropped(&t.trace, &format!("expired, blocked by {}", t.blocked_by));
+            }
+            if let Some(trace) = queue.pop_front() {
+                return trace;
+            }
+            queue = self.cond.wait(queue).unwrap();
+        }
+    }
+
+    pub fn record_dropped(&self, trace: &OneShotTrace, reason: &str) {
+        log::info!("Dropped {} trace {}: {}", trace.tag, trace.correlation_id, reason);
+        let mut dropped = self.dropped.lock().unwrap();
+        if dropped.len() == DROPPED_HISTORY_SIZE {
+            dropped.pop_front();
+        }
+        dropped.push_back((trace.tag.clone(), reason.to_string()));
+    }
+
+    pub fn status(&self) -> String {
+        let mut status = String::new();
+        let now = Instant::now();
+        for t in self.queue.lock().unwrap().iter() {
+            let remaining = t.deadline.saturating_duration_since(now).as_secs();
+            writeln!(
+                status,
+                "queued: {} ({} priority, blocked by {}, expires in {}s)",
+                t.trace.tag,
+                t.trace.priority.as_str(),
+                t.blocked_by,
+                remaining
+            )
+            .unwrap();
+        }
+        for (tag, reason) in self.dropped.lock().unwrap().iter() {
+            writeln!(status, "dropped: {} ({})", tag, reason).unwrap();
+        }
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{OneShotQueue, OneShotTrace, Priority, QueuedTrace};
+    use crate::config::Config;
+    use crate::trace_metadata::{new_correlation_id, Annotations};
+
+    fn queued(tag: &str, priority: Priority, expiry: Duration) -> QueuedTrace {
+        QueuedTrace {
+            trace: OneShotTrace {
+                tag: tag.to_string(),
+                processes: String::new(),
+                sampling_period: Duration::from_millis(10),
+                expiry,
+                annotations: Annotations::new(),
+                priority,
+                correlation_id: new_correlation_id(),
+            },
+            config: Config::from_env().unwrap(),
+            deadline: Instant::now() + expiry,
+            blocked_by: "test".to_string(),
+            counted_as_suppressed: false,
+        }
+    }
+
+    #[test]
+    fn tries_requests_by_priority() {
+        let queue = OneShotQueue::default();
+        let expiry = Duration::from_secs(60);
+        queue.push(queued("low", Priority::Low, expiry));
+        queue.push(queued("normal_1", Priority::Normal, expiry));
+        queue.push(queued("critical", Priority::Critical, expiry));
+        queue.push(queued("normal_2", Priority::Normal, expiry));
+
+        let critical = queue.pop();
+        assert_eq!(critical.trace.tag, "critical");
+        // A request put back keeps its place ahead of the requests of a lower priority only.
+        queue.push_front(critical);
+        queue.push(queued("critical_2", Priority::Critical, expiry));
+        let tags: Vec<String> = (0..5).map(|_| queue.pop().trace.tag).collect();
+        assert_eq!(tags, ["critical", "critical_2", "normal_1", "normal_2", "low"]);
+    }
+
+    #[test]
+    fn drops_expired_requests() {
+        let queue = OneShotQueue::default();
+        queue.push(queued("expired", Priority::Critical, Duration::ZERO));
+        queue.push(queued("pending", Priority::Low, Duration::from_secs(60)));
+        assert!(queue.status().contains("queued: expired (critical priority, blocked by test"));
+
+        assert_eq!(queue.pop().trace.tag, "pending");
+        let status = queue.status();
+        assert!(!status.contains("queued:"), "{}", status);
+        assert!(status.contains("dropped: expired (expired, blocked by test)"), "{}", status);
+    }
+}
@@ -0,0 +1,187 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Detection of the perf sessions of perfetto. Concurrent perf_event users have caused both
+//! traced_perf and simpleperf to record corrupted data on some kernels, so no trace starts while
+//! perfetto is profiling. Blocked one-shot traces are queued, and periodic ones skipped.
+
+use std::fs::{read_dir, read_link, read_to_string};
+use std::path::Path;
+use std::sync::Mutex;
+
+const PROC_DIR: &str = "/proc";
+/// Processes of perfetto which open perf events for profiling sessions.
+const PERFETTO_PERF_PROCESSES: &[&str] = &["traced_perf"];
+/// Target of the file descriptors of perf events.
+const PERF_EVENT_FD: &str = "anon_inode:[perf_event]";
+
+/// What is known of the perf session of a perfetto process, named `<name> (<pid>)`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PerfSession {
+    /// The process holds perf events, so a session is running.
+    Active(String),
+    /// The file descriptors of the process cannot be read, e.g. when denied by SELinux, so its
+    /// sessions cannot be detected.
+    Undetectable(String),
+}
+
+/// Returns the perf session of the perfetto processes, if any, see `find_perf_session`.
+pub fn active_perf_session() -> Option<PerfSession> {
+    find_perf_session(Path::new(PROC_DIR))
+}
+
+/// Returns the first perfetto process of `proc_dir` with an active perf session, or else the
+/// first one whose sessions cannot be detected. The processes only hold perf events while a
+/// session is running.
+fn find_perf_session(proc_dir: &Path) -> Option<PerfSession> {
+    let mut undetectable = None;
+    for process in read_dir(proc_dir).ok()?.filter_map(|e| e.ok()).map(|e| e.path()) {
+        let Some(pid) = process.file_name().and_then(|f| f.to_str()?.parse::<u32>().ok()) else {
+            continue;
+        };
+        let Ok(comm) = read_to_string(process.join("comm")) else {
+            continue;
+        };
+        let name = comm.trim();
+        if !PERFETTO_PERF_PROCESSES.contains(&name) {
+            continue;
+        }
+        let process_name = format!("{} ({})", name, pid);
+        match holds_perf_events(&process) {
+            Some(true) => return Some(PerfSession::Active(process_name)),
+            Some(false) => (),
+            None => {
+                undetectable.get_or_insert(PerfSession::Undetectable(process_name));
+            }
+        }
+    }
+    undetectable
+}
+
+/// Whether `process` holds perf events, None if its file descriptors cannot be read.
+fn holds_perf_events(process: &Path) -> Option<bool> {
+    let fds = read_dir(process.join("fd")).ok()?;
+    Some(
+        fds.filter_map(|e| e.ok())
+            .any(|fd| read_link(fd.path()).is_ok_and(|target| target == Path::new(PERF_EVENT_FD))),
+    )
+}
+
+/// Traces held off by perfetto perf sessions.
+#[derive(Default)]
+pub struct PerfSessionConflicts {
+    /// Number of traces held off, and the session which held off the last one.
+    conflicts: Mutex<(u32, String)>,
+    /// The perfetto process whose sessions could not be detected at the last check, if any.
+    undetectable: Mutex<Option<String>>,
+}
+
+impl PerfSessionConflicts {
+    pub fn record(&self, session: &str) {
+        let mut conflicts = self.conflicts.lock().unwrap();
+        conflicts.0 += 1;
+        conflicts.1 = session.to_string();
+    }
+
+    /// Record the perfetto process whose sessions could not be detected before a trace, None if
+    /// they could.
+    pub fn set_undetectable(&self, process: Option<&str>) {
+        *self.undetectable.lock().unwrap() = process.map(str::to_string);
+    }
+
+    pub fn status(&self) -> String {
+        let mut status = String::new();
+        let conflicts = self.conflicts.lock().unwrap();
+        if conflicts.0 > 0 {
+            status += &format!(
+                "perfetto conflicts: {} traces held off, last by {}\n",
+                conflicts.0, conflicts.1
+            );
+        }
+        if let Some(process) = &*self.undetectable.lock().unwrap() {
+            status += &format!(
+                "perfetto conflicts: sessions of {} undetectable, its fds are unreadable\n",
+                process
+            );
+        }
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+    use std::os::unix::fs::symlink;
+    use std::path::Path;
+
+    use super::{find_perf_session, PerfSession, PerfSessionConflicts, PERF_EVENT_FD};
+    use crate::trace_metadata::new_correlation_id;
+
+    /// Add process `pid` named `comm` to `proc_dir`, with `fds` as the targets of its file
+    /// descriptors, or with unreadable file descriptors if None.
+    fn add_process(proc_dir: &Path, pid: u32, comm: &str, fds: Option<&[&str]>) {
+        let process = proc_dir.join(pid.to_string());
+        create_dir_all(&process).unwrap();
+        write(process.join("comm"), format!("{}\n", comm)).unwrap();
+        if let Some(fds) = fds {
+            create_dir_all(process.join("fd")).unwrap();
+            for (fd, target) in fds.iter().enumerate() {
+                symlink(target, process.join("fd").join(fd.to_string())).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn detects_perf_sessions() {
+        let proc_dir = temp_dir().join(format!("profcollectd_proc_{}", new_correlation_id()));
+        create_dir_all(proc_dir.join("self")).unwrap();
+        add_process(&proc_dir, 100, "simpleperf", Some(&[PERF_EVENT_FD]));
+        add_process(&proc_dir, 200, "traced_perf", Some(&["/dev/null"]));
+        assert_eq!(find_perf_session(&proc_dir), None);
+
+        // The fds of traced_perf are unreadable, e.g. denied by SELinux.
+        add_process(&proc_dir, 300, "traced_perf", None);
+        assert_eq!(
+            find_perf_session(&proc_dir),
+            Some(PerfSession::Undetectable("traced_perf (300)".to_string()))
+        );
+
+        // An active session wins over undetectable ones.
+        add_process(&proc_dir, 400, "traced_perf", Some(&["/dev/null", PERF_EVENT_FD]));
+        assert_eq!(
+            find_perf_session(&proc_dir),
+            Some(PerfSession::Active("traced_perf (400)".to_string()))
+        );
+        remove_dir_all(&proc_dir).unwrap();
+    }
+
+    #[test]
+    fn counts_conflicts_in_status() {
+        let conflicts = PerfSessionConflicts::default();
+        assert_eq!(conflicts.status(), "");
+        conflicts.record("traced_perf (123)");
+        conflicts.record("traced_perf (456)");
+        assert_eq!(
+            conflicts.status(),
+            "perfetto conflicts: 2 traces held off, last by traced_perf (456)\n"
+        );
+        conflicts.set_undetectable(Some("traced_perf (789)"));
+        assert!(conflicts.status().contains("sessions of traced_perf (789) undetectable"));
+        conflicts.set_undetectable(None);
+        assert!(!conflicts.status().contains("undetectable"));
+    }
+}
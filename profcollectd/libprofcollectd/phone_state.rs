@@ -0,0 +1,29 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Voice call state, read from the audio mode. No trace is started during a call, to avoid audio
+//! glitches from CoreSight bus contention seen on some SoCs.
+
+extern "C" {
+    /// Defined in native/phone_state.cpp.
+    fn profcollectd_in_call() -> bool;
+}
+
+/// Whether a voice call, over the modem or VoIP, is active.
+pub fn in_call() -> bool {
+    // SAFETY: The shim takes no argument and only queries audioserver.
+    unsafe { profcollectd_in_call() }
+}
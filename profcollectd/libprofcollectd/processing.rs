@@ -0,0 +1,569 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Trace processing pipeline. Traces are turned into profiles by a sequence of stages, run in
+//! order, so that new transformations can be added as stages of their own. The stages are
+//! configured by name in `processing_stages`:
+//!
+//! - binary_filter: leaves the binaries which repeatedly failed to resolve out of the decoding.
+//! - scrub: decides whether raw kernel addresses are scrubbed from the profiles.
+//! - decode: decodes the traces into profiles, required.
+//! - attach_metadata: moves the metadata of the traces next to their profiles, required.
+//! - merge: merges the profiles of the run which can be used together.
+//! - score: scores the profiles of the run, see `profile_score`.
+
+use anyhow::{ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, copy, read_dir, read_to_string, remove_file, write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::config::{Config, KernelAddressPolicy};
+use crate::in_memory_trace::InMemoryTrace;
+use crate::kernel_addresses::ScrubDecision;
+use crate::resolution_cache::ResolutionCache;
+use crate::trace_metadata::{
+    get_metadata_path, is_metadata, move_processed_metadata, TraceMetadata,
+};
+use crate::trace_provider::{ProfileFormat, TraceProvider};
+
+/// Stages traces are processed through when `processing_stages` is not set, or is invalid.
+pub const DEFAULT_PROCESSING_STAGES: &str = "binary_filter,scrub,decode,attach_metadata";
+
+/// What the stages operate on, and the state they hand over to the next stages.
+pub struct StageContext<'a> {
+    pub provider: &'a dyn TraceProvider,
+    pub config: &'a Config,
+    pub trace_dir: &'a Path,
+    pub profile_dir: &'a Path,
+    /// Binaries which repeatedly failed to resolve, None if they are not tracked, e.g. in
+    /// benchmarks.
+    pub resolution_cache: Option<&'a ResolutionCache>,
+    /// Filter of the binaries to decode.
+    pub binary_filter: String,
+    /// Whether kernel addresses are scrubbed from the profiles. They are kept unless the scrub
+    /// stage decides otherwise.
+    pub kernel_addresses: ScrubDecision,
+    /// Profiles decoded by this run, set by the decode stage.
+    pub profiles: Vec<PathBuf>,
+}
+
+impl<'a> StageContext<'a> {
+    pub fn new(
+        provider: &'a dyn TraceProvider,
+        config: &'a Config,
+        trace_dir: &'a Path,
+        profile_dir: &'a Path,
+        binary_filter: &str,
+        resolution_cache: Option<&'a ResolutionCache>,
+    ) -> Self {
+        StageContext {
+            provider,
+            config,
+            trace_dir,
+            profile_dir,
+            resolution_cache,
+            binary_filter: binary_filter.to_string(),
+            kernel_addresses: ScrubDecision::decide(KernelAddressPolicy::Keep, None),
+            profiles: Vec::new(),
+        }
+    }
+}
+
+/// When a stage runs, relative to the decoding of the traces. The stages of a pipeline are
+/// configured in the order of their phases.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Phase {
+    /// Before the traces are decoded, deciding how they are decoded.
+    Prepare,
+    Decode,
+    AttachMetadata,
+    /// On the decoded profiles of the run and their metadata.
+    Profiles,
+}
+
+/// A stage of the processing pipeline.
+pub trait Stage: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn phase(&self) -> Phase;
+    fn run(&self, context: &mut StageContext) -> Result<()>;
+}
+
+/// The stage called `name`, None if there is none.
+fn new_stage(name: &str) -> Option<Box<dyn Stage>> {
+    let stage: Box<dyn Stage> = match name {
+        "binary_filter" => Box::new(BinaryFilter),
+        "scrub" => Box::new(Scrub),
+        "decode" => Box::new(Decode),
+        "attach_metadata" => Box::new(AttachMetadata),
+        "merge" => Box::new(Merge),
+        "score" => Box::new(Score),
+        _ => return None,
+    };
+    Some(stage)
+}
+
+/// Leave the binaries which repeatedly failed to resolve out of the binary filter.
+struct BinaryFilter;
+
+impl Stage for BinaryFilter {
+    fn name(&self) -> &'static str {
+        "binary_filter"
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Prepare
+    }
+
+    fn run(&self, context: &mut StageContext) -> Result<()> {
+        if let Some(cache) = context.resolution_cache {
+            context.binary_filter = cache.binary_filter(&context.binary_filter, SystemTime::now());
+        }
+        Ok(())
+    }
+}
+
+/// Decide whether kernel addresses are scrubbed from the profiles, once per processing run.
+struct Scrub;
+
+impl Stage for Scrub {
+    fn name(&self) -> &'static str {
+        "scrub"
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Prepare
+    }
+
+    fn run(&self, context: &mut StageContext) -> Result<()> {
+        context.kernel_addresses = ScrubDecision::current(context.config);
+        Ok(())
+    }
+}
+
+/// Decode the traces into profiles of the binaries matching the binary filter, in each of the
+/// configured formats.
+struct Decode;
+
+impl Stage for Decode {
+    fn name(&self) -> &'static str {
+        "decode"
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Decode
+    }
+
+    fn run(&self, context: &mut StageContext) -> Result<()> {
+        let before = list_profiles(context.profile_dir)?;
+        context.provider.process(
+            context.trace_dir,
+            context.profile_dir,
+            &context.binary_filter,
+            context.kernel_addresses.scrubbed,
+            &context.config.profile_formats(),
+        )?;
+        context.profiles =
+            list_profiles(context.profile_dir)?.difference(&before).cloned().collect();
+        Ok(())
+    }
+}
+
+/// Move the metadata of the processed traces next to their profiles, recording how kernel
+/// addresses were scrubbed from them.
+struct AttachMetadata;
+
+impl Stage for AttachMetadata {
+    fn name(&self) -> &'static str {
+        "attach_metadata"
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::AttachMetadata
+    }
+
+    fn run(&self, context: &mut StageContext) -> Result<()> {
+        move_processed_metadata(
+            context.trace_dir,
+            context.profile_dir,
+            Some(context.kernel_addresses),
+        )
+    }
+}
+
+/// Merge the branch-list profiles of the run which can be used together, those of the same tag,
+/// provider, config, APEX versions and kernel address decision, into the profile of the earliest
+/// trace. Their BOLT profiles are merged from the same branch lists.
+struct Merge;
+
+impl Stage for Merge {
+    fn name(&self) -> &'static str {
+        "merge"
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Profiles
+    }
+
+    fn run(&self, context: &mut StageContext) -> Result<()> {
+        let mut groups: BTreeMap<String, Vec<(PathBuf, TraceMetadata)>> = BTreeMap::new();
+        for profile in context.profiles.iter().filter(|p| is_branch_list(p)) {
+            let Some(metadata) = read_metadata(profile) else { continue };
+            let key = serde_json::to_string(&(
+                &metadata.tag,
+                &metadata.provider,
+                &metadata.config_digest,
+                &metadata.apex_versions,
+                &metadata.kernel_addresses,
+            ))?;
+            groups.entry(key).or_default().push((profile.clone(), metadata));
+        }
+        for mut group in groups.into_values().filter(|g| g.len() > 1) {
+            group.sort_by_key(|(_, metadata)| metadata.start_time_ms);
+            merge_profiles(context, group)?;
+        }
+        Ok(())
+    }
+}
+
+/// Merge the profiles of `group` into the first one, along with their metadata.
+fn merge_profiles(context: &mut StageContext, group: Vec<(PathBuf, TraceMetadata)>) -> Result<()> {
+    let inputs: Vec<PathBuf> = group.iter().map(|(profile, _)| profile.clone()).collect();
+    let mut group = group.into_iter();
+    let Some((target, mut metadata)) = group.next() else { return Ok(()) };
+    let bolt_profile = |profile: &Path| profile.with_extension(ProfileFormat::Bolt.extension());
+    // Merged into memory first, so that the profiles are left as they are if merging fails.
+    let mut merged = Vec::new();
+    for format in [ProfileFormat::BranchList, ProfileFormat::Bolt] {
+        let output = match format {
+            ProfileFormat::Bolt => bolt_profile(&target),
+            _ => target.clone(),
+        };
+        if output.exists() {
+            let in_memory = InMemoryTrace::new()?;
+            context.provider.merge(&inputs, &context.binary_filter, format, in_memory.path())?;
+            merged.push((in_memory, output));
+        }
+    }
+    for (in_memory, output) in merged {
+        copy(in_memory.path(), output)?;
+    }
+    for (profile, other) in group {
+        metadata.absorb(other);
+        for file in [bolt_profile(&profile), get_metadata_path(&profile), profile.clone()] {
+            if file.exists() {
+                remove_file(&file)?;
+            }
+        }
+        context.profiles.retain(|p| p != &profile && p != &bolt_profile(&profile));
+    }
+    write(get_metadata_path(&target), serde_json::to_string(&metadata)?)?;
+    Ok(())
+}
+
+/// Record the score of each branch-list profile of the run in its metadata.
+struct Score;
+
+impl Stage for Score {
+    fn name(&self) -> &'static str {
+        "score"
+    }
+
+    fn phase(&self) -> Phase {
+        Phase::Profiles
+    }
+
+    fn run(&self, context: &mut StageContext) -> Result<()> {
+        for profile in context.profiles.iter().filter(|p| is_branch_list(p)) {
+            let Some(mut metadata) = read_metadata(profile) else { continue };
+            let bytes = fs::metadata(profile)?.len();
+            metadata.score = Some(profile_score(bytes, metadata.data_loss_percent));
+            write(get_metadata_path(profile), serde_json::to_string(&metadata)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Score of a profile of `bytes` whose trace lost `data_loss_percent` of its data: the bytes of
+/// branch data it holds, discounted by the share lost during the capture. Profiles with higher
+/// scores contribute more once merged.
+pub fn profile_score(bytes: u64, data_loss_percent: Option<f64>) -> f64 {
+    bytes as f64 * (1.0 - data_loss_percent.unwrap_or(0.0).clamp(0.0, 100.0) / 100.0)
+}
+
+fn is_branch_list(profile: &Path) -> bool {
+    profile.extension().is_some_and(|ext| ext == ProfileFormat::BranchList.extension())
+}
+
+/// Profiles in `profile_dir`, without their metadata.
+fn list_profiles(profile_dir: &Path) -> Result<BTreeSet<PathBuf>> {
+    Ok(read_dir(profile_dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && !is_metadata(p))
+        .collect())
+}
+
+/// Metadata of `profile`, None if it has none or it is malformed. Such profiles are left as they
+/// are by the stages updating metadata.
+fn read_metadata(profile: &Path) -> Option<TraceMetadata> {
+    let metadata = get_metadata_path(profile);
+    let contents = read_to_string(&metadata).ok()?;
+    serde_json::from_str(&contents)
+        .map_err(|e| log::error!("Malformed metadata {}: {:?}", metadata.display(), e))
+        .ok()
+}
+
+/// The stages traces are processed through, in order.
+pub struct ProcessingPipeline {
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl Default for ProcessingPipeline {
+    fn default() -> Self {
+        let stages = DEFAULT_PROCESSING_STAGES.split(',').filter_map(new_stage).collect();
+        ProcessingPipeline { stages }
+    }
+}
+
+impl ProcessingPipeline {
+    /// The pipeline of the stages of `config`. The stages must be given in the order of their
+    /// phases, with the decode and attach_metadata stages, and with the scrub stage if the kernel
+    /// address policy may scrub addresses.
+    pub fn new(config: &Config) -> Result<Self> {
+        let mut stages: Vec<Box<dyn Stage>> = Vec::new();
+        for name in &config.processing_stages {
+            let stage = new_stage(name).with_context(|| format!("Unknown stage {}.", name))?;
+            ensure!(stages.iter().all(|s| s.name() != stage.name()), "Repeated stage {}.", name);
+            if let Some(last) = stages.last() {
+                ensure!(
+                    last.phase() <= stage.phase(),
+                    "Stage {} cannot run after {}.",
+                    name,
+                    last.name()
+                );
+            }
+            stages.push(stage);
+        }
+        let has_stage = |name: &str| stages.iter().any(|s| s.name() == name);
+        for required in ["decode", "attach_metadata"] {
+            ensure!(has_stage(required), "Missing stage {}.", required);
+        }
+        ensure!(
+            config.kernel_address_policy == KernelAddressPolicy::Keep || has_stage("scrub"),
+            "Missing stage scrub, required by the kernel address policy."
+        );
+        Ok(ProcessingPipeline { stages })
+    }
+
+    /// Run the stages in order, stopping at the first failure.
+    pub fn run(&self, context: &mut StageContext) -> Result<()> {
+        for stage in &self.stages {
+            stage.run(context).with_context(|| format!("Stage {} failed.", stage.name()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Cost of the processing of the traces since the last report, included in its manifest so that
+/// trace duration and frequency can be tuned to the measured cost on each device model. The stats
+/// are recorded by tag, so that reports scoped to some tags only account for their traces.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessingStats {
+    /// Bytes of the processed traces.
+    pub trace_bytes: u64,
+    /// CPU time spent processing them, in milliseconds.
+    pub cpu_time_ms: u64,
+}
+
+impl ProcessingStats {
+    /// Load the stats by tag from `file`, empty if there are none.
+    fn load_by_tag(file: &Path) -> BTreeMap<String, ProcessingStats> {
+        read_to_string(file).ok().and_then(|s| serde_json::from_str(&s).ok()).unwrap_or_default()
+    }
+
+    /// Load the stats of `tags` from `file`, of all tags if empty.
+    pub fn load(file: &Path, tags: &[String]) -> Self {
+        let mut total = ProcessingStats::default();
+        for (tag, stats) in Self::load_by_tag(file) {
+            if tags.is_empty() || tags.contains(&tag) {
+                total.trace_bytes += stats.trace_bytes;
+                total.cpu_time_ms += stats.cpu_time_ms;
+            }
+        }
+        total
+    }
+
+    /// Add the processing of the traces of `trace_bytes_by_tag` in `cpu_time` to the stats in
+    /// `file`. The CPU time is shared between tags in proportion to their bytes.
+    pub fn record(
+        file: &Path,
+        trace_bytes_by_tag: &BTreeMap<String, u64>,
+        cpu_time: Duration,
+    ) -> Result<()> {
+        let total_bytes: u64 = trace_bytes_by_tag.values().sum();
+        if total_bytes == 0 {
+            return Ok(());
+        }
+        let mut by_tag = Self::load_by_tag(file);
+        for (tag, &trace_bytes) in trace_bytes_by_tag {
+            let stats = by_tag.entry(tag.clone()).or_default();
+            stats.trace_bytes += trace_bytes;
+            stats.cpu_time_ms +=
+                (cpu_time.as_millis() * trace_bytes as u128 / total_bytes as u128) as u64;
+        }
+        write(file, serde_json::to_string(&by_tag)?)?;
+        Ok(())
+    }
+
+    /// Remove the stats of `tags` from `file`, once they are reported.
+    pub fn remove(file: &Path, tags: &[String]) -> Result<()> {
+        let mut by_tag = Self::load_by_tag(file);
+        let count = by_tag.len();
+        by_tag.retain(|tag, _| !tags.contains(tag));
+        if by_tag.len() != count {
+            write(file, serde_json::to_string(&by_tag)?)?;
+        }
+        Ok(())
+    }
+
+    /// Megabytes of traces processed per second of CPU time, None if nothing was processed.
+    pub fn throughput(&self) -> Option<f64> {
+        (self.trace_bytes > 0 && self.cpu_time_ms > 0).then(|| {
+            (self.trace_bytes as f64 / (1024.0 * 1024.0)) / (self.cpu_time_ms as f64 / 1000.0)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::env::temp_dir;
+    use std::fs;
+    use std::time::Duration;
+
+    use super::{profile_score, ProcessingPipeline, ProcessingStats, StageContext};
+    use crate::config::{Config, KernelAddressPolicy};
+    use crate::logging_trace_provider::LoggingTraceProvider;
+    use crate::trace_metadata::{get_metadata_path, new_correlation_id, TraceMetadata};
+    use crate::trace_provider::TraceProvider;
+
+    fn with_stages(stages: &str) -> Config {
+        let mut config = Config::from_env().unwrap();
+        config.kernel_address_policy = KernelAddressPolicy::Keep;
+        config.processing_stages = stages.split(',').map(str::to_string).collect();
+        config
+    }
+
+    #[test]
+    fn stages_are_configured_in_order() {
+        let pipeline =
+            ProcessingPipeline::new(&with_stages("scrub,decode,attach_metadata,score")).unwrap();
+        let names: Vec<&str> = pipeline.stages.iter().map(|s| s.name()).collect();
+        assert_eq!(names, ["scrub", "decode", "attach_metadata", "score"]);
+        assert!(ProcessingPipeline::new(&with_stages("decode,attach_metadata")).is_ok());
+
+        for invalid in [
+            "decode,attach_metadata,compress",
+            "decode,decode,attach_metadata",
+            "binary_filter,attach_metadata",
+            "decode,merge,attach_metadata",
+            "decode,attach_metadata,scrub",
+        ] {
+            assert!(ProcessingPipeline::new(&with_stages(invalid)).is_err(), "{}", invalid);
+        }
+
+        // Kernel addresses cannot be left unscrubbed by leaving the stage out.
+        let mut config = with_stages("decode,attach_metadata");
+        config.kernel_address_policy = KernelAddressPolicy::Scrub;
+        assert!(ProcessingPipeline::new(&config).is_err());
+        config.processing_stages =
+            super::DEFAULT_PROCESSING_STAGES.split(',').map(str::to_string).collect();
+        assert!(ProcessingPipeline::new(&config).is_ok());
+    }
+
+    #[test]
+    fn merges_and_scores_the_profiles_of_a_tag() {
+        let work_dir = temp_dir().join(format!("profcollectd_processing_{}", new_correlation_id()));
+        let (trace_dir, profile_dir) = (work_dir.join("traces"), work_dir.join("profiles"));
+        fs::create_dir_all(&trace_dir).unwrap();
+        fs::create_dir_all(&profile_dir).unwrap();
+        let config = with_stages("decode,attach_metadata,merge,score");
+        let provider = LoggingTraceProvider {};
+        let mut ids = BTreeMap::new();
+        for tag in ["periodic", "periodic", "applaunch"] {
+            let id = new_correlation_id();
+            let trace =
+                provider.trace_system(&trace_dir, tag, &id, None, &Duration::ZERO, "").unwrap();
+            let mut metadata = TraceMetadata::new(tag, &id, provider.get_name(), &config);
+            metadata.annotations.insert(format!("{}_key", id), "value".to_string());
+            metadata.finish(&trace).unwrap();
+            ids.entry(tag).or_insert_with(Vec::new).push(id);
+        }
+
+        let pipeline = ProcessingPipeline::new(&config).unwrap();
+        let mut context =
+            StageContext::new(&provider, &config, &trace_dir, &profile_dir, ".*", None);
+        pipeline.run(&mut context).unwrap();
+
+        // The profiles of the periodic traces are merged into one, the other one is kept.
+        assert_eq!(context.profiles.len(), 2);
+        assert_eq!(fs::read_dir(&profile_dir).unwrap().count(), 4);
+        for profile in &context.profiles {
+            let metadata: TraceMetadata =
+                serde_json::from_str(&fs::read_to_string(get_metadata_path(profile)).unwrap())
+                    .unwrap();
+            let mut merged_ids = vec![metadata.correlation_id.clone()];
+            merged_ids.extend(metadata.merged_correlation_ids.iter().cloned());
+            merged_ids.sort();
+            let mut expected = ids[metadata.tag.as_str()].clone();
+            expected.sort();
+            assert_eq!(merged_ids, expected);
+            assert_eq!(metadata.annotations.len(), expected.len());
+            assert_eq!(metadata.score, Some(0.0));
+        }
+        assert_eq!(profile_score(1000, Some(25.0)), 750.0);
+        assert_eq!(profile_score(1000, None), 1000.0);
+        fs::remove_dir_all(work_dir).unwrap();
+    }
+
+    #[test]
+    fn stats_are_scoped_to_tags() {
+        let file = temp_dir().join(format!("profcollectd_stats_{}.json", new_correlation_id()));
+        let processed = BTreeMap::from([("a".to_string(), 3000), ("b".to_string(), 1000)]);
+        ProcessingStats::record(&file, &processed, Duration::from_millis(400)).unwrap();
+
+        let (a, b) = (vec!["a".to_string()], vec!["b".to_string()]);
+        assert_eq!(
+            ProcessingStats::load(&file, &a),
+            ProcessingStats { trace_bytes: 3000, cpu_time_ms: 300 }
+        );
+        assert_eq!(
+            ProcessingStats::load(&file, &[]),
+            ProcessingStats { trace_bytes: 4000, cpu_time_ms: 400 }
+        );
+
+        // Reporting the stats of a tag keeps the others for the next report.
+        ProcessingStats::remove(&file, &a).unwrap();
+        assert_eq!(ProcessingStats::load(&file, &a), ProcessingStats::default());
+        assert_eq!(
+            ProcessingStats::load(&file, &b),
+            ProcessingStats { trace_bytes: 1000, cpu_time_ms: 100 }
+        );
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Summary of the processed profiles, so that developers can sanity-check the collected data on
+//! the device without pulling and post-processing a report. The profiles are merged into a text
+//! AutoFDO profile, and the binaries and functions executed the most are listed. Functions are
+//! found with the symbols left in the binaries on the device, e.g. the mini debug info of stripped
+//! binaries. Ranges without a symbol are listed by their offsets in the binary instead, which can
+//! be symbolized on the host against the unstripped binaries.
+
+use anyhow::Result;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs::{read_dir, read_to_string};
+use std::path::{Path, PathBuf};
+
+use crate::in_memory_trace::InMemoryTrace;
+use crate::trace_metadata::is_metadata;
+use crate::trace_provider::{is_bolt_profile, ProfileFormat, TraceProvider};
+
+/// Number of binaries, and of functions per binary, listed when none is given.
+pub const DEFAULT_SUMMARY_ENTRIES: usize = 10;
+
+/// Execution counts of the address ranges of a binary.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct BinaryCounts {
+    pub binary: String,
+    /// Start and end offsets of the ranges, and how many times they were executed.
+    pub ranges: Vec<(u64, u64, u64)>,
+}
+
+impl BinaryCounts {
+    fn total(&self) -> u64 {
+        self.ranges.iter().map(|(_, _, count)| count).sum()
+    }
+}
+
+/// Parse the range counts of a text AutoFDO profile, as written by simpleperf inject. Each binary
+/// starts with `// build_id: <id>` and `// <path>` comments, followed by the number of ranges and
+/// `<start>-<end>:<count>` lines in hex, then by address and branch counts, which are skipped.
+pub fn parse_autofdo(text: &str) -> Vec<BinaryCounts> {
+    let mut binaries: Vec<BinaryCounts> = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if let Some(comment) = line.strip_prefix("// ") {
+            if !comment.starts_with("build_id:") {
+                binaries.push(BinaryCounts { binary: comment.to_string(), ranges: Vec::new() });
+            }
+            continue;
+        }
+        let Some(binary) = binaries.last_mut() else { continue };
+        let Some((range, count)) = line.split_once(':') else { continue };
+        let Some((start, end)) = range.split_once('-').filter(|_| !range.contains("->")) else {
+            continue;
+        };
+        let hex = |s: &str| u64::from_str_radix(s.trim_start_matches("0x"), 16).ok();
+        if let (Some(start), Some(end), Ok(count)) = (hex(start), hex(end), count.parse()) {
+            binary.ranges.push((start, end, count));
+        }
+    }
+    binaries
+}
+
+/// Finds the names of the functions of a binary at offsets of its profile, None for the offsets
+/// without a symbol.
+pub type Symbolizer<'a> = &'a dyn Fn(&str, &[u64]) -> Vec<Option<String>>;
+
+/// Format the `max_entries` binaries executed the most, each with its `max_entries` functions
+/// executed the most, as a share of all executions. Ranges are attributed to the function
+/// `symbolize` finds at their start, and listed on their own if it finds none.
+pub fn format_summary(
+    binaries: &mut [BinaryCounts],
+    profiles: usize,
+    max_entries: usize,
+    symbolize: Symbolizer,
+) -> String {
+    let total: u64 = binaries.iter().map(BinaryCounts::total).sum();
+    let mut summary = format!("{} profiles, {} range executions.\n", profiles, total);
+    if total == 0 {
+        return summary;
+    }
+    let share = |count: u64| count as f64 * 100.0 / total as f64;
+    binaries.sort_by_key(|b| Reverse(b.total()));
+    for binary in binaries.iter().take(max_entries) {
+        writeln!(summary, "{:5.1}% {}", share(binary.total()), binary.binary).unwrap();
+        let starts: Vec<u64> = binary.ranges.iter().map(|(start, _, _)| *start).collect();
+        let mut functions: BTreeMap<String, u64> = BTreeMap::new();
+        for ((start, end, count), name) in
+            binary.ranges.iter().zip(symbolize(&binary.binary, &starts))
+        {
+            let function = name.unwrap_or_else(|| format!("{:#x}-{:#x}", start, end));
+            *functions.entry(function).or_insert(0) += count;
+        }
+        let mut functions: Vec<(String, u64)> = functions.into_iter().collect();
+        functions.sort_by_key(|(_, count)| Reverse(*count));
+        for (function, count) in functions.iter().take(max_entries) {
+            writeln!(summary, "    {:5.1}% {}", share(*count), function).unwrap();
+        }
+    }
+    summary
+}
+
+/// Summarize the processed profiles in `profile_dir` of the binaries matching `binary_filter`.
+pub fn summarize_profiles(
+    provider: &dyn TraceProvider,
+    profile_dir: &Path,
+    binary_filter: &str,
+    max_entries: usize,
+) -> Result<String> {
+    let profiles: Vec<PathBuf> = read_dir(profile_dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && !is_metadata(p) && !is_bolt_profile(p))
+        .collect();
+    if profiles.is_empty() {
+        return Ok("No processed profile.\n".to_string());
+    }
+    // Merge into memory, so that no file is left in the audited output directories.
+    let merged = InMemoryTrace::new()?;
+    provider.merge(&profiles, binary_filter, ProfileFormat::AutoFdo, merged.path())?;
+    let mut binaries = parse_autofdo(&read_to_string(merged.path())?);
+    let symbolize = |binary: &str, offsets: &[u64]| {
+        simpleperf_profcollect::symbolize_autofdo_offsets(Path::new(binary), offsets)
+    };
+    Ok(format_summary(&mut binaries, profiles.len(), max_entries, &symbolize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_summary, parse_autofdo, BinaryCounts};
+
+    fn unsymbolized(_binary: &str, offsets: &[u64]) -> Vec<Option<String>> {
+        vec![None; offsets.len()]
+    }
+
+    const AUTOFDO: &str = "\
+    // build_id: 0x1234
+    // /system/lib64/libc.so
+
+    2
+    1000-10f0:30
+    2000-2010:10
+    0
+    1
+    10f0->2000:5
+
+    // build_id: 0x5678
+    // /system/bin/surfaceflinger
+
+    1
+    400-480:60
+    0
+    0
+    ";
+
+    #[test]
+    fn parses_range_counts() {
+        let binaries = parse_autofdo(AUTOFDO);
+        assert_eq!(
+            binaries,
+            vec![
+                BinaryCounts {
+                    binary: "/system/lib64/libc.so".to_string(),
+                    ranges: vec![(0x1000, 0x10f0, 30), (0x2000, 0x2010, 10)],
+                },
+                BinaryCounts {
+                    binary: "/system/bin/surfaceflinger".to_string(),
+                    ranges: vec![(0x400, 0x480, 60)],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_most_executed_first() {
+        let mut binaries = parse_autofdo(AUTOFDO);
+        assert_eq!(
+            format_summary(&mut binaries, 3, 1, &unsymbolized),
+            concat!(
+                "3 profiles, 100 range executions.\n",
+                " 60.0% /system/bin/surfaceflinger\n",
+                "     60.0% 0x400-0x480\n",
+            )
+        );
+        assert_eq!(
+            format_summary(&mut [], 0, 1, &unsymbolized),
+            "0 profiles, 0 range executions.\n"
+        );
+    }
+
+    #[test]
+    fn attributes_ranges_to_functions() {
+        let mut binaries = vec![BinaryCounts {
+            binary: "/system/lib64/libc.so".to_string(),
+            ranges: vec![(0x1000, 0x1010, 20), (0x1020, 0x1030, 15), (0x2000, 0x2010, 25)],
+        }];
+        // Both ranges of memcpy together are executed more than the unknown range.
+        let symbolize = |binary: &str, offsets: &[u64]| {
+            assert_eq!(binary, "/system/lib64/libc.so");
+            offsets.iter().map(|&o| (o < 0x2000).then(|| "memcpy".to_string())).collect()
+        };
+        assert_eq!(
+            format_summary(&mut binaries, 1, 2, &symbolize),
+            concat!(
+                "1 profiles, 60 range executions.\n",
+                "100.0% /system/lib64/libc.so\n",
+                "     58.3% memcpy\n",
+                "     41.7% 0x2000-0x2010\n",
+            )
+        );
+    }
+}
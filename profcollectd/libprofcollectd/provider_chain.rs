@@ -0,0 +1,190 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Fallback chain of trace providers. The first provider of the chain which the device supports,
+//! and which has not failed on it, is used. Once the active provider fails too many traces in a
+//! row, it is marked failed and the next provider of the chain takes over. Collection is disabled
+//! once no provider is left. Failed providers are forgotten on config changes, including OTAs.
+
+use anyhow::Result;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use crate::config::{get_failed_providers, set_failed_providers, Config};
+use crate::provider_error::{ProviderError, ProviderErrorKind};
+
+pub struct ProviderChain {
+    /// Provider names, in order of preference.
+    rungs: Vec<String>,
+    /// Providers of the chain which failed on this device.
+    failed: Mutex<Vec<String>>,
+    /// The provider in use, None if it is not part of the chain.
+    active: Mutex<Option<String>>,
+    failures_before_downgrade: u32,
+    /// Traces failed in a row by the active provider.
+    consecutive_failures: AtomicU32,
+}
+
+impl ProviderChain {
+    pub fn load(config: &Config) -> Self {
+        ProviderChain {
+            rungs: config.provider_chain.clone(),
+            failed: Mutex::new(get_failed_providers()),
+            active: Mutex::new(None),
+            failures_before_downgrade: config.provider_failures_before_downgrade,
+            consecutive_failures: AtomicU32::new(0),
+        }
+    }
+
+    /// Providers to try, in order, skipping the failed ones.
+    pub fn candidates(&self) -> Vec<String> {
+        let failed = self.failed.lock().unwrap();
+        self.rungs.iter().filter(|r| !failed.contains(r)).cloned().collect()
+    }
+
+    /// Make `provider` the active one, or none of the chain if None.
+    pub fn set_active(&self, provider: Option<&str>) {
+        let rung = provider.and_then(|p| self.rungs.iter().position(|r| r == p));
+        if let (Some(provider), Some(rung)) = (provider, rung) {
+            log::info!(
+                "Provider chain at rung {} of {}: {}.",
+                rung + 1,
+                self.rungs.len(),
+                provider
+            );
+        }
+        *self.active.lock().unwrap() = rung.map(|r| self.rungs[r].clone());
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+    }
+
+    /// Position of the active provider in the chain, 1 for the preferred one. None if the provider
+    /// in use is not part of the chain.
+    pub fn active_rung(&self) -> Option<u32> {
+        let active = self.active.lock().unwrap();
+        let rung = self.rungs.iter().position(|r| Some(r) == active.as_ref())?;
+        Some(rung as u32 + 1)
+    }
+
+    /// Record the outcome of a trace of the active provider. Returns why the provider failed if it
+    /// just did, in which case it is marked failed and the next one should be picked. Only the
+    /// failures categorized as permanent are counted: the tracing hardware being busy or memory
+    /// being short may clear up on their own, and uncategorized failures may not be the fault of
+    /// the provider.
+    pub fn record(&self, result: &Result<Box<Path>>) -> Option<String> {
+        let active = self.active.lock().unwrap().clone()?;
+        match result {
+            Ok(_) => {
+                self.consecutive_failures.store(0, Ordering::Relaxed);
+                return None;
+            }
+            Err(e) => match e.downcast_ref::<ProviderError>() {
+                Some(e) if !e.kind.is_transient() && e.kind != ProviderErrorKind::Other => (),
+                _ => return None,
+            },
+        }
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.failures_before_downgrade == 0 || failures != self.failures_before_downgrade {
+            return None;
+        }
+        let mut failed = self.failed.lock().unwrap();
+        failed.push(active.clone());
+        if let Err(e) = set_failed_providers(&failed) {
+            log::error!("Failed to mark provider {} failed: {:?}", active, e);
+        }
+        Some(format!("provider {} failed {} traces in a row", active, failures))
+    }
+
+    pub fn status(&self) -> String {
+        if self.rungs.is_empty() {
+            return String::new();
+        }
+        let failed = self.failed.lock().unwrap();
+        let active = self.active.lock().unwrap();
+        let rungs: Vec<String> = self
+            .rungs
+            .iter()
+            .map(|r| match r {
+                r if failed.contains(r) => format!("{} (failed)", r),
+                r if active.as_ref() == Some(r) => format!("{} (active)", r),
+                r => r.clone(),
+            })
+            .collect();
+        format!("provider chain: {}\n", rungs.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProviderChain;
+    use crate::provider_error::{ProviderError, ProviderErrorKind};
+    use anyhow::Result;
+    use std::path::Path;
+    use std::sync::atomic::AtomicU32;
+    use std::sync::Mutex;
+
+    fn chain(failures_before_downgrade: u32) -> ProviderChain {
+        let chain = ProviderChain {
+            rungs: vec!["simpleperf_etm".to_string(), "simpleperf_lbr".to_string()],
+            failed: Mutex::new(Vec::new()),
+            active: Mutex::new(None),
+            failures_before_downgrade,
+            consecutive_failures: AtomicU32::new(0),
+        };
+        chain.set_active(Some("simpleperf_etm"));
+        chain
+    }
+
+    fn failure(kind: ProviderErrorKind) -> Result<Box<Path>> {
+        Err(ProviderError { kind, message: String::new() }.into())
+    }
+
+    #[test]
+    fn only_counts_permanent_failures() {
+        let chain = chain(2);
+        assert_eq!(chain.record(&failure(ProviderErrorKind::Permission)), None);
+        assert_eq!(chain.record(&failure(ProviderErrorKind::Other)), None);
+        assert_eq!(chain.record(&failure(ProviderErrorKind::HardwareBusy)), None);
+        assert_eq!(chain.record(&Err(anyhow::anyhow!("not a provider error"))), None);
+        assert!(chain.record(&failure(ProviderErrorKind::Permission)).is_some());
+        assert_eq!(chain.candidates(), vec!["simpleperf_lbr".to_string()]);
+    }
+
+    #[test]
+    fn success_resets_failure_count() {
+        let chain = chain(2);
+        assert_eq!(chain.record(&failure(ProviderErrorKind::Decode)), None);
+        assert_eq!(chain.record(&Ok(Path::new("trace").into())), None);
+        assert_eq!(chain.record(&failure(ProviderErrorKind::Decode)), None);
+        assert_eq!(chain.candidates().len(), 2);
+    }
+
+    #[test]
+    fn status_shows_active_rung() {
+        let chain = chain(1);
+        assert_eq!(chain.active_rung(), Some(1));
+        assert_eq!(chain.status(), "provider chain: simpleperf_etm (active), simpleperf_lbr\n");
+        chain.record(&failure(ProviderErrorKind::Permission));
+        chain.set_active(Some("simpleperf_lbr"));
+        assert_eq!(chain.active_rung(), Some(2));
+        assert_eq!(
+            chain.status(),
+            "provider chain: simpleperf_etm (failed), simpleperf_lbr (active)\n"
+        );
+        chain.set_active(None);
+        assert_eq!(chain.active_rung(), None);
+    }
+}
@@ -0,0 +1,243 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Conformance suite of trace providers, so that a new provider can be validated against the
+//! expectations of the scheduler before it is registered in `get_trace_provider`. The registered
+//! providers the device supports are checked by `check_supported_providers`.
+
+use anyhow::{bail, ensure, Context, Result};
+use std::fs::{create_dir_all, read_dir, remove_dir_all, remove_file};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex, TryLockError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::in_memory_trace::InMemoryTrace;
+use crate::logging_trace_provider::LoggingTraceProvider;
+use crate::simpleperf_etm_trace_provider::SimpleperfEtmTraceProvider;
+use crate::simpleperf_lbr_trace_provider::SimpleperfLbrTraceProvider;
+use crate::storage::get_tag;
+use crate::trace_metadata::{is_metadata, new_correlation_id};
+use crate::trace_provider::{ProfileFormat, TraceProvider};
+
+const TAG: &str = "conformance";
+const SAMPLING_PERIOD: Duration = Duration::from_millis(200);
+/// How long a trace may last past its sampling period, to start and stop the provider.
+const TIMEOUT_SLACK: Duration = Duration::from_secs(5);
+
+/// Run the conformance checks against each provider of `get_trace_provider` supported by the
+/// device, using subdirectories of `work_dir`. Returns the names of the checked providers, or the
+/// first failed check.
+pub fn check_supported_providers(work_dir: &Path) -> Result<Vec<&'static str>> {
+    let mut providers: Vec<Arc<Mutex<dyn TraceProvider + Send>>> = Vec::new();
+    if SimpleperfEtmTraceProvider::supported() {
+        providers.push(Arc::new(Mutex::new(SimpleperfEtmTraceProvider::new())));
+    }
+    if SimpleperfLbrTraceProvider::supported() {
+        providers.push(Arc::new(Mutex::new(SimpleperfLbrTraceProvider::new())));
+    }
+    if LoggingTraceProvider::supported() {
+        providers.push(Arc::new(Mutex::new(LoggingTraceProvider {})));
+    }
+    providers
+        .into_iter()
+        .map(|provider| {
+            let name = provider.lock().unwrap().get_name();
+            check_provider(provider, &work_dir.join(name))
+                .with_context(|| format!("Provider {} does not conform.", name))?;
+            Ok(name)
+        })
+        .collect()
+}
+
+/// Run the conformance checks against `provider`, using `work_dir` as the trace directory. The
+/// directory is created, and removed afterwards. Returns the first failed check.
+pub fn check_provider(
+    provider: Arc<Mutex<dyn TraceProvider + Send>>,
+    work_dir: &Path,
+) -> Result<()> {
+    create_dir_all(work_dir)?;
+    let result = run_checks(provider, work_dir);
+    remove_dir_all(work_dir)?;
+    result
+}
+
+fn run_checks(provider: Arc<Mutex<dyn TraceProvider + Send>>, work_dir: &Path) -> Result<()> {
+    check_name(provider.lock().unwrap().get_name()).context("Check name failed.")?;
+    check_ready(&*provider.lock().unwrap()).context("Check is_ready failed.")?;
+    let processes = std::process::id().to_string();
+    check_trace(&*provider.lock().unwrap(), work_dir, &|p, dir, id, in_memory| {
+        p.trace_system(dir, TAG, id, in_memory, &SAMPLING_PERIOD, ".*")
+    })
+    .context("Check trace_system failed.")?;
+    check_trace(&*provider.lock().unwrap(), work_dir, &|p, dir, id, in_memory| {
+        p.trace_process(dir, TAG, id, in_memory, &SAMPLING_PERIOD, &processes)
+    })
+    .context("Check trace_process failed.")?;
+    check_process(&*provider.lock().unwrap(), work_dir).context("Check process failed.")?;
+    check_holds_provider(provider, work_dir).context("Check concurrent request failed.")?;
+    Ok(())
+}
+
+type TraceFn<'a> =
+    dyn Fn(&dyn TraceProvider, &Path, &str, Option<&InMemoryTrace>) -> Result<Box<Path>> + 'a;
+
+/// The name is used as the directory of the traces and profiles of the provider.
+fn check_name(name: &str) -> Result<()> {
+    ensure!(
+        !name.is_empty()
+            && name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_'),
+        "Name {:?} is not a valid directory name.",
+        name
+    );
+    Ok(())
+}
+
+/// A supported provider is ready to trace once the scheduler starts, the scheduler only waits for
+/// supported providers which are not ready yet at boot.
+fn check_ready(provider: &dyn TraceProvider) -> Result<()> {
+    let start = Instant::now();
+    while !provider.is_ready() {
+        ensure!(start.elapsed() <= TIMEOUT_SLACK, "Not ready after {:?}.", TIMEOUT_SLACK);
+        thread::sleep(Duration::from_millis(100));
+    }
+    Ok(())
+}
+
+/// A trace starts when requested and stops at the end of its sampling period, or times out
+/// within the slack, and is named after its tag and correlation ID in the trace directory. A trace
+/// recorded in memory is not written to the trace directory.
+fn check_trace(provider: &dyn TraceProvider, dir: &Path, trace: &TraceFn) -> Result<()> {
+    let id = new_correlation_id();
+    let start = Instant::now();
+    let trace_file = trace(provider, dir, &id, None)?;
+    let elapsed = start.elapsed();
+    ensure!(
+        elapsed >= SAMPLING_PERIOD,
+        "Trace stopped after {:?}, before its sampling period of {:?}.",
+        elapsed,
+        SAMPLING_PERIOD
+    );
+    ensure!(
+        elapsed <= SAMPLING_PERIOD + TIMEOUT_SLACK,
+        "Trace timed out after {:?}, for a sampling period of {:?}.",
+        elapsed,
+        SAMPLING_PERIOD
+    );
+    ensure!(
+        trace_file.parent() == Some(dir),
+        "{} is not in {}.",
+        trace_file.display(),
+        dir.display()
+    );
+    ensure!(trace_file.is_file(), "{} was not written.", trace_file.display());
+    ensure!(!is_metadata(&trace_file), "{} is named as metadata.", trace_file.display());
+    ensure!(
+        get_tag(&trace_file) == Some(TAG),
+        "{} is not named after its tag.",
+        trace_file.display()
+    );
+    ensure!(
+        trace_file.to_string_lossy().contains(&id),
+        "{} is not named after its correlation ID.",
+        trace_file.display()
+    );
+    if let Some(data_loss) = provider.get_data_loss(&trace_file) {
+        ensure!(
+            (0.0..=100.0).contains(&data_loss),
+            "Data loss of {}% is not a percentage.",
+            data_loss
+        );
+    }
+
+    remove_file(&trace_file)?;
+
+    let in_memory = InMemoryTrace::new()?;
+    let trace_file = trace(provider, dir, &new_correlation_id(), Some(&in_memory))?;
+    ensure!(!trace_file.exists(), "{} was written, instead of in memory.", trace_file.display());
+    Ok(())
+}
+
+/// Processing consumes the traces of the provider in the trace directory, and names the profiles
+/// after their traces, with the extension of their format.
+fn check_process(provider: &dyn TraceProvider, dir: &Path) -> Result<()> {
+    let profile_dir = dir.join("profiles");
+    create_dir_all(&profile_dir)?;
+    let trace_file =
+        provider.trace_system(dir, TAG, &new_correlation_id(), None, &SAMPLING_PERIOD, ".*")?;
+    let format = ProfileFormat::BranchList;
+    provider.process(dir, &profile_dir, ".*", false, &[format])?;
+    ensure!(!trace_file.exists(), "{} was not consumed.", trace_file.display());
+    let expected =
+        profile_dir.join(trace_file.file_name().unwrap()).with_extension(format.extension());
+    let profiles: Vec<PathBuf> =
+        read_dir(&profile_dir)?.map(|e| Ok(e?.path())).collect::<Result<_>>()?;
+    for profile in &profiles {
+        ensure!(*profile == expected, "Unexpected profile {}.", profile.display());
+    }
+    remove_dir_all(&profile_dir)?;
+    Ok(())
+}
+
+/// The provider is held for the whole trace, so that concurrent trace requests which do not wait
+/// are rejected until it ends.
+fn check_holds_provider(provider: Arc<Mutex<dyn TraceProvider + Send>>, dir: &Path) -> Result<()> {
+    let tracing = provider.clone();
+    let dir = dir.to_path_buf();
+    let (started_tx, started_rx) = channel();
+    let trace = thread::spawn(move || {
+        let p = tracing.lock().unwrap();
+        started_tx.send(()).unwrap();
+        p.trace_system(&dir, TAG, &new_correlation_id(), None, &SAMPLING_PERIOD, ".*")
+    });
+    started_rx.recv()?;
+    thread::sleep(SAMPLING_PERIOD / 2);
+    let held = matches!(provider.try_lock(), Err(TryLockError::WouldBlock));
+    match trace.join() {
+        Ok(result) => result?,
+        Err(_) => bail!("Trace panicked."),
+    };
+    ensure!(held, "The trace ended before its sampling period.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::sync::{Arc, Mutex};
+
+    use super::{check_provider, check_supported_providers};
+    use crate::logging_trace_provider::LoggingTraceProvider;
+    use crate::trace_metadata::new_correlation_id;
+
+    #[test]
+    fn logging_provider_conforms() {
+        let work_dir =
+            temp_dir().join(format!("profcollectd_conformance_{}", new_correlation_id()));
+        check_provider(Arc::new(Mutex::new(LoggingTraceProvider {})), &work_dir).unwrap();
+    }
+
+    // The providers of the tracing hardware are only checked on devices which have it.
+    #[test]
+    fn supported_providers_conform() {
+        let work_dir =
+            temp_dir().join(format!("profcollectd_conformance_{}", new_correlation_id()));
+        let checked = check_supported_providers(&work_dir).unwrap();
+        assert!(checked.contains(&"logging"), "{:?}", checked);
+        std::fs::remove_dir_all(work_dir).unwrap();
+    }
+}
@@ -0,0 +1,217 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Categories of trace provider failures. simpleperf runs in process and only reports whether a
+//! command succeeded, so failures are categorized from what it logged meanwhile, and retries and
+//! status handle them by kind instead of by message. The log of each command is captured whether
+//! or not the trace log is kept.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+
+/// Kind of a provider failure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ProviderErrorKind {
+    /// The perf events or the tracing devices could not be opened.
+    Permission,
+    /// The tracing hardware is used by another tracer.
+    HardwareBusy,
+    /// A trace could not be decoded into a profile.
+    Decode,
+    /// Buffers could not be allocated.
+    OutOfMemory,
+    Other,
+}
+
+impl ProviderErrorKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProviderErrorKind::Permission => "permission",
+            ProviderErrorKind::HardwareBusy => "hardware_busy",
+            ProviderErrorKind::Decode => "decode",
+            ProviderErrorKind::OutOfMemory => "out_of_memory",
+            ProviderErrorKind::Other => "other",
+        }
+    }
+
+    /// Whether the failure may not happen again if the trace is retried later.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, ProviderErrorKind::HardwareBusy | ProviderErrorKind::OutOfMemory)
+    }
+}
+
+/// Log messages of simpleperf, by the kind of failure they denote. The first match wins.
+const PATTERNS: &[(ProviderErrorKind, &[&str])] = &[
+    (ProviderErrorKind::Permission, &["Permission denied", "Operation not permitted", "paranoid"]),
+    (ProviderErrorKind::HardwareBusy, &["Device or resource busy", "is busy"]),
+    (ProviderErrorKind::OutOfMemory, &["Cannot allocate memory", "out of memory", "Out of memory"]),
+    (ProviderErrorKind::Decode, &["decode", "Decode"]),
+];
+
+/// Categorize a failure from the simpleperf log `output`.
+pub fn classify(output: &str) -> ProviderErrorKind {
+    PATTERNS
+        .iter()
+        .find(|(_, patterns)| patterns.iter().any(|p| output.contains(p)))
+        .map_or(ProviderErrorKind::Other, |(kind, _)| *kind)
+}
+
+/// A failed simpleperf command.
+#[derive(Debug)]
+pub struct ProviderError {
+    pub kind: ProviderErrorKind,
+    /// The last line simpleperf logged, if any.
+    pub message: String,
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.kind.name())
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+thread_local! {
+    /// Logs of the simpleperf commands run on this thread, and how many bytes of them are kept,
+    /// while `collect_logs` runs.
+    static COLLECTED_LOGS: RefCell<Option<(String, usize)>> = const { RefCell::new(None) };
+}
+
+/// Run the simpleperf `command` through `run`. Its failure is categorized from what simpleperf
+/// logged meanwhile.
+pub fn run_simpleperf(command: &str, run: impl FnOnce() -> bool) -> Result<(), ProviderError> {
+    check_command(command, simpleperf_profcollect::run_capturing_log(run))
+}
+
+/// Check the result of `command` given with its captured `log`, see `run_simpleperf`.
+fn check_command(command: &str, (succeeded, log): (bool, String)) -> Result<(), ProviderError> {
+    COLLECTED_LOGS.with(|logs| {
+        if let Some((collected, max_bytes)) = logs.borrow_mut().as_mut() {
+            let room = max_bytes.saturating_sub(collected.len());
+            let end = (0..=room.min(log.len())).rev().find(|&i| log.is_char_boundary(i));
+            collected.push_str(&log[..end.unwrap_or(0)]);
+        }
+    });
+    if succeeded {
+        return Ok(());
+    }
+    let last_line = log.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("no log");
+    Err(ProviderError {
+        kind: classify(&log),
+        message: format!("simpleperf {} failed: {}", command, last_line.trim()),
+    })
+}
+
+/// Run `f`, collecting up to `max_bytes` of the logs of the simpleperf commands it runs on this
+/// thread.
+pub fn collect_logs<T>(max_bytes: usize, f: impl FnOnce() -> T) -> (T, String) {
+    COLLECTED_LOGS.with(|logs| *logs.borrow_mut() = Some((String::new(), max_bytes)));
+    let result = f();
+    let logs = COLLECTED_LOGS.with(|logs| logs.borrow_mut().take());
+    (result, logs.map(|(collected, _)| collected).unwrap_or_default())
+}
+
+/// Count of the provider failures by kind, listed in the status.
+#[derive(Default)]
+pub struct ProviderErrors {
+    counts: Mutex<BTreeMap<ProviderErrorKind, u32>>,
+}
+
+impl ProviderErrors {
+    pub fn record(&self, kind: ProviderErrorKind) {
+        *self.counts.lock().unwrap().entry(kind).or_insert(0) += 1;
+    }
+
+    pub fn status(&self) -> String {
+        let counts = self.counts.lock().unwrap();
+        if counts.is_empty() {
+            return String::new();
+        }
+        let counts: Vec<String> =
+            counts.iter().map(|(kind, count)| format!("{} {}", kind.name(), count)).collect();
+        format!("provider errors: {}\n", counts.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_command, classify, collect_logs, ProviderErrorKind, ProviderErrors};
+
+    #[test]
+    fn classifies_simpleperf_log() {
+        let cases = [
+            ("failed to open perf event file: Permission denied", ProviderErrorKind::Permission),
+            ("perf_event_paranoid is 3, access is denied", ProviderErrorKind::Permission),
+            ("failed to open ETM device: Device or resource busy", ProviderErrorKind::HardwareBusy),
+            ("failed to mmap aux buffer: Cannot allocate memory", ProviderErrorKind::OutOfMemory),
+            ("failed to decode ETM data at offset 0x10", ProviderErrorKind::Decode),
+            ("unexpected end of record file", ProviderErrorKind::Other),
+            ("", ProviderErrorKind::Other),
+        ];
+        for (output, kind) in cases {
+            assert_eq!(classify(output), kind, "{}", output);
+        }
+    }
+
+    #[test]
+    fn only_busy_and_out_of_memory_are_transient() {
+        assert!(ProviderErrorKind::HardwareBusy.is_transient());
+        assert!(ProviderErrorKind::OutOfMemory.is_transient());
+        assert!(!ProviderErrorKind::Permission.is_transient());
+        assert!(!ProviderErrorKind::Decode.is_transient());
+        assert!(!ProviderErrorKind::Other.is_transient());
+    }
+
+    #[test]
+    fn categorizes_failures_from_command_log() {
+        assert!(check_command("record", (true, "W failed, but succeeded\n".to_string())).is_ok());
+        let log = "I recording\nE failed to open ETM device: Device or resource busy\n\n";
+        let e = check_command("record", (false, log.to_string())).unwrap_err();
+        assert_eq!(e.kind, ProviderErrorKind::HardwareBusy);
+        assert_eq!(
+            e.message,
+            "simpleperf record failed: E failed to open ETM device: Device or resource busy"
+        );
+        let e = check_command("inject", (false, String::new())).unwrap_err();
+        assert_eq!(e.kind, ProviderErrorKind::Other);
+        assert_eq!(e.message, "simpleperf inject failed: no log");
+    }
+
+    #[test]
+    fn collects_command_logs_up_to_limit() {
+        let ((), logs) = collect_logs(10, || {
+            check_command("inject", (true, "0123456\n".to_string())).unwrap();
+            check_command("inject", (false, "789abc\n".to_string())).unwrap_err();
+        });
+        assert_eq!(logs, "0123456\n78");
+        // Nothing is collected outside of collect_logs.
+        check_command("inject", (true, "0123456\n".to_string())).unwrap();
+        assert_eq!(collect_logs(10, || ()).1, "");
+    }
+
+    #[test]
+    fn status_counts_errors_by_kind() {
+        let errors = ProviderErrors::default();
+        assert_eq!(errors.status(), "");
+        errors.record(ProviderErrorKind::Permission);
+        errors.record(ProviderErrorKind::HardwareBusy);
+        errors.record(ProviderErrorKind::HardwareBusy);
+        assert_eq!(errors.status(), "provider errors: permission 1, hardware_busy 2\n");
+    }
+}
@@ -16,33 +16,265 @@
 
 //! Pack profiles into reports.
 
-use anyhow::{anyhow, Result};
-use macaddr::MacAddr6;
+use anyhow::{anyhow, bail, ensure, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
 use std::fs::{self, File, Permissions};
-use std::io::{Read, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 use std::time::{Duration, SystemTime};
-use uuid::v1::{Context, Timestamp};
 use uuid::Uuid;
 use zip::write::FileOptions;
 use zip::CompressionMethod::Deflated;
 use zip::ZipWriter;
 
-use crate::config::{clear_processed_files, Config};
+use crate::apex::ApexVersions;
+use crate::config::{
+    clear_processed_files, get_or_create_install_id, get_or_rotate_device_salt, salted_digest,
+    Config, DeviceIdentifiers, CONFIG_FILE, LOG_FILE, PROCESSING_STATS_FILE, REPORT_RETENTION_SECS,
+    RESOLUTION_CACHE_FILE,
+};
+use crate::eviction::{evict, Candidate, Limits};
+use crate::fault_injection::{inject_fault, FaultPoint};
+use crate::kernel_addresses::ScrubDecision;
+use crate::processing::ProcessingStats;
+use crate::storage::get_tag;
+use crate::trace_metadata::{is_metadata, to_rfc3339, Annotations, TraceMetadata};
+use crate::trace_provider::is_bolt_profile;
 
 pub const NO_USAGE_SETTING: i32 = -1;
 
-pub static UUID_CONTEXT: Context = Context::new(0);
+static MANIFEST_FILE: &str = "manifest.json";
+/// Directory of the report the BOLT profiles are packed under.
+static BOLT_SECTION: &str = "bolt";
+
+/// Maximum number of bytes returned by `read_report_chunk`, to stay well under the binder
+/// transaction limit.
+pub const MAX_REPORT_CHUNK_BYTES: usize = 256 * 1024;
+
+/// Message of the salt digest, see `salted_digest`.
+const SALT_DIGEST_MESSAGE: &[u8] = b"profcollect salt digest";
+
+/// Manifest describing a report, packed into the report as manifest.json.
+#[derive(Serialize)]
+struct ReportManifest {
+    /// Version of the manifest scheme, always equals to 1.
+    version: u32,
+    report_id: String,
+    /// Creation time of the report, in RFC 3339 UTC.
+    created_at: String,
+    /// Digest keyed with the device salt, see `salted_digest`. It is the same for all reports from
+    /// the device until the salt is rotated.
+    salt_digest: String,
+    /// Digest of the report ID keyed with the device salt, tying the report to the salt.
+    salted_report_id: String,
+    /// Random ID of this install of profcollect, the same for all reports until it is reset, see
+    /// `get_or_create_install_id`.
+    install_id: String,
+    /// Annotations of the profiles in the report, by profile path without extension.
+    annotations: BTreeMap<String, Annotations>,
+    /// Correlation IDs of the trace requests behind the profiles in the report, by profile path
+    /// without extension. The first ID is the one of the trace, the others of the requests merged
+    /// into it.
+    correlation_ids: BTreeMap<String, Vec<String>>,
+    /// Position in the provider chain of the provider behind the profiles in the report, by
+    /// profile path without extension, 1 for the preferred provider. Profiles of providers outside
+    /// of the chain are left out.
+    provider_rungs: BTreeMap<String, u32>,
+    /// Tags the report is scoped to, empty if it holds the profiles of all tags.
+    tags: Vec<String>,
+    /// Uncompressed bytes of the profiles and metadata in the report, by tag.
+    bytes_by_tag: BTreeMap<String, u64>,
+    /// Profiles in the report, grouped by the APEX versions they were collected with. Profiles of
+    /// different groups must not be merged.
+    apex_version_groups: Vec<ApexVersionGroup>,
+    /// Cost of processing the traces of the tags in the report, of all tags if not scoped.
+    processing_stats: ProcessingStats,
+    /// Megabytes of traces processed per second of CPU time, None if unknown.
+    processing_mb_per_cpu_sec: Option<f64>,
+    /// Whether raw kernel addresses were scrubbed from the profiles in the report, as decided when
+    /// they were processed, by profile path without extension.
+    kernel_addresses: BTreeMap<String, ScrubDecision>,
+    /// Whether profiles over the report size limit by themselves were left out, see `Selection`.
+    truncated: bool,
+    /// Whether profiles were kept for the next report to stay under the report size limit.
+    split: bool,
+}
+
+/// Profiles collected with the same APEX versions.
+#[derive(Serialize)]
+struct ApexVersionGroup {
+    apex_versions: ApexVersions,
+    profiles: Vec<String>,
+}
+
+/// The identifiers recorded in report manifests.
+pub struct ReportIdentifiers {
+    salt: String,
+    install_id: String,
+}
+
+impl ReportIdentifiers {
+    /// The identifiers of the device, rotating the salt when due, see `get_or_rotate_device_salt`.
+    pub fn of_device(config: &Config) -> Result<Self> {
+        Ok(ReportIdentifiers {
+            salt: get_or_rotate_device_salt(&DeviceIdentifiers, config.salt_rotation_period)?,
+            install_id: get_or_create_install_id(&DeviceIdentifiers)?,
+        })
+    }
+
+    /// New random identifiers, neither persisted nor tied to the device, e.g. for benchmarks.
+    #[cfg(any(test, feature = "benchmark"))]
+    pub fn scratch() -> Self {
+        use crate::config::new_random_id;
+        ReportIdentifiers { salt: new_random_id(), install_id: new_random_id() }
+    }
+}
+
+impl ReportManifest {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        report_id: &str,
+        identifiers: &ReportIdentifiers,
+        annotations: BTreeMap<String, Annotations>,
+        correlation_ids: BTreeMap<String, Vec<String>>,
+        provider_rungs: BTreeMap<String, u32>,
+        kernel_addresses: BTreeMap<String, ScrubDecision>,
+        tags: &[String],
+        bytes_by_tag: BTreeMap<String, u64>,
+        apex_version_groups: Vec<ApexVersionGroup>,
+        selection: &Selection,
+    ) -> Self {
+        let salt = &identifiers.salt;
+        let processing_stats = ProcessingStats::load(&PROCESSING_STATS_FILE, tags);
+        ReportManifest {
+            version: 1,
+            report_id: report_id.to_string(),
+            created_at: to_rfc3339(SystemTime::now()),
+            salt_digest: salted_digest(salt, SALT_DIGEST_MESSAGE),
+            salted_report_id: salted_digest(salt, report_id.as_bytes()),
+            install_id: identifiers.install_id.clone(),
+            annotations,
+            correlation_ids,
+            provider_rungs,
+            tags: tags.to_vec(),
+            bytes_by_tag,
+            apex_version_groups,
+            processing_stats,
+            processing_mb_per_cpu_sec: processing_stats.throughput(),
+            kernel_addresses,
+            truncated: !selection.oversized.is_empty(),
+            split: selection.split,
+        }
+    }
+}
+
+/// A packed report.
+pub struct ReportInfo {
+    pub name: String,
+    /// Size of the report file in bytes.
+    pub size: u64,
+    /// Number of profiles packed into the report, not counting metadata and logs.
+    pub profile_count: u32,
+    /// Whether profiles were left out of the report and deleted, as each is over the report size
+    /// limit by itself.
+    pub truncated: bool,
+    /// Whether profiles were kept for the next report, as they did not fit under the report size
+    /// limit.
+    pub split: bool,
+}
+
+/// The profile files packed into a report of at most `max_report_size_mb`.
+struct Selection {
+    packed: Vec<PathBuf>,
+    /// Files of the profiles over the limit by themselves, which never fit in a report.
+    oversized: Vec<PathBuf>,
+    /// Whether profiles were kept for the next report.
+    split: bool,
+}
+
+impl Selection {
+    /// Select the profiles of `files` to pack into a report of at most `max_bytes`, 0 for no
+    /// limit. Each profile is packed whole, with its metadata and BOLT profile, and the oldest
+    /// first, as profile names start with their timestamp.
+    fn new(files: Vec<PathBuf>, max_bytes: u64) -> Result<Self> {
+        let mut profiles: BTreeMap<(OsString, PathBuf), (Vec<PathBuf>, u64)> = BTreeMap::new();
+        for file in files {
+            let size = fs::metadata(&file)?.len();
+            let stem = file.with_extension("");
+            let name = stem.file_name().unwrap_or_default().to_os_string();
+            let (files, bytes) = profiles.entry((name, stem)).or_default();
+            files.push(file);
+            *bytes += size;
+        }
+
+        let mut selection = Selection { packed: Vec::new(), oversized: Vec::new(), split: false };
+        let mut packed_bytes = 0;
+        for ((_, stem), (files, bytes)) in profiles {
+            if max_bytes > 0 && bytes > max_bytes {
+                log::error!("{} is over the report size limit, leaving it out.", stem.display());
+                selection.oversized.extend(files);
+            } else if selection.split || (max_bytes > 0 && packed_bytes + bytes > max_bytes) {
+                selection.split = true;
+            } else {
+                packed_bytes += bytes;
+                selection.packed.extend(files);
+            }
+        }
+        Ok(selection)
+    }
+}
 
 pub fn pack_report(
     profile: &Path,
     report: &Path,
     config: &Config,
     usage_setting: i32,
-) -> Result<String> {
+) -> Result<ReportInfo> {
+    let identifiers = ReportIdentifiers::of_device(config)?;
+    pack(profile, report, &identifiers, usage_setting, &[], config.max_report_size_mb)
+}
+
+/// Pack all profiles into a report recording `identifiers` instead of those of the device.
+#[cfg(any(test, feature = "benchmark"))]
+pub fn pack_report_with_identifiers(
+    profile: &Path,
+    report: &Path,
+    identifiers: &ReportIdentifiers,
+) -> Result<ReportInfo> {
+    pack(profile, report, identifiers, NO_USAGE_SETTING, &[], 0)
+}
+
+/// Pack only the profiles of `tags` into a separate report, e.g. for the owners of an experiment.
+/// The profiles of the other tags, and the trace log, are kept for the next report.
+pub fn pack_report_for_tags(
+    profile: &Path,
+    report: &Path,
+    config: &Config,
+    tags: &[String],
+) -> Result<ReportInfo> {
+    if tags.is_empty() {
+        bail!("No tag to report.");
+    }
+    let identifiers = ReportIdentifiers::of_device(config)?;
+    pack(profile, report, &identifiers, NO_USAGE_SETTING, tags, config.max_report_size_mb)
+}
+
+/// Pack the profiles of `tags`, or all profiles if empty, into a report of at most
+/// `max_report_size_mb` of profiles, 0 to not limit.
+fn pack(
+    profile: &Path,
+    report: &Path,
+    identifiers: &ReportIdentifiers,
+    usage_setting: i32,
+    tags: &[String],
+    max_report_size_mb: u64,
+) -> Result<ReportInfo> {
+    inject_fault(FaultPoint::Packing)?;
     let mut report = PathBuf::from(report);
-    let report_filename = get_report_filename(&config.node_id)?;
+    let report_filename = Uuid::new_v4().to_string();
     report.push(&report_filename);
     report.set_extension("zip");
 
@@ -57,49 +289,379 @@ pub fn pack_report(
 
     let options = FileOptions::default().compression_method(Deflated);
     let mut zip = ZipWriter::new(report_file);
+    let mut profile_count = 0;
+    let mut annotations = BTreeMap::new();
+    let mut correlation_ids = BTreeMap::new();
+    let mut provider_rungs = BTreeMap::new();
+    let mut kernel_addresses = BTreeMap::new();
+    let mut bytes_by_tag = BTreeMap::new();
+    let mut apex_version_groups: Vec<ApexVersionGroup> = Vec::new();
 
-    fs::read_dir(profile)?
-        .filter_map(|e| e.ok())
-        .map(|e| e.path())
-        .filter(|e| e.is_file())
-        .try_for_each(|e| -> Result<()> {
-            let filename = e
-                .file_name()
-                .and_then(|f| f.to_str())
-                .ok_or_else(|| anyhow!("Malformed profile path: {}", e.display()))?;
+    // Profiles are packed under the directory of their provider, as in the profile directory, and
+    // BOLT profiles in a separate bolt/ section. The processing stats are recorded in the manifest
+    // instead, and the resolution cache is local to the device.
+    let in_tags = |e: &PathBuf| {
+        tags.is_empty() || get_tag(e).is_some_and(|tag| tags.iter().any(|t| t == tag))
+    };
+    let files: Vec<PathBuf> = list_profile_files(profile)?
+        .into_iter()
+        .filter(|e| e != *PROCESSING_STATS_FILE && e != *RESOLUTION_CACHE_FILE)
+        .filter(in_tags)
+        .collect();
+    let selection = Selection::new(files, max_report_size_mb * 1024 * 1024)?;
+    selection.packed.iter().try_for_each(|e| -> Result<()> {
+        let filename = e
+            .strip_prefix(profile)
+            .ok()
+            .and_then(|f| f.to_str())
+            .ok_or_else(|| anyhow!("Malformed profile path: {}", e.display()))?;
+        if !is_metadata(e) && !is_bolt_profile(e) && **e != **LOG_FILE {
+            profile_count += 1;
+        }
+        if is_bolt_profile(e) {
+            zip.start_file(format!("{}/{}", BOLT_SECTION, filename), options)?;
+        } else {
             zip.start_file(filename, options)?;
-            let mut f = File::open(e)?;
-            let mut buffer = Vec::new();
-            f.read_to_end(&mut buffer)?;
-            zip.write_all(&buffer)?;
-            Ok(())
-        })?;
+        }
+        let mut f = File::open(e)?;
+        let mut buffer = Vec::new();
+        f.read_to_end(&mut buffer)?;
+        zip.write_all(&buffer)?;
+        if let Some(tag) = get_tag(e) {
+            *bytes_by_tag.entry(tag.to_string()).or_insert(0) += buffer.len() as u64;
+        }
+        if is_metadata(e) {
+            if let Ok(metadata) = serde_json::from_slice::<TraceMetadata>(&buffer) {
+                let profile = Path::new(filename).with_extension("").to_string_lossy().into_owned();
+                match apex_version_groups
+                    .iter_mut()
+                    .find(|g| g.apex_versions == metadata.apex_versions)
+                {
+                    Some(group) => group.profiles.push(profile.clone()),
+                    None => apex_version_groups.push(ApexVersionGroup {
+                        apex_versions: metadata.apex_versions,
+                        profiles: vec![profile.clone()],
+                    }),
+                }
+                let mut ids = vec![metadata.correlation_id];
+                ids.extend(metadata.merged_correlation_ids);
+                correlation_ids.insert(profile.clone(), ids);
+                if let Some(rung) = metadata.provider_rung {
+                    provider_rungs.insert(profile.clone(), rung);
+                }
+                if let Some(decision) = metadata.kernel_addresses {
+                    kernel_addresses.insert(profile.clone(), decision);
+                }
+                if !metadata.annotations.is_empty() {
+                    annotations.insert(profile, metadata.annotations);
+                }
+            }
+        }
+        Ok(())
+    })?;
+
+    zip.start_file(MANIFEST_FILE, options)?;
+    zip.write_all(
+        serde_json::to_string(&ReportManifest::new(
+            &report_filename,
+            identifiers,
+            annotations,
+            correlation_ids,
+            provider_rungs,
+            kernel_addresses,
+            tags,
+            bytes_by_tag,
+            apex_version_groups,
+            &selection,
+        ))?
+        .as_bytes(),
+    )?;
 
     if usage_setting != NO_USAGE_SETTING {
         zip.start_file("usage_setting", options)?;
         zip.write_all(usage_setting.to_string().as_bytes())?;
     }
     zip.finish()?;
-    clear_processed_files()?;
+    selection.oversized.iter().try_for_each(fs::remove_file)?;
+    if tags.is_empty() && !selection.split {
+        clear_processed_files(profile)?;
+    } else {
+        selection.packed.iter().try_for_each(fs::remove_file)?;
+        if tags.is_empty() {
+            // The processing stats of all tags are in this report's manifest.
+            fs::remove_file(*PROCESSING_STATS_FILE).ok();
+        } else {
+            ProcessingStats::remove(&PROCESSING_STATS_FILE, tags)?;
+        }
+    }
+
+    Ok(ReportInfo {
+        name: report_filename,
+        size: fs::metadata(&report)?.len(),
+        profile_count,
+        truncated: !selection.oversized.is_empty(),
+        split: selection.split,
+    })
+}
+
+/// List the files in `profile`, and in its per-provider subdirectories.
+fn list_profile_files(profile: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for e in fs::read_dir(profile)?.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if e.is_dir() {
+            files.extend(
+                fs::read_dir(&e)?.filter_map(|e| e.ok()).map(|e| e.path()).filter(|e| e.is_file()),
+            );
+        } else if e.is_file() {
+            files.push(e);
+        }
+    }
+    Ok(files)
+}
+
+/// Whether `filename` is the name of a report, a random (version 4) UUID. Report names carry no
+/// device identifier nor creation time, the report file modification time is its creation time.
+pub fn is_report_name(filename: &str) -> bool {
+    Uuid::parse_str(filename).is_ok_and(|uuid| uuid.get_version_num() == 4)
+}
 
-    Ok(report_filename)
+/// Read up to `len` bytes of the report `name` in `report_dir`, starting at `offset`. Returns fewer
+/// bytes at the end of the report, and none past it.
+pub fn read_report_chunk(
+    report_dir: &Path,
+    name: &str,
+    offset: u64,
+    len: usize,
+) -> Result<Vec<u8>> {
+    // Only report names are accepted, so that no other file can be read.
+    ensure!(is_report_name(name), "Invalid report name {}.", name);
+    let mut report = File::open(report_dir.join(name).with_extension("zip"))?;
+    report.seek(SeekFrom::Start(offset))?;
+    let mut chunk = Vec::new();
+    report.take(len.min(MAX_REPORT_CHUNK_BYTES) as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
 }
 
-fn get_report_filename(node_id: &MacAddr6) -> Result<String> {
-    let since_epoch = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
-    let ts = Timestamp::from_unix(&UUID_CONTEXT, since_epoch.as_secs(), since_epoch.subsec_nanos());
-    let uuid = Uuid::new_v1(
-        ts,
-        node_id.as_bytes().try_into().expect("Invalid number of bytes in V1 UUID"),
-    );
-    Ok(uuid.to_string())
+/// Remove the files in `report` which are not reports, then evict the reports past the retention
+/// period or over the report storage limit. Returns the number of bytes freed.
+pub fn evict_reports(report: &Path, config: &Config) -> Result<u64> {
+    let mut freed = 0;
+    let mut candidates = Vec::new();
+    for report in fs::read_dir(report)? {
+        let report = report?.path();
+        let report_name = report
+            .file_stem()
+            .and_then(|f| f.to_str())
+            .ok_or_else(|| anyhow!("Malformed path {}", report.display()))?;
+        if is_report_name(report_name) {
+            let mut candidate = Candidate::from_file(report.clone())?;
+            candidate.age = fs::metadata(&report)?.modified()?.elapsed().unwrap_or_default();
+            candidates.push(candidate);
+        } else {
+            log::error!("{} is not a report, deleting", report_name);
+            freed += fs::metadata(&report)?.len();
+            fs::remove_file(report)?;
+        }
+    }
+    let limits = Limits {
+        max_age: Some(Duration::from_secs(REPORT_RETENTION_SECS)),
+        ..Limits::from_mb(config.max_report_limit_mb)
+    };
+    freed += evict(&config.eviction_weights, &limits, candidates)?;
+    Ok(freed)
+}
+
+/// Get the age of the newest processed profile and of the newest report, None if there is none.
+/// Metadata sidecars are left out, they are rewritten without the profile changing.
+pub fn get_profile_freshness(
+    profile: &Path,
+    report: &Path,
+) -> Result<(Option<Duration>, Option<Duration>)> {
+    let newest_profile = list_profile_files(profile)?
+        .into_iter()
+        .filter(|e| {
+            !is_metadata(e)
+                && ![*CONFIG_FILE, *LOG_FILE, *PROCESSING_STATS_FILE, *RESOLUTION_CACHE_FILE]
+                    .contains(&e.as_path())
+        })
+        .filter_map(|e| e.metadata().and_then(|m| m.modified()).ok())
+        .max();
+
+    let newest_report = fs::read_dir(report)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|e| e.file_stem().and_then(|f| f.to_str()).is_some_and(is_report_name))
+        .filter_map(|e| e.metadata().and_then(|m| m.modified()).ok())
+        .max();
+
+    // Timestamps in the future (e.g. after a clock change) are considered fresh.
+    let age = |ts: SystemTime| ts.elapsed().unwrap_or_default();
+    Ok((newest_profile.map(age), newest_report.map(age)))
 }
 
-/// Get report creation timestamp through its filename (version 1 UUID).
-pub fn get_report_ts(filename: &str) -> Result<SystemTime> {
-    let uuid_ts = Uuid::parse_str(filename)?
-        .get_timestamp()
-        .ok_or_else(|| anyhow!("filename is not a valid V1 UUID."))?
-        .to_unix();
-    Ok(SystemTime::UNIX_EPOCH + Duration::new(uuid_ts.0, uuid_ts.1))
+/// The ages of the newest profile and report, for the status.
+pub fn freshness_status(profile: &Path, report: &Path) -> String {
+    let age =
+        |age: Option<Duration>| age.map_or("none".to_string(), |a| format!("{}s", a.as_secs()));
+    match get_profile_freshness(profile, report) {
+        Ok((profile_age, report_age)) => format!(
+            "newest profile age: {}\nnewest report age: {}\n",
+            age(profile_age),
+            age(report_age)
+        ),
+        Err(e) => format!("profile freshness: unknown ({})\n", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+    use zip::ZipArchive;
+
+    use super::{
+        get_profile_freshness, pack, pack_report_for_tags, pack_report_with_identifiers,
+        ReportIdentifiers, NO_USAGE_SETTING,
+    };
+    use crate::config::Config;
+    use crate::trace_metadata::new_correlation_id;
+
+    fn packed_files(report: &Path, name: &str) -> Vec<String> {
+        let zip = File::open(report.join(name).with_extension("zip")).unwrap();
+        let mut names: Vec<String> =
+            ZipArchive::new(zip).unwrap().file_names().map(str::to_string).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn packs_bolt_profiles_in_their_own_section() {
+        let root = temp_dir().join(format!("profcollectd_report_{}", new_correlation_id()));
+        let (profile, report) = (root.join("output"), root.join("report"));
+        fs::create_dir_all(profile.join("simpleperf_etm")).unwrap();
+        fs::create_dir_all(&report).unwrap();
+        for ext in ["data", "fdata"] {
+            let name = format!("simpleperf_etm/20261014T120000Z_test.{}", ext);
+            fs::write(profile.join(name), ext).unwrap();
+        }
+
+        let identifiers = ReportIdentifiers::scratch();
+        let info = pack_report_with_identifiers(&profile, &report, &identifiers).unwrap();
+        // Only the branch list counts as a profile.
+        assert_eq!(info.profile_count, 1);
+        assert_eq!(
+            packed_files(&report, &info.name),
+            [
+                "bolt/simpleperf_etm/20261014T120000Z_test.fdata",
+                "manifest.json",
+                "simpleperf_etm/20261014T120000Z_test.data",
+            ]
+        );
+        // The manifest records the given identifiers, not those of the device.
+        let zip = File::open(report.join(&info.name).with_extension("zip")).unwrap();
+        let mut manifest = String::new();
+        ZipArchive::new(zip)
+            .unwrap()
+            .by_name("manifest.json")
+            .unwrap()
+            .read_to_string(&mut manifest)
+            .unwrap();
+        assert!(manifest.contains(&identifiers.install_id));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn packs_only_the_reported_tags() {
+        let root = temp_dir().join(format!("profcollectd_report_{}", new_correlation_id()));
+        let (profile, report) = (root.join("output"), root.join("report"));
+        fs::create_dir_all(profile.join("simpleperf_etm")).unwrap();
+        fs::create_dir_all(&report).unwrap();
+        let files = ["a.data", "a.json", "b.data", "b.json"]
+            .map(|f| profile.join(format!("simpleperf_etm/20261014T120000Z_{}", f)));
+        for file in &files {
+            fs::write(file, "profile").unwrap();
+        }
+
+        // Tags are checked before the identifiers of the device are read.
+        let config = Config::from_env().unwrap();
+        assert!(pack_report_for_tags(&profile, &report, &config, &[]).is_err());
+        let identifiers = ReportIdentifiers::scratch();
+        let tags = ["a".to_string()];
+        let info = pack(&profile, &report, &identifiers, NO_USAGE_SETTING, &tags, 0).unwrap();
+        assert_eq!(info.profile_count, 1);
+        assert_eq!(
+            packed_files(&report, &info.name),
+            [
+                "manifest.json",
+                "simpleperf_etm/20261014T120000Z_a.data",
+                "simpleperf_etm/20261014T120000Z_a.json",
+            ]
+        );
+        // The reported files are removed, those of the other tags are kept for the next report.
+        assert_eq!(files.map(|f| f.exists()), [false, false, true, true]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn splits_and_truncates_reports_over_the_size_limit() {
+        let root = temp_dir().join(format!("profcollectd_report_{}", new_correlation_id()));
+        let (profile, report) = (root.join("output"), root.join("report"));
+        fs::create_dir_all(profile.join("simpleperf_etm")).unwrap();
+        fs::create_dir_all(&report).unwrap();
+        let mb = 1024 * 1024;
+        let profiles = [("20261014T120000Z_a", mb / 2), ("20261014T130000Z_b", 2 * mb)]
+            .into_iter()
+            .chain([("20261014T140000Z_c", mb / 4), ("20261014T150000Z_d", mb / 2)]);
+        for (name, size) in profiles {
+            let name = profile.join("simpleperf_etm").join(name);
+            fs::write(name.with_extension("data"), vec![0; size]).unwrap();
+            fs::write(name.with_extension("json"), "{}").unwrap();
+        }
+        let exists = |name: &str| profile.join("simpleperf_etm").join(name).exists();
+
+        // b is over the limit of 1MB by itself, and d is kept for the next report.
+        let identifiers = ReportIdentifiers::scratch();
+        let info = pack(&profile, &report, &identifiers, NO_USAGE_SETTING, &[], 1).unwrap();
+        assert_eq!(info.profile_count, 2);
+        assert!(info.truncated && info.split);
+        assert_eq!(
+            packed_files(&report, &info.name),
+            [
+                "manifest.json",
+                "simpleperf_etm/20261014T120000Z_a.data",
+                "simpleperf_etm/20261014T120000Z_a.json",
+                "simpleperf_etm/20261014T140000Z_c.data",
+                "simpleperf_etm/20261014T140000Z_c.json",
+            ]
+        );
+        assert!(!exists("20261014T130000Z_b.data") && !exists("20261014T130000Z_b.json"));
+        assert!(exists("20261014T150000Z_d.data") && exists("20261014T150000Z_d.json"));
+
+        let info = pack(&profile, &report, &identifiers, NO_USAGE_SETTING, &[], 1).unwrap();
+        assert_eq!(info.profile_count, 1);
+        assert!(!info.truncated && !info.split);
+        assert!(!exists("20261014T150000Z_d.data"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn rewritten_sidecars_do_not_refresh_profiles() {
+        let root = temp_dir().join(format!("profcollectd_report_{}", new_correlation_id()));
+        let (profile, report) = (root.join("output"), root.join("report"));
+        fs::create_dir_all(profile.join("simpleperf_etm")).unwrap();
+        fs::create_dir_all(&report).unwrap();
+        let name = profile.join("simpleperf_etm/20261014T120000Z_test");
+        let day = Duration::from_secs(24 * 3600);
+        let data = File::create(name.with_extension("data")).unwrap();
+        data.set_modified(SystemTime::now() - day).unwrap();
+        fs::write(name.with_extension("json"), "{}").unwrap();
+
+        let (profile_age, report_age) = get_profile_freshness(&profile, &report).unwrap();
+        assert!(profile_age.unwrap() >= day);
+        assert_eq!(report_age, None);
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
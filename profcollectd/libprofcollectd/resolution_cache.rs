@@ -0,0 +1,248 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Negative cache of the binaries whose build ID or symbols repeatedly fail to resolve while traces
+//! are processed, e.g. odd vendor blobs. They are left out of the binary filter, so that their
+//! lookup is not paid for every trace, until they are rechecked.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{read_to_string, write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Number of processing runs a binary must fail to resolve in before it is skipped.
+pub const FAILURES_BEFORE_SKIP: u32 = 3;
+/// How long a binary is skipped for before it is resolved again, and how long its failures are
+/// remembered for.
+pub const RECHECK_INTERVAL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Most bytes of the simpleperf log of a processing run scanned for resolution failures.
+pub const MAX_PROCESSING_LOG_BYTES: usize = 1024 * 1024;
+
+/// simpleperf log messages reporting that the binary they name failed to resolve, lowercase, see
+/// `Dso` and `cmd_inject.cpp`.
+const FAILURE_PATTERNS: &[&str] = &[
+    "isn't used because of build id mismatch",
+    "failed to read symbols from",
+    "failed to build instr ranges for binary",
+];
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Entry {
+    /// Processing runs the binary failed to resolve in.
+    failures: u32,
+    /// Seconds since the epoch of the last failure.
+    last_failure: u64,
+    /// Seconds since the epoch until which the binary is skipped, None if it is not.
+    skipped_until: Option<u64>,
+}
+
+/// Resolution failures by binary path.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResolutionCache {
+    binaries: BTreeMap<String, Entry>,
+}
+
+fn secs_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// The binaries which failed to resolve according to the simpleperf `log`, i.e. the first absolute
+/// path of each line reporting a failure.
+pub fn failed_binaries(log: &str) -> BTreeSet<String> {
+    log.lines()
+        .filter(|line| {
+            let line = line.to_lowercase();
+            FAILURE_PATTERNS.iter().any(|p| line.contains(p))
+        })
+        .filter_map(|line| {
+            line.split(|c: char| c.is_whitespace() || c == '\'' || c == '"')
+                .find(|word| word.starts_with('/'))
+        })
+        .map(|path| path.trim_end_matches([':', ',', '.', ')']).to_string())
+        .filter(|path| path.len() > 1)
+        .collect()
+}
+
+/// Escape the regex metacharacters of `path`.
+fn escape(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl ResolutionCache {
+    /// Load the cache from `file`, empty if there is none.
+    pub fn load(file: &Path) -> Self {
+        read_to_string(file).ok().and_then(|s| serde_json::from_str(&s).ok()).unwrap_or_default()
+    }
+
+    pub fn save(&self, file: &Path) -> Result<()> {
+        write(file, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Record the binaries which failed to resolve in a processing run at `now`, from the
+    /// simpleperf `log` of the run.
+    pub fn record(&mut self, log: &str, now: SystemTime) {
+        let now = secs_since_epoch(now);
+        let interval = RECHECK_INTERVAL.as_secs();
+        // Forget the binaries which did not fail for a whole interval. Skipped binaries are
+        // rechecked once their interval is over, and skipped again on their next failure.
+        self.binaries.retain(|_, entry| match entry.skipped_until {
+            Some(until) if until <= now => {
+                entry.failures = FAILURES_BEFORE_SKIP - 1;
+                entry.last_failure = now;
+                entry.skipped_until = None;
+                true
+            }
+            Some(_) => true,
+            None => now.saturating_sub(entry.last_failure) < interval,
+        });
+        for binary in failed_binaries(log) {
+            let entry = self.binaries.entry(binary.clone()).or_default();
+            entry.failures += 1;
+            entry.last_failure = now;
+            if entry.failures >= FAILURES_BEFORE_SKIP && entry.skipped_until.is_none() {
+                log::info!("Skipping {}, it failed to resolve {} times.", binary, entry.failures);
+                entry.skipped_until = Some(now + interval);
+            }
+        }
+    }
+
+    /// The binaries skipped at `now`.
+    pub fn skipped(&self, now: SystemTime) -> Vec<&str> {
+        let now = secs_since_epoch(now);
+        self.binaries
+            .iter()
+            .filter(|(_, entry)| entry.skipped_until.is_some_and(|until| until > now))
+            .map(|(binary, _)| binary.as_str())
+            .collect()
+    }
+
+    /// The filter of the binaries to process, which leaves the skipped binaries out of
+    /// `binary_filter`.
+    pub fn binary_filter(&self, binary_filter: &str, now: SystemTime) -> String {
+        let skipped = self.skipped(now);
+        if skipped.is_empty() {
+            return binary_filter.to_string();
+        }
+        let skipped: Vec<String> = skipped.into_iter().map(escape).collect();
+        format!("^(?!(?:{})$).*?(?:{})", skipped.join("|"), binary_filter)
+    }
+
+    pub fn status(&self, now: SystemTime) -> String {
+        match self.skipped(now).len() {
+            0 => String::new(),
+            count => format!("binaries skipped for failing to resolve: {}\n", count),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{
+        failed_binaries, ResolutionCache, FAILURES_BEFORE_SKIP, FAILURE_PATTERNS, RECHECK_INTERVAL,
+    };
+
+    const BLOB: &str = "/vendor/lib64/libblob.so";
+    const LOG: &str = "simpleperf W 2026-10-14T00:00:00Z dso.cpp:517] failed to read symbols from \
+        /vendor/lib64/libblob.so: Malformed file\n";
+
+    fn hours(hours: u64) -> Duration {
+        Duration::from_secs(hours * 60 * 60)
+    }
+
+    #[test]
+    fn parses_failed_binaries_from_log() {
+        let log = "\
+            simpleperf W t dso.cpp:517] failed to read symbols from /vendor/lib64/libblob.so: \
+            No build id\n\
+            simpleperf W t dso.cpp:138] /system/lib64/libc.so isn't used because of build id \
+            mismatch: expected 1234, real 5678\n\
+            simpleperf W t cmd_inject.cpp:622] failed to build instr ranges for binary \
+            /system/lib64/libm.so: no instructions\n\
+            simpleperf I t cmd_inject.cpp:816] Skip empty output file.\n\
+            simpleperf W t dso.cpp:517] failed to read symbols from relative: File not found\n";
+        let binaries: Vec<String> = failed_binaries(log).into_iter().collect();
+        assert_eq!(binaries, vec!["/system/lib64/libc.so", "/system/lib64/libm.so", BLOB]);
+    }
+
+    #[test]
+    fn failure_patterns_are_simpleperf_messages() {
+        let sources = [
+            include_str!("../../simpleperf/dso.cpp"),
+            include_str!("../../simpleperf/cmd_inject.cpp"),
+        ]
+        .map(str::to_lowercase);
+        for pattern in FAILURE_PATTERNS {
+            assert!(sources.iter().any(|s| s.contains(pattern)), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn skips_binary_after_repeated_failures() {
+        let start = SystemTime::now();
+        let mut cache = ResolutionCache::default();
+        for run in 0..FAILURES_BEFORE_SKIP {
+            assert!(cache.skipped(start).is_empty());
+            cache.record(LOG, start + hours(run as u64));
+        }
+        let now = start + hours(FAILURES_BEFORE_SKIP as u64);
+        assert_eq!(cache.skipped(now), vec![BLOB]);
+        assert_eq!(
+            cache.binary_filter("^/vendor/.+", now),
+            "^(?!(?:/vendor/lib64/libblob\\.so)$).*?(?:^/vendor/.+)"
+        );
+        assert_eq!(cache.status(now), "binaries skipped for failing to resolve: 1\n");
+    }
+
+    #[test]
+    fn rechecks_skipped_binary_after_interval() {
+        let start = SystemTime::now();
+        let mut cache = ResolutionCache::default();
+        for _ in 0..FAILURES_BEFORE_SKIP {
+            cache.record(LOG, start);
+        }
+        // Rechecked once the interval is over, and skipped again on the next failure.
+        let recheck = start + RECHECK_INTERVAL + hours(1);
+        cache.record("", recheck);
+        assert!(cache.skipped(recheck).is_empty());
+        assert_eq!(cache.binary_filter("^/vendor/.+", recheck), "^/vendor/.+");
+        cache.record(LOG, recheck);
+        assert_eq!(cache.skipped(recheck), vec![BLOB]);
+    }
+
+    #[test]
+    fn forgets_failures_older_than_interval() {
+        let start = SystemTime::now();
+        let mut cache = ResolutionCache::default();
+        for _ in 1..FAILURES_BEFORE_SKIP {
+            cache.record(LOG, start);
+        }
+        cache.record(LOG, start + RECHECK_INTERVAL + hours(1));
+        assert!(cache.skipped(start + RECHECK_INTERVAL + hours(1)).is_empty());
+        assert_eq!(cache.status(start), "");
+    }
+}
@@ -0,0 +1,60 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Scheduling policy of the collection and processing worker threads.
+
+use std::thread;
+
+use crate::config::Config;
+
+extern "C" {
+    /// Defined in native/sched_policy.cpp.
+    fn profcollectd_set_background(tid: libc::pid_t) -> bool;
+}
+
+/// Apply the background task profiles to the calling thread, as the rc file applies its
+/// task_profiles to the service, so that profcollect work never preempts foreground threads. The
+/// profiles leave the cgroup and scheduler details to the device. Does nothing if disabled in the
+/// config.
+pub fn set_background(config: &Config) {
+    if !config.background_worker_threads {
+        return;
+    }
+
+    // SAFETY: This is always safe to call.
+    let tid = unsafe { libc::gettid() };
+    // SAFETY: The shim only reads its argument.
+    if !unsafe { profcollectd_set_background(tid) } {
+        log::error!("Failed to apply background task profiles to thread {}", tid);
+    }
+}
+
+/// Run `f` on a new background thread, see `set_background()`, and wait for its result. Used for
+/// work requested over binder, so that binder threads keep their scheduling policy.
+pub fn run_in_background<T, F>(config: &Config, f: F) -> T
+where
+    T: Send,
+    F: FnOnce() -> T + Send,
+{
+    thread::scope(|s| {
+        s.spawn(|| {
+            set_background(config);
+            f()
+        })
+        .join()
+        .expect("Background worker thread panicked.")
+    })
+}
@@ -0,0 +1,253 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Simulation of the periodic collection schedule, so that operators can check how a config plays
+//! out before rolling it out.
+
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::intensive_mode::INTENSIVE_TAG;
+use crate::scheduler::POST_OTA_TAG;
+
+/// How far ahead the schedule is simulated.
+pub const SIMULATION_HORIZON: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// State of the device the simulation starts from. The device is assumed to stay awake, and the
+/// state to only change as the windows below expire.
+pub struct SimulationState {
+    pub since_boot: Duration,
+    /// Time left in the post-OTA window and in the intensive mode, None if they are off.
+    pub post_ota_remaining: Option<Duration>,
+    pub intensive_remaining: Option<Duration>,
+    /// Active blackouts, with the time left until they expire. Blackouts without expiry are
+    /// assumed to last for the whole simulation.
+    pub blackouts: Vec<(String, Option<Duration>)>,
+    /// Why all traces are suppressed for the whole simulation, e.g. collection is disabled.
+    pub suppressed_by: Option<String>,
+    /// Time left in the cooldown after the last trace, see `min_trace_interval`. None if there is
+    /// none.
+    pub cooldown_remaining: Option<Duration>,
+    /// Whether the trace storage is over its limit. Processing and eviction are not simulated, so
+    /// it is assumed to stay over for the whole simulation. A backlog of unprocessed traces over
+    /// its limit is one of the blackouts instead.
+    pub trace_storage_full: bool,
+}
+
+/// A periodic trace planned by the simulation.
+pub struct PlannedTrace {
+    /// Time from the start of the simulation.
+    pub at: Duration,
+    pub tag: &'static str,
+    pub sampling_period: Duration,
+    /// Why the trace would be skipped, None if it would be recorded.
+    pub skipped_by: Option<String>,
+}
+
+/// Plan the periodic traces of the next `SIMULATION_HORIZON`, as the periodic collection worker
+/// would perform them if it was scheduled now.
+pub fn simulate(
+    config: &Config,
+    state: &SimulationState,
+    sampling_period: Duration,
+) -> Vec<PlannedTrace> {
+    let mut plan = Vec::new();
+    let mut at = Duration::ZERO;
+    let mut cooldown_end = state.cooldown_remaining.unwrap_or_default();
+    loop {
+        let (interval, tag) = if state.post_ota_remaining.is_some_and(|r| at < r) {
+            (config.post_ota_collection_interval, POST_OTA_TAG)
+        } else if state.intensive_remaining.is_some_and(|r| at < r) {
+            (config.intensive_collection_interval, INTENSIVE_TAG)
+        } else {
+            (config.collection_interval, "periodic")
+        };
+        at += interval;
+        if interval.is_zero() || at > SIMULATION_HORIZON {
+            break;
+        }
+        let blackouts: Vec<&str> = state
+            .blackouts
+            .iter()
+            .filter(|(_, remaining)| remaining.is_none_or(|r| at < r))
+            .map(|(reason, _)| reason.as_str())
+            .collect();
+        // Checked in the order of the periodic collection worker.
+        let in_boot_warmup = state.since_boot + at < config.boot_warmup_period;
+        let skipped_by = if tag != POST_OTA_TAG && in_boot_warmup {
+            Some("boot warm-up".to_string())
+        } else if let Some(reason) = &state.suppressed_by {
+            Some(reason.clone())
+        } else if !blackouts.is_empty() {
+            Some(format!("blackout: {}", blackouts.join(", ")))
+        } else if state.trace_storage_full {
+            Some("trace storage full".to_string())
+        } else if at < cooldown_end {
+            Some(format!("cooldown, {:.1}s left", (cooldown_end - at).as_secs_f32()))
+        } else {
+            None
+        };
+        let sampling_period = match tag {
+            INTENSIVE_TAG => config.intensive_sampling_period,
+            _ => sampling_period,
+        };
+        let recorded = skipped_by.is_none();
+        plan.push(PlannedTrace { at, tag, sampling_period, skipped_by });
+        if recorded {
+            // The next interval starts once the trace is recorded.
+            at += sampling_period;
+            cooldown_end = at + config.min_trace_interval;
+        }
+    }
+    plan
+}
+
+/// Human-readable plan, one line per trace with its time from now, and a summary.
+pub fn format_plan(config: &Config, plan: &[PlannedTrace]) -> String {
+    let mut out = String::new();
+    let mut recorded = 0;
+    let mut tracing = Duration::ZERO;
+    for trace in plan {
+        let secs = trace.at.as_secs();
+        write!(out, "+{:02}:{:02}:{:02} {}", secs / 3600, secs / 60 % 60, secs % 60, trace.tag)
+            .unwrap();
+        match &trace.skipped_by {
+            Some(reason) => writeln!(out, " skipped: {}", reason),
+            None => writeln!(out, " for {:.1}s", trace.sampling_period.as_secs_f32()),
+        }
+        .unwrap();
+        if trace.skipped_by.is_none() {
+            recorded += 1;
+            tracing += trace.sampling_period;
+        }
+    }
+    writeln!(
+        out,
+        "{} traces planned, {} skipped, {:.1}s of tracing in the next {}h.",
+        recorded,
+        plan.len() - recorded,
+        tracing.as_secs_f32(),
+        SIMULATION_HORIZON.as_secs() / 3600
+    )
+    .unwrap();
+    if config.system_server_trace_freq > 0 {
+        writeln!(
+            out,
+            "{}% of periodic traces trace system_server instead of the whole system.",
+            config.system_server_trace_freq
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_plan, simulate, SimulationState};
+    use crate::blackout::BACKLOG_REASON;
+    use crate::config::Config;
+    use crate::intensive_mode::INTENSIVE_TAG;
+    use std::time::Duration;
+
+    const SAMPLING_PERIOD: Duration = Duration::from_secs(1);
+
+    fn test_config() -> Config {
+        let mut config = Config::from_env().unwrap();
+        config.collection_interval = Duration::from_secs(3600);
+        config.boot_warmup_period = Duration::ZERO;
+        config.min_trace_interval = Duration::ZERO;
+        config.system_server_trace_freq = 0;
+        config
+    }
+
+    fn idle_state() -> SimulationState {
+        SimulationState {
+            since_boot: Duration::from_secs(3600),
+            post_ota_remaining: None,
+            intensive_remaining: None,
+            blackouts: Vec::new(),
+            suppressed_by: None,
+            cooldown_remaining: None,
+            trace_storage_full: false,
+        }
+    }
+
+    #[test]
+    fn plans_a_trace_per_interval() {
+        let config = test_config();
+        let plan = simulate(&config, &idle_state(), SAMPLING_PERIOD);
+        // Each interval starts once the previous trace is recorded.
+        assert_eq!(plan.len(), 23);
+        assert_eq!(plan[0].at, Duration::from_secs(3600));
+        assert_eq!(plan[1].at, Duration::from_secs(7201));
+        assert!(plan.iter().all(|t| t.skipped_by.is_none() && t.tag == "periodic"));
+        let out = format_plan(&config, &plan[..2]);
+        assert_eq!(
+            out,
+            concat!(
+                "+01:00:00 periodic for 1.0s\n",
+                "+02:00:01 periodic for 1.0s\n",
+                "2 traces planned, 0 skipped, 2.0s of tracing in the next 24h.\n",
+            )
+        );
+    }
+
+    #[test]
+    fn skips_traces_in_cooldown() {
+        let mut config = test_config();
+        config.intensive_collection_interval = Duration::from_secs(10);
+        config.intensive_sampling_period = Duration::from_secs(5);
+        config.min_trace_interval = Duration::from_secs(15);
+        let state = SimulationState {
+            intensive_remaining: Some(Duration::from_secs(60)),
+            cooldown_remaining: Some(Duration::from_secs(20)),
+            ..idle_state()
+        };
+        let plan = simulate(&config, &state, SAMPLING_PERIOD);
+        let skipped: Vec<(u64, Option<&str>)> =
+            plan.iter().take(4).map(|t| (t.at.as_secs(), t.skipped_by.as_deref())).collect();
+        assert_eq!(
+            skipped,
+            vec![
+                (10, Some("cooldown, 10.0s left")),
+                (20, None),
+                (35, Some("cooldown, 5.0s left")),
+                (45, None),
+            ]
+        );
+        assert!(plan.iter().take(4).all(|t| t.tag == INTENSIVE_TAG));
+    }
+
+    #[test]
+    fn skips_traces_over_backlog_and_storage_limits() {
+        let config = test_config();
+        let state = SimulationState {
+            blackouts: vec![(BACKLOG_REASON.to_string(), None)],
+            trace_storage_full: true,
+            ..idle_state()
+        };
+        let plan = simulate(&config, &state, SAMPLING_PERIOD);
+        assert_eq!(plan.len(), 24);
+        assert_eq!(plan[0].skipped_by.as_deref(), Some("blackout: unprocessed backlog"));
+
+        let state = SimulationState { trace_storage_full: true, ..idle_state() };
+        let plan = simulate(&config, &state, SAMPLING_PERIOD);
+        assert!(plan.iter().all(|t| t.skipped_by.as_deref() == Some("trace storage full")));
+        assert!(format_plan(&config, &plan)
+            .ends_with("0 traces planned, 24 skipped, 0.0s of tracing in the next 24h.\n"));
+    }
+}
@@ -16,67 +16,289 @@
 
 //! ProfCollect tracing scheduler.
 
+use std::collections::BTreeMap;
 use std::fs;
 use std::mem;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{sync_channel, SyncSender};
 use std::sync::Arc;
-use std::sync::Mutex;
+use std::sync::{Mutex, Once, RwLock, TryLockError};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::config::{get_sampling_period, Config, LOG_FILE, PROFILE_OUTPUT_DIR, TRACE_OUTPUT_DIR};
+use crate::audit::{audit, Audits};
+use crate::blackout::{Blackouts, BACKLOG_REASON, BACKUP_REASON, MAX_BACKUP_DURATION};
+use crate::charge_budget::{Activity, ChargeBudget, PropertyStore};
+use crate::clock_watch::ClockWatch;
+use crate::config::{
+    get_profile_dir, get_sampling_period, get_trace_dir, ConcurrentTracePolicy, Config,
+    CONFIG_FILE, LOG_FILE, PROCESSING_STATS_FILE, PROFILE_OUTPUT_DIR, QUARANTINE_DIR,
+    REPORT_OUTPUT_DIR, RESOLUTION_CACHE_FILE, TRACE_OUTPUT_DIR,
+};
+use crate::disabled_trace_provider::DISABLED_PROVIDER_NAME;
+use crate::eviction::{evict, evicted_on_arrival, list_candidates, Candidate, Limits};
+use crate::fault_injection::{inject_fault, FaultPoint};
+use crate::in_memory_trace::InMemoryTrace;
+use crate::intensive_mode::{IntensiveMode, INTENSIVE_TAG};
+use crate::kernel_log::{KernelLogMonitor, KernelWarnings};
+use crate::log_budget;
+use crate::oneshot_queue::{OneShotQueue, OneShotTrace, Priority, QueuedTrace};
+use crate::perf_sessions::{active_perf_session, PerfSession, PerfSessionConflicts};
+use crate::phone_state::in_call;
+use crate::processing::{ProcessingPipeline, ProcessingStats, StageContext};
+use crate::profile_summary;
+use crate::provider_chain::ProviderChain;
+use crate::provider_error::{collect_logs, ProviderError, ProviderErrors};
+use crate::resolution_cache::{ResolutionCache, MAX_PROCESSING_LOG_BYTES};
+use crate::sched_policy::{run_in_background, set_background};
+use crate::schedule_simulation::{format_plan, simulate, SimulationState};
+use crate::scheduler_state::{State, StateMachine};
+use crate::storage::{
+    self, get_backlog, get_backlog_by_tag, get_storage_usage, remove_orphaned_metadata, TagUsage,
+};
+use crate::system_server::{get_system_server_processes, SYSTEM_SERVER_TAG};
+use crate::trace_metadata::{
+    merge_annotations, move_processed_metadata, new_correlation_id, process_cpu_time,
+    time_since_boot, Annotations, TraceMetadata,
+};
 use crate::trace_provider::{self, TraceProvider};
+use crate::zygote::{check_zygote_process, ZygotePhase, ZYGOTE_PHASE_ANNOTATION};
 use anyhow::{anyhow, ensure, Context, Result};
+use rand::Rng;
+
+/// Tag given to periodic traces collected while the post-OTA profiling window is open.
+pub const POST_OTA_TAG: &str = "post-ota";
+/// Annotation marking all traces collected while the post-OTA profiling window is open. One-shot
+/// traces keep the tag they were requested with, so that they can still be reported and evicted
+/// by tag.
+const POST_OTA_ANNOTATION: &str = "post_ota";
+
+/// How long to wait before retrying a queued one-shot trace which is still blocked.
+const ONESHOT_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Why collection is disabled once every provider of the chain failed, or none is supported.
+const NO_PROVIDER_LEFT: &str = "no trace provider left";
+
+/// A trace being recorded.
+struct ActiveTrace {
+    tag: String,
+    correlation_id: String,
+    system_wide: bool,
+    /// Tags and correlation IDs of the trace requests merged into this trace.
+    merged_tags: Vec<String>,
+    merged_correlation_ids: Vec<String>,
+    /// Annotations of the trace request, and of the requests merged into this trace.
+    annotations: Annotations,
+}
+
+/// State shared between the scheduler and its worker threads to record and process traces.
+struct Recorder {
+    /// The preferred trace provider for the system. It is replaced in place when the provider
+    /// fails, see `fall_back`.
+    trace_provider: Arc<Mutex<Box<dyn TraceProvider + Send>>>,
+    /// Directories of the traces and profiles of the trace provider.
+    dirs: RwLock<ProviderDirs>,
+    state: StateMachine,
+    blackouts: Blackouts,
+    active_trace: Mutex<Option<ActiveTrace>>,
+    /// When the last trace ended, no trace starts until `min_trace_interval` after it.
+    last_trace_end: Mutex<Option<Instant>>,
+    kernel_warnings: KernelWarnings,
+    charge_budget: ChargeBudget,
+    audits: Audits,
+    intensive_mode: IntensiveMode,
+    clock_watch: ClockWatch,
+    provider_errors: ProviderErrors,
+    provider_chain: ProviderChain,
+    perf_session_conflicts: PerfSessionConflicts,
+}
+
+/// Name of a trace provider, and directories of its traces and profiles. The name is kept here so
+/// that it can be read while the provider is busy.
+struct ProviderDirs {
+    provider: &'static str,
+    trace_dir: PathBuf,
+    profile_dir: PathBuf,
+}
+
+impl ProviderDirs {
+    fn create(provider: &'static str) -> Result<Self> {
+        let dirs = ProviderDirs {
+            provider,
+            trace_dir: get_trace_dir(provider),
+            profile_dir: get_profile_dir(provider),
+        };
+        fs::create_dir_all(&dirs.trace_dir)?;
+        fs::create_dir_all(&dirs.profile_dir)?;
+        Ok(dirs)
+    }
+}
+
+/// Signals to the periodic worker.
+enum PeriodicSignal {
+    /// Stop periodic collection.
+    Terminate,
+    /// Start waiting for the next trace again, as its interval changed.
+    Reschedule,
+}
 
 pub struct Scheduler {
-    /// Signal to terminate the periodic collection worker thread, None if periodic collection is
-    /// not scheduled.
-    termination_ch: Option<SyncSender<()>>,
-    /// The preferred trace provider for the system.
-    trace_provider: Arc<Mutex<dyn TraceProvider + Send>>,
+    /// Signals to the periodic collection worker thread, while it runs. Whether periodic
+    /// collection is scheduled is kept by the state machine.
+    termination_ch: Option<SyncSender<PeriodicSignal>>,
+    recorder: Arc<Recorder>,
     provider_ready_callbacks: Arc<Mutex<Vec<Box<dyn FnOnce() + Send>>>>,
+    /// End of the post-OTA profiling window, None if the device did not just take an update.
+    post_ota_deadline: Option<Instant>,
+    oneshot_queue: Arc<OneShotQueue>,
+    /// Starts the worker thread performing queued one-shot traces.
+    oneshot_worker: Once,
 }
 
 impl Scheduler {
-    pub fn new() -> Result<Self> {
-        let p = trace_provider::get_trace_provider()?;
-        p.lock().map_err(|e| anyhow!(e.to_string()))?.set_log_file(&LOG_FILE);
+    pub fn new(config: &Config) -> Result<Self> {
+        let provider_chain = ProviderChain::load(config);
+        let provider = trace_provider::get_trace_provider(&provider_chain);
+        let dirs = ProviderDirs::create(provider.get_name())?;
+        let state = StateMachine::default();
+        if provider.get_name() == DISABLED_PROVIDER_NAME {
+            state.disable(NO_PROVIDER_LEFT);
+        }
         Ok(Scheduler {
             termination_ch: None,
-            trace_provider: p,
+            recorder: Arc::new(Recorder {
+                trace_provider: Arc::new(Mutex::new(provider)),
+                dirs: RwLock::new(dirs),
+                state,
+                blackouts: Blackouts::default(),
+                active_trace: Mutex::new(None),
+                last_trace_end: Mutex::new(None),
+                kernel_warnings: KernelWarnings::default(),
+                charge_budget: ChargeBudget::load(Box::new(PropertyStore)),
+                audits: Audits::default(),
+                intensive_mode: IntensiveMode::default(),
+                clock_watch: ClockWatch::default(),
+                provider_errors: ProviderErrors::default(),
+                provider_chain,
+                perf_session_conflicts: PerfSessionConflicts::default(),
+            }),
             provider_ready_callbacks: Arc::new(Mutex::new(Vec::new())),
+            post_ota_deadline: None,
+            oneshot_queue: Arc::new(OneShotQueue::default()),
+            oneshot_worker: Once::new(),
         })
     }
 
     fn is_scheduled(&self) -> bool {
-        self.termination_ch.is_some()
+        self.recorder.state.is_scheduled()
+    }
+
+    /// Collect more aggressively, with traces tagged `post-ota`, until the post-OTA window ends.
+    pub fn start_post_ota_window(&mut self, config: &Config) {
+        log::info!("Post-OTA profiling enabled for {}s.", config.post_ota_window.as_secs());
+        self.post_ota_deadline = Some(Instant::now() + config.post_ota_window);
+    }
+
+    /// Mark `annotations` as those of a post-OTA trace while the post-OTA window is open.
+    fn mark_post_ota(&self, annotations: &mut Annotations) {
+        if in_post_ota_window(self.post_ota_deadline) {
+            mark_post_ota(annotations);
+        }
     }
 
     pub fn schedule_periodic(&mut self, config: &Config) -> Result<()> {
-        ensure!(!self.is_scheduled(), "Already scheduled.");
+        self.recorder.state.set_scheduled(true)?;
 
         let (sender, receiver) = sync_channel(1);
         self.termination_ch = Some(sender);
 
-        // Clone config and trace_provider ARC for the worker thread.
+        // Clone config and recorder ARC for the worker thread.
         let config = config.clone();
-        let trace_provider = self.trace_provider.clone();
+        let recorder = self.recorder.clone();
+        let post_ota_deadline = self.post_ota_deadline;
 
         thread::spawn(move || {
+            set_background(&config);
             loop {
-                match receiver.recv_timeout(config.collection_interval) {
-                    Ok(_) => break,
+                let (interval, tag) = if in_post_ota_window(post_ota_deadline) {
+                    (config.post_ota_collection_interval, POST_OTA_TAG)
+                } else if recorder.intensive_mode.is_active() {
+                    (config.intensive_collection_interval, INTENSIVE_TAG)
+                } else {
+                    (config.collection_interval, "periodic")
+                };
+                recorder.clock_watch.anchor();
+                match receiver.recv_timeout(interval) {
+                    Ok(PeriodicSignal::Terminate) => break,
+                    Ok(PeriodicSignal::Reschedule) => continue,
                     Err(_) => {
                         // Did not receive a termination signal, initiate trace event.
-                        if check_space_limit(&TRACE_OUTPUT_DIR, &config).unwrap() {
-                            trace_provider.lock().unwrap().trace_system(
-                                &TRACE_OUTPUT_DIR,
-                                "periodic",
-                                &get_sampling_period(),
-                                &config.binary_filter,
+                        // The interval is measured in awake time, so the trace is due even after
+                        // a long suspend. Skipping it would stop collection on devices which are
+                        // suspended most of the time.
+                        if let Some(suspended) = recorder.clock_watch.check(interval) {
+                            log::info!(
+                                "Periodic trace due after a {}s suspend.",
+                                suspended.as_secs()
                             );
                         }
+                        if tag != POST_OTA_TAG && in_boot_warmup(&config) {
+                            log::info!("Periodic trace skipped: boot warm-up.");
+                            continue;
+                        }
+                        if recorder.suppress(&config, tag) {
+                            continue;
+                        }
+                        if !recorder.has_trace_space(&config) {
+                            continue;
+                        }
+                        let id = new_correlation_id();
+                        let trace_system_server = tag == "periodic"
+                            && rand::thread_rng().gen_range(0..100)
+                                < config.system_server_trace_freq;
+                        let sampling_period = recorder.trace_duration(
+                            &config,
+                            match tag {
+                                INTENSIVE_TAG => config.intensive_sampling_period,
+                                _ => get_sampling_period(),
+                            },
+                        );
+                        let result = if trace_system_server {
+                            recorder.trace_system_server_processes(&config, &id)
+                        } else {
+                            let mut annotations = Annotations::new();
+                            if tag == POST_OTA_TAG {
+                                mark_post_ota(&mut annotations);
+                            }
+                            recorder.record(
+                                &config,
+                                tag,
+                                &id,
+                                "",
+                                true,
+                                false,
+                                &annotations,
+                                |p, _| {
+                                    p.trace_system(
+                                        &recorder.trace_dir(),
+                                        tag,
+                                        &id,
+                                        None,
+                                        &sampling_period,
+                                        &config.get_binary_filter(),
+                                    )
+                                },
+                            )
+                        };
+                        match result {
+                            Ok(()) if tag == INTENSIVE_TAG => {
+                                recorder.intensive_mode.record_trace()
+                            }
+                            Ok(()) => (),
+                            Err(blocked_by) => {
+                                log::info!("Periodic trace {} skipped: {}", id, blocked_by)
+                            }
+                        }
                     }
                 }
             }
@@ -85,26 +307,27 @@ impl Scheduler {
     }
 
     pub fn terminate_periodic(&mut self) -> Result<()> {
+        self.recorder.state.set_scheduled(false)?;
         self.termination_ch
-            .as_ref()
+            .take()
             .ok_or_else(|| anyhow!("Not scheduled"))?
-            .send(())
-            .context("Scheduler worker disappeared.")?;
-        self.termination_ch = None;
-        Ok(())
+            .send(PeriodicSignal::Terminate)
+            .context("Scheduler worker disappeared.")
     }
 
     pub fn trace_system(&self, config: &Config, tag: &str) -> Result<()> {
-        let trace_provider = self.trace_provider.clone();
-        if check_space_limit(&TRACE_OUTPUT_DIR, config)? {
-            trace_provider.lock().unwrap().trace_system(
-                &TRACE_OUTPUT_DIR,
-                tag,
-                &get_sampling_period(),
-                &config.binary_filter,
-            );
-        }
-        Ok(())
+        self.trace_once(
+            config,
+            OneShotTrace {
+                tag: tag.to_string(),
+                processes: String::new(),
+                sampling_period: get_sampling_period(),
+                expiry: config.oneshot_expiry,
+                annotations: Annotations::new(),
+                priority: Priority::Normal,
+                correlation_id: new_correlation_id(),
+            },
+        )
     }
 
     pub fn trace_process(
@@ -114,38 +337,298 @@ impl Scheduler {
         processes: &str,
         samplng_period: f32,
     ) -> Result<()> {
-        let trace_provider = self.trace_provider.clone();
         let duration = match samplng_period {
             0.0 => get_sampling_period(),
             _ => Duration::from_millis(samplng_period as u64),
         };
-        if check_space_limit(&TRACE_OUTPUT_DIR, config)? {
-            trace_provider.lock().unwrap().trace_process(
-                &TRACE_OUTPUT_DIR,
-                tag,
-                &duration,
-                processes,
-            );
+        self.trace_once(
+            config,
+            OneShotTrace {
+                tag: tag.to_string(),
+                processes: processes.to_string(),
+                sampling_period: duration,
+                expiry: config.oneshot_expiry,
+                annotations: Annotations::new(),
+                priority: Priority::Normal,
+                correlation_id: new_correlation_id(),
+            },
+        )
+    }
+
+    /// Trace `pid` in the zygote `phase` now, if the phase is sampled. The trace is handed off to
+    /// the one-shot worker, so that the hint returns right away, and dropped rather than retried if
+    /// it cannot start, as the phase would be over by then.
+    pub fn trace_zygote_phase(
+        &self,
+        config: &Config,
+        phase: ZygotePhase,
+        pid: u32,
+        zygotes: &[u32],
+    ) -> Result<()> {
+        let Some(sampling_period) = phase.sampling_period(config) else {
+            return Ok(());
+        };
+        check_zygote_process(phase, pid, zygotes)?;
+        if !check_space_limit(&self.recorder.trace_dir(), config)? {
+            return Ok(());
+        }
+        let mut annotations =
+            Annotations::from([(ZYGOTE_PHASE_ANNOTATION.to_string(), phase.name().to_string())]);
+        self.mark_post_ota(&mut annotations);
+        self.start_oneshot_worker(config);
+        self.oneshot_queue.push(QueuedTrace {
+            // A trace starting once the sampling period passed would miss all of the phase.
+            deadline: Instant::now() + sampling_period,
+            trace: OneShotTrace {
+                tag: phase.tag().to_string(),
+                processes: pid.to_string(),
+                sampling_period,
+                expiry: Duration::ZERO,
+                annotations,
+                priority: Priority::Critical,
+                correlation_id: new_correlation_id(),
+            },
+            config: config.clone(),
+            blocked_by: "not tried yet".to_string(),
+            counted_as_suppressed: false,
+        });
+        Ok(())
+    }
+
+    /// Perform a one-shot trace now if possible. Otherwise the trace is queued until it can be
+    /// performed, or dropped once its expiry has passed.
+    pub fn trace_once(&self, config: &Config, mut trace: OneShotTrace) -> Result<()> {
+        self.mark_post_ota(&mut trace.annotations);
+        if !check_space_limit(&self.recorder.trace_dir(), config)? {
+            return Ok(());
+        }
+        let mut counted_as_suppressed = false;
+        let blocked_by = run_in_background(config, || {
+            self.recorder.try_oneshot(config, &trace, &mut counted_as_suppressed)
+        });
+        let Some(blocked_by) = blocked_by else {
+            return Ok(());
+        };
+        if trace.expiry.is_zero() {
+            self.oneshot_queue.record_dropped(&trace, &blocked_by);
+            return Ok(());
         }
+        self.start_oneshot_worker(config);
+        self.oneshot_queue.push(QueuedTrace {
+            deadline: Instant::now() + trace.expiry,
+            trace,
+            config: config.clone(),
+            blocked_by,
+            counted_as_suppressed,
+        });
         Ok(())
     }
 
+    fn start_oneshot_worker(&self, config: &Config) {
+        self.oneshot_worker.call_once(|| {
+            let config = config.clone();
+            let recorder = self.recorder.clone();
+            let queue = self.oneshot_queue.clone();
+
+            thread::spawn(move || {
+                set_background(&config);
+                loop {
+                    let mut queued = queue.pop();
+                    let blocked_by = recorder.try_oneshot(
+                        &queued.config,
+                        &queued.trace,
+                        &mut queued.counted_as_suppressed,
+                    );
+                    if let Some(blocked_by) = blocked_by {
+                        // Traces without expiry are only handed off to be tried once.
+                        if queued.trace.expiry.is_zero() {
+                            queue.record_dropped(&queued.trace, &blocked_by);
+                            continue;
+                        }
+                        queued.blocked_by = blocked_by;
+                        queue.push_front(queued);
+                        thread::sleep(ONESHOT_RETRY_INTERVAL);
+                    }
+                }
+            });
+        });
+    }
+
     pub fn process(&self, config: &Config) -> Result<()> {
-        let trace_provider = self.trace_provider.clone();
-        trace_provider
-            .lock()
-            .unwrap()
-            .process(&TRACE_OUTPUT_DIR, &PROFILE_OUTPUT_DIR, &config.binary_filter)
+        run_in_background(config, || self.recorder.process(config))
             .context("Failed to process profiles.")?;
+        self.recorder.check_backlog(config);
         Ok(())
     }
 
-    pub fn get_trace_provider_name(&self) -> &'static str {
-        self.trace_provider.lock().unwrap().get_name()
+    /// Remove stale metadata, and evict traces and profiles over their storage limits. Returns the
+    /// number of bytes freed.
+    pub fn run_cleanup(&self, config: &Config) -> Result<u64> {
+        // Hold the provider, so that no trace is recorded or processed meanwhile.
+        let _provider = self.recorder.trace_provider.lock().unwrap();
+        let recorder = &self.recorder;
+        // The traces behind them were processed by an earlier run, under an unknown decision.
+        move_processed_metadata(&recorder.trace_dir(), &recorder.profile_dir(), None)
+            .context("Failed to move trace metadata.")?;
+        let mut freed = remove_orphaned_metadata(&recorder.profile_dir(), &CONFIG_FILE)?;
+        let weights = &config.eviction_weights;
+        let trace_limits = Limits::from_mb(config.max_trace_limit_mb);
+        freed += evict(weights, &trace_limits, list_candidates(&recorder.trace_dir())?)?;
+        let profile_limits = Limits::from_mb(config.max_profile_limit_mb);
+        freed += evict(weights, &profile_limits, list_candidates(&recorder.profile_dir())?)?;
+        self.recorder.check_backlog(config);
+        Ok(freed)
+    }
+
+    /// Audit the output directories every `config.audit_interval`.
+    pub fn start_audits(&self, config: &Config) {
+        if config.audit_interval.is_zero() {
+            return;
+        }
+        let config = config.clone();
+        let recorder = self.recorder.clone();
+        thread::spawn(move || {
+            set_background(&config);
+            loop {
+                thread::sleep(config.audit_interval);
+                // Hold the provider, so that no trace is recorded or processed meanwhile.
+                let provider = recorder.trace_provider.lock().unwrap();
+                let result = audit(
+                    &TRACE_OUTPUT_DIR,
+                    &PROFILE_OUTPUT_DIR,
+                    &REPORT_OUTPUT_DIR,
+                    &QUARANTINE_DIR,
+                    provider.get_name(),
+                );
+                if let Err(e) = &result {
+                    log::error!("Failed to audit output directories: {:?}", e);
+                }
+                recorder.audits.record(result);
+            }
+        });
+    }
+
+    /// Collect more often and for longer for `duration`, bounded by
+    /// `config.max_intensive_mode_duration`. Collection returns to the normal schedule afterwards,
+    /// or now if `duration` is zero.
+    pub fn set_intensive_mode(&self, config: &Config, duration: Duration) {
+        self.recorder.intensive_mode.set(duration.min(config.max_intensive_mode_duration));
+        // Wake the periodic worker, so that the wait for the next trace follows the new interval
+        // instead of the one it started with. A pending signal wakes it all the same.
+        if let Some(ch) = &self.termination_ch {
+            ch.try_send(PeriodicSignal::Reschedule).ok();
+        }
+    }
+
+    pub fn begin_blackout(&self, reason: &str, duration: Option<Duration>) {
+        self.recorder.blackouts.begin(reason, duration);
+        self.recorder.sync_suspended();
+    }
+
+    pub fn end_blackout(&self, reason: &str) -> Result<()> {
+        let result = self.recorder.blackouts.end(reason);
+        self.recorder.sync_suspended();
+        result
+    }
+
+    /// Hold a blackout while a backup or restore operation is in progress, so that no trace adds
+    /// to its I/O. The blackout expires after `MAX_BACKUP_DURATION`.
+    pub fn set_backup_in_progress(&self, in_progress: bool) {
+        if in_progress {
+            self.begin_blackout(BACKUP_REASON, Some(MAX_BACKUP_DURATION));
+        } else {
+            self.end_blackout(BACKUP_REASON).ok();
+        }
+    }
+
+    /// Reporter of the status of the scheduler under `config`.
+    pub fn status_reporter(&self, config: &Config) -> StatusReporter {
+        StatusReporter {
+            recorder: self.recorder.clone(),
+            oneshot_queue: self.oneshot_queue.clone(),
+            config: config.clone(),
+        }
+    }
+
+    /// Simulate the periodic collection of the next 24 hours from the current state, as a
+    /// human-readable timeline.
+    pub fn simulate_schedule(&self, config: &Config) -> String {
+        let now = Instant::now();
+        let recorder = &self.recorder;
+        let suppressed_by = if recorder.state.state() == State::Disabled {
+            Some("collection is disabled".to_string())
+        } else {
+            recorder.charge_budget.exhausted(config, Activity::Tracing)
+        };
+        // The backlog blackout is updated before each trace. Processing is not simulated, so it
+        // follows the backlog of now.
+        let mut blackouts = recorder.blackouts.active_windows();
+        blackouts.retain(|(reason, _)| reason != BACKLOG_REASON);
+        if get_backlog(&recorder.trace_dir())
+            .is_ok_and(|(count, bytes)| is_backlog_over_limit(config, count, bytes))
+        {
+            blackouts.push((BACKLOG_REASON.to_string(), None));
+        }
+        let state = SimulationState {
+            since_boot: time_since_boot(),
+            post_ota_remaining: self
+                .post_ota_deadline
+                .map(|d| d.saturating_duration_since(now))
+                .filter(|d| !d.is_zero()),
+            intensive_remaining: recorder.intensive_mode.remaining(),
+            blackouts,
+            suppressed_by,
+            cooldown_remaining: recorder
+                .last_trace_end
+                .lock()
+                .unwrap()
+                .map(|t| (t + config.min_trace_interval).saturating_duration_since(now))
+                .filter(|d| !d.is_zero()),
+            trace_storage_full: !recorder.has_trace_space(config),
+        };
+        let mut timeline = String::new();
+        if !self.is_scheduled() {
+            timeline.push_str("Periodic collection is not scheduled, assuming it is from now.\n");
+        }
+        let plan = simulate(config, &state, get_sampling_period());
+        timeline.push_str(&format_plan(config, &plan));
+        timeline
+    }
+
+    /// Get the storage used by the traces and profiles of the trace provider, by tag.
+    pub fn get_storage_usage(&self) -> Result<BTreeMap<String, TagUsage>> {
+        get_storage_usage(&self.recorder.trace_dir(), &self.recorder.profile_dir())
+    }
+
+    pub fn set_trace_clock(&self, config: &Config) {
+        self.recorder.trace_provider.lock().unwrap().set_trace_clock(config.trace_clock);
+    }
+
+    /// Log simpleperf output to LOG_FILE, if `config` keeps the trace log.
+    pub fn set_trace_log(&self, config: &Config) {
+        let provider = self.recorder.trace_provider.lock().unwrap();
+        if config.trace_log {
+            provider.set_log_file(&LOG_FILE);
+        } else {
+            provider.reset_log_file();
+        }
+    }
+
+    /// Summarize the processed profiles, see `profile_summary`.
+    pub fn summarize_profiles(&self, config: &Config, max_entries: usize) -> Result<String> {
+        run_in_background(config, || {
+            let provider = self.recorder.trace_provider.lock().unwrap();
+            profile_summary::summarize_profiles(
+                &**provider,
+                &self.recorder.profile_dir(),
+                &config.get_binary_filter(),
+                max_entries,
+            )
+        })
     }
 
     pub fn is_provider_ready(&self) -> bool {
-        self.trace_provider.lock().unwrap().is_ready()
+        self.recorder.trace_provider.lock().unwrap().is_ready()
     }
 
     pub fn register_provider_ready_callback(&self, cb: Box<dyn FnOnce() + Send>) {
@@ -157,7 +640,7 @@ impl Scheduler {
     }
 
     fn start_thread_waiting_for_provider_ready(&self) {
-        let provider = self.trace_provider.clone();
+        let provider = self.recorder.trace_provider.clone();
         let callbacks = self.provider_ready_callbacks.clone();
 
         thread::spawn(move || {
@@ -189,18 +672,534 @@ impl Scheduler {
         });
     }
 
+    /// Remove the simpleperf trace log. Logging stops until `set_trace_log`.
     pub fn clear_trace_log(&self) -> Result<()> {
-        let provider = self.trace_provider.lock().map_err(|e| anyhow!(e.to_string()))?;
+        let provider = self.recorder.trace_provider.lock().map_err(|e| anyhow!(e.to_string()))?;
         provider.reset_log_file();
         let mut result = Ok(());
         if LOG_FILE.exists() {
             result = fs::remove_file(*LOG_FILE).map_err(|e| anyhow!(e));
         }
-        provider.set_log_file(&LOG_FILE);
         result
     }
 }
 
+/// Status of the scheduler, read without holding the scheduler, so that the status can be shown
+/// while a long command, like processing, runs.
+pub struct StatusReporter {
+    recorder: Arc<Recorder>,
+    oneshot_queue: Arc<OneShotQueue>,
+    config: Config,
+}
+
+impl StatusReporter {
+    pub fn status(&self) -> String {
+        self.recorder.sync_suspended();
+        let mut status = format!(
+            "provider: {}\nbuild tier: {}\nstate: {}\n",
+            self.recorder.provider_name(),
+            self.config.build_tier.name(),
+            self.recorder.state.state()
+        );
+        if let Some(active) = self.recorder.active_trace.lock().unwrap().as_ref() {
+            status.push_str(&format!("tracing: {} ({})\n", active.tag, active.correlation_id));
+        }
+        status.push_str(&self.recorder.blackouts.status());
+        status.push_str(&self.recorder.kernel_warnings.status());
+        status.push_str(&self.recorder.provider_errors.status());
+        status.push_str(&self.recorder.provider_chain.status());
+        status.push_str(&self.recorder.perf_session_conflicts.status());
+        status.push_str(&ResolutionCache::load(&RESOLUTION_CACHE_FILE).status(SystemTime::now()));
+        status.push_str(&self.recorder.charge_budget.status());
+        status.push_str(&self.recorder.audits.status());
+        status.push_str(&self.recorder.intensive_mode.status());
+        status.push_str(&self.recorder.clock_watch.status());
+        status.push_str(&self.oneshot_queue.status());
+        status.push_str(&log_budget::status());
+        match get_storage_usage(&self.recorder.trace_dir(), &self.recorder.profile_dir()) {
+            Ok(usage) => status.push_str(&storage::status(&usage)),
+            Err(e) => status.push_str(&format!("storage: unavailable ({})\n", e)),
+        }
+        status
+    }
+
+    /// Name of the trace provider in use.
+    pub fn provider_name(&self) -> &'static str {
+        self.recorder.provider_name()
+    }
+
+    /// The config the status is reported under.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+}
+
+impl Recorder {
+    /// Keep the Suspended state in line with the blackout windows, which may expire at any time.
+    fn sync_suspended(&self) {
+        let suspended = self.blackouts.active_reasons().is_some();
+        if let Err(e) = self.state.set_suspended(suspended) {
+            log::error!("{:?}", e);
+        }
+    }
+
+    /// Pause collection with a blackout while the backlog of unprocessed traces is over its
+    /// limits, instead of piling up traces which may never be processed. Collection resumes once
+    /// processing catches up.
+    fn check_backlog(&self, config: &Config) {
+        let (count, bytes) = match get_backlog(&self.trace_dir()) {
+            Ok(backlog) => backlog,
+            Err(e) => {
+                log::error!("Failed to check unprocessed traces: {:?}", e);
+                return;
+            }
+        };
+        let over_limit = is_backlog_over_limit(config, count, bytes);
+        let paused = self.blackouts.is_active(BACKLOG_REASON);
+        if over_limit && !paused {
+            log::info!("{} unprocessed traces ({} bytes), pausing collection.", count, bytes);
+            self.blackouts.begin(BACKLOG_REASON, None);
+        } else if !over_limit && paused {
+            self.blackouts.end(BACKLOG_REASON).ok();
+        }
+        self.sync_suspended();
+    }
+
+    /// Returns whether a trace should be suppressed, counting blackout suppressions.
+    fn suppress(&self, config: &Config, tag: &str) -> bool {
+        self.check_backlog(config);
+        if self.state.is_disabled() {
+            log::info!("Trace {} suppressed, collection is disabled.", tag);
+            return true;
+        }
+        if let Some(reason) = self.charge_budget.exhausted(config, Activity::Tracing) {
+            log::info!("Trace {} suppressed: {}", tag, reason);
+            return true;
+        }
+        self.blackouts.suppress(tag)
+    }
+
+    /// Whether the trace directory is under its space limit. Failing to tell is logged and taken as
+    /// no space, the trace is skipped but collection goes on, as the error may be transient.
+    fn has_trace_space(&self, config: &Config) -> bool {
+        check_space_limit(&self.trace_dir(), config)
+            .map_err(|e| log::error!("Failed to check trace storage: {:?}", e))
+            .unwrap_or(false)
+    }
+
+    /// Try to perform a one-shot trace, returning what blocked it if it could not be performed. A
+    /// trace blocked by a blackout is counted as suppressed unless `counted_as_suppressed` tells it
+    /// already was, on an earlier try.
+    fn try_oneshot(
+        &self,
+        config: &Config,
+        trace: &OneShotTrace,
+        counted_as_suppressed: &mut bool,
+    ) -> Option<String> {
+        if self.state.is_disabled() {
+            return Some("collection disabled".to_string());
+        }
+        self.check_backlog(config);
+        if let Some(reasons) = self.blackouts.active_reasons() {
+            if !*counted_as_suppressed {
+                self.blackouts.count_suppressed(&trace.tag, &reasons);
+                *counted_as_suppressed = true;
+            }
+            return Some(format!("blackout: {}", reasons));
+        }
+        if let Some(reason) = self.charge_budget.exhausted(config, Activity::Tracing) {
+            return Some(reason);
+        }
+        let (tag, id) = (trace.tag.as_str(), trace.correlation_id.as_str());
+        let sampling_period = self.trace_duration(config, trace.sampling_period);
+        let in_memory = sampling_period <= config.in_memory_trace_max_sampling_period;
+        let annotations = &trace.annotations;
+        let result = if trace.processes.is_empty() {
+            self.record(config, tag, id, "", false, in_memory, annotations, |p, in_memory| {
+                p.trace_system(
+                    &self.trace_dir(),
+                    tag,
+                    id,
+                    in_memory,
+                    &sampling_period,
+                    &config.get_binary_filter(),
+                )
+            })
+        } else {
+            let processes = &trace.processes;
+            self.record(
+                config,
+                tag,
+                id,
+                processes,
+                false,
+                in_memory,
+                annotations,
+                |p, in_memory| {
+                    p.trace_process(
+                        &self.trace_dir(),
+                        tag,
+                        id,
+                        in_memory,
+                        &sampling_period,
+                        processes,
+                    )
+                },
+            )
+        };
+        result.err()
+    }
+
+    /// Record a trace through `record`, and write its provenance metadata next to the trace file.
+    /// A request arriving while another trace is being recorded is handled according to the
+    /// concurrent trace policy: it either waits for the provider, or is merged into the running
+    /// trace. If `wait` is false, returns an error instead of waiting for the provider. Returns
+    /// what prevented the trace from starting, if anything. `processes` are the traced processes,
+    /// empty for a system-wide trace. If `in_memory` is true, the trace is recorded into a memfd
+    /// given to `record`, and only written to the trace directory if it is retained. A trace
+    /// failing for a transient provider error is returned as blocked, so that it can be retried.
+    #[allow(clippy::too_many_arguments)]
+    fn record<F>(
+        &self,
+        config: &Config,
+        tag: &str,
+        correlation_id: &str,
+        processes: &str,
+        wait: bool,
+        in_memory: bool,
+        annotations: &Annotations,
+        record: F,
+    ) -> Result<(), String>
+    where
+        F: FnOnce(&dyn TraceProvider, Option<&InMemoryTrace>) -> Result<Box<Path>>,
+    {
+        if let Some(active) = self.active_trace.lock().unwrap().as_mut() {
+            if config.concurrent_trace_policy == ConcurrentTracePolicy::Merge && active.system_wide
+            {
+                log::info!(
+                    "Merging {} trace {} into running {} trace {}.",
+                    tag,
+                    correlation_id,
+                    active.tag,
+                    active.correlation_id
+                );
+                active.merged_tags.push(tag.to_string());
+                active.merged_correlation_ids.push(correlation_id.to_string());
+                merge_annotations(&mut active.annotations, annotations);
+                return Ok(());
+            }
+            if !wait {
+                return Err("another trace".to_string());
+            }
+            log::info!(
+                "Queuing {} trace {} behind running {} trace {}.",
+                tag,
+                correlation_id,
+                active.tag,
+                active.correlation_id
+            );
+        }
+
+        // Hold the provider for the whole recording, so that the trace is not processed before
+        // its metadata is written.
+        let mut provider = if wait {
+            self.trace_provider.lock().unwrap()
+        } else {
+            match self.trace_provider.try_lock() {
+                Ok(provider) => provider,
+                Err(TryLockError::WouldBlock) => return Err("another trace".to_string()),
+                Err(TryLockError::Poisoned(e)) => panic!("{}", e),
+            }
+        };
+        // Keep the cooldown after the last trace, whatever triggered this one.
+        let last_trace_end = *self.last_trace_end.lock().unwrap();
+        if let Some(remaining) = last_trace_end
+            .map(|t| (t + config.min_trace_interval).saturating_duration_since(Instant::now()))
+            .filter(|remaining| !remaining.is_zero())
+        {
+            return Err(format!("cooldown, {:.1}s left", remaining.as_secs_f32()));
+        }
+        if config.defer_to_perfetto {
+            let session = active_perf_session();
+            // Tracing goes on when sessions are undetectable, as perfetto may well be idle.
+            self.perf_session_conflicts.set_undetectable(match &session {
+                Some(PerfSession::Undetectable(process)) => Some(process),
+                _ => None,
+            });
+            if let Some(PerfSession::Active(session)) = session {
+                self.perf_session_conflicts.record(&session);
+                return Err(format!("perfetto perf session of {}", session));
+            }
+        }
+        if in_call() {
+            return Err("voice call".to_string());
+        }
+        let _tracing = self.state.begin(State::Tracing).map_err(|e| e.to_string())?;
+        *self.active_trace.lock().unwrap() = Some(ActiveTrace {
+            tag: tag.to_string(),
+            correlation_id: correlation_id.to_string(),
+            system_wide: processes.is_empty(),
+            merged_tags: Vec::new(),
+            merged_correlation_ids: Vec::new(),
+            annotations: annotations.clone(),
+        });
+        log::info!("Recording {} trace {}.", tag, correlation_id);
+        let mut metadata = TraceMetadata::new(tag, correlation_id, provider.get_name(), config);
+        metadata.provider_rung = self.provider_chain.active_rung();
+        let kernel_log = match config.max_traces_with_kernel_warnings {
+            0 => None,
+            _ => KernelLogMonitor::start()
+                .map_err(|e| log::error!("Failed to watch kernel log: {:?}", e))
+                .ok(),
+        };
+        let in_memory = in_memory.then(InMemoryTrace::new).and_then(|r| {
+            r.map_err(|e| log::error!("Failed to record in memory, using disk: {:?}", e)).ok()
+        });
+        let start = Instant::now();
+        let result = inject_fault(FaultPoint::ProviderStart)
+            .and_then(|_| record(&**provider, in_memory.as_ref()));
+        if let Some(reason) = self.provider_chain.record(&result) {
+            self.fall_back(config, &mut provider, &reason);
+        }
+        *self.last_trace_end.lock().unwrap() = Some(Instant::now());
+        // Only the time actually spent is charged, as the trace ends early if the traced processes
+        // exit.
+        let elapsed = start.elapsed();
+        self.charge_budget.spend(Activity::Tracing, elapsed);
+        if !processes.is_empty() && !any_process_alive(processes) {
+            log::info!(
+                "Traced processes exited, {} trace {} ended after {:?}.",
+                tag,
+                correlation_id,
+                elapsed
+            );
+            metadata.processes_exited = true;
+        }
+        if let Some(kernel_log) = kernel_log {
+            let traces = self.kernel_warnings.record(tag, kernel_log.finish());
+            if traces >= config.max_traces_with_kernel_warnings {
+                self.state.disable(&format!("kernel warnings logged during {} traces", traces));
+            }
+        }
+        if let Some(active) = self.active_trace.lock().unwrap().take() {
+            metadata.merged_tags = active.merged_tags;
+            metadata.merged_correlation_ids = active.merged_correlation_ids;
+            metadata.annotations = active.annotations;
+        }
+        if let Err(e) = result.and_then(|trace_file| {
+            inject_fault(FaultPoint::TraceWrite)?;
+            let recorded = trace_provider::output_path(&trace_file, in_memory.as_ref());
+            metadata.data_loss_percent = provider.get_data_loss(&recorded);
+            if let Some(data_loss_percent) = metadata.data_loss_percent {
+                provider.adjust_to_data_loss(config, data_loss_percent);
+            }
+            if let Some(in_memory) = in_memory {
+                ensure!(
+                    !metadata.apexes_updated(),
+                    "APEXes were updated during the trace, discarded."
+                );
+                if self.evicted_on_arrival(config, &trace_file, &in_memory, &metadata)? {
+                    log::info!(
+                        "Discarded {} trace {} recorded in memory, it would be evicted right away.",
+                        tag,
+                        correlation_id
+                    );
+                    return Ok(());
+                }
+                in_memory.spill(&trace_file)?;
+            }
+            metadata.finish(&trace_file)
+        }) {
+            log::error!("Failed to record {} trace {}: {:?}", tag, correlation_id, e);
+            if let Some(e) = e.downcast_ref::<ProviderError>() {
+                self.provider_errors.record(e.kind);
+                if e.kind.is_transient() {
+                    return Err(format!("provider {}", e.kind.name()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the failed `provider` with the next one of the chain, carrying over the settings of
+    /// `config`. Collection is disabled if no provider is left.
+    fn fall_back(
+        &self,
+        config: &Config,
+        provider: &mut Box<dyn TraceProvider + Send>,
+        reason: &str,
+    ) {
+        let next = trace_provider::get_trace_provider(&self.provider_chain);
+        let dirs = match ProviderDirs::create(next.get_name()) {
+            Ok(dirs) => dirs,
+            Err(e) => {
+                self.state.disable(&format!("{}, failed to fall back: {:?}", reason, e));
+                return;
+            }
+        };
+        if next.get_name() == DISABLED_PROVIDER_NAME {
+            self.state.disable(&format!("{}, {}", reason, NO_PROVIDER_LEFT));
+        } else {
+            log::warn!("{}, falling back to {}.", reason, next.get_name());
+        }
+        next.set_trace_clock(config.trace_clock);
+        if config.trace_log {
+            next.set_log_file(&LOG_FILE);
+        }
+        *provider = next;
+        *self.dirs.write().unwrap() = dirs;
+    }
+
+    fn provider_name(&self) -> &'static str {
+        self.dirs.read().unwrap().provider
+    }
+
+    fn trace_dir(&self) -> PathBuf {
+        self.dirs.read().unwrap().trace_dir.clone()
+    }
+
+    fn profile_dir(&self) -> PathBuf {
+        self.dirs.read().unwrap().profile_dir.clone()
+    }
+
+    /// Whether a trace recorded in memory would be evicted from the trace directory as soon as it
+    /// is written to `trace_file`.
+    fn evicted_on_arrival(
+        &self,
+        config: &Config,
+        trace_file: &Path,
+        in_memory: &InMemoryTrace,
+        metadata: &TraceMetadata,
+    ) -> Result<bool> {
+        let candidate = Candidate {
+            files: vec![trace_file.to_path_buf()],
+            bytes: in_memory.size()?,
+            age: Duration::ZERO,
+            data_loss_percent: metadata.data_loss_percent.unwrap_or(0.0),
+            tag: Some(metadata.tag.clone()),
+        };
+        Ok(evicted_on_arrival(
+            &config.eviction_weights,
+            &Limits::from_mb(config.max_trace_limit_mb),
+            list_candidates(&self.trace_dir())?,
+            &candidate,
+        ))
+    }
+
+    /// Record a trace of system_server and the system processes forked from zygote.
+    fn trace_system_server_processes(
+        &self,
+        config: &Config,
+        correlation_id: &str,
+    ) -> Result<(), String> {
+        let processes = match get_system_server_processes() {
+            Ok(processes) if !processes.is_empty() => processes,
+            Ok(_) => return Err("no system_server process found".to_string()),
+            Err(e) => return Err(format!("failed to find system_server processes: {:?}", e)),
+        };
+        let (tag, annotations) = (SYSTEM_SERVER_TAG, Annotations::new());
+        let sampling_period = self.trace_duration(config, get_sampling_period());
+        self.record(config, tag, correlation_id, &processes, true, false, &annotations, |p, _| {
+            p.trace_process(
+                &self.trace_dir(),
+                tag,
+                correlation_id,
+                None,
+                &sampling_period,
+                &processes,
+            )
+        })
+    }
+
+    /// The duration of a trace of `requested` duration, clamped to the tracing budget left.
+    fn trace_duration(&self, config: &Config, requested: Duration) -> Duration {
+        let duration = self.charge_budget.clamp(config, Activity::Tracing, requested);
+        if duration < requested {
+            log::info!("Trace clamped to the {:?} left of the tracing budget.", duration);
+        }
+        duration
+    }
+
+    fn process(&self, config: &Config) -> Result<()> {
+        let provider = self.trace_provider.lock().unwrap();
+        if let Some(reason) = self.charge_budget.exhausted(config, Activity::Processing) {
+            log::info!("Processing skipped: {}", reason);
+            return Ok(());
+        }
+        let _processing = self.state.begin(State::Processing)?;
+        inject_fault(FaultPoint::Processing)?;
+        let start = Instant::now();
+        let start_cpu_time = process_cpu_time();
+        let backlog = get_backlog_by_tag(&self.trace_dir())?;
+        let pipeline = ProcessingPipeline::new(config).unwrap_or_else(|e| {
+            log::error!("Invalid processing stages, using the default ones: {:?}", e);
+            ProcessingPipeline::default()
+        });
+        // Binaries which repeatedly failed to resolve are skipped, the failures are told from the
+        // simpleperf logs of the run.
+        let now = SystemTime::now();
+        let mut resolution_cache = ResolutionCache::load(&RESOLUTION_CACHE_FILE);
+        let (result, log) = collect_logs(MAX_PROCESSING_LOG_BYTES, || {
+            pipeline.run(&mut StageContext::new(
+                &**provider,
+                config,
+                &self.trace_dir(),
+                &self.profile_dir(),
+                &config.get_binary_filter(),
+                Some(&resolution_cache),
+            ))
+        });
+        resolution_cache.record(&log, now);
+        if let Err(e) = resolution_cache.save(&RESOLUTION_CACHE_FILE) {
+            log::error!("Failed to save resolution cache: {:?}", e);
+        }
+        self.charge_budget.spend(Activity::Processing, start.elapsed());
+        let remaining = get_backlog_by_tag(&self.trace_dir())?;
+        let processed: BTreeMap<String, u64> = backlog
+            .into_iter()
+            .map(|(tag, bytes)| {
+                let remaining_bytes = remaining.get(&tag).copied().unwrap_or(0);
+                (tag, bytes.saturating_sub(remaining_bytes))
+            })
+            .filter(|(_, bytes)| *bytes > 0)
+            .collect();
+        let cpu_time = process_cpu_time().saturating_sub(start_cpu_time);
+        if let Err(e) = ProcessingStats::record(&PROCESSING_STATS_FILE, &processed, cpu_time) {
+            log::error!("Failed to record processing stats: {:?}", e);
+        }
+        result
+    }
+}
+
+/// Mark `annotations` as those of a post-OTA trace. The mark is added even if the annotations of
+/// the request are at their limit, which only bounds the annotations of callers.
+fn mark_post_ota(annotations: &mut Annotations) {
+    annotations.insert(POST_OTA_ANNOTATION.to_string(), "true".to_string());
+}
+
+fn in_post_ota_window(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|d| Instant::now() < d)
+}
+
+/// Whether any of the comma-separated `processes` is alive. Processes given by name are assumed
+/// alive.
+fn any_process_alive(processes: &str) -> bool {
+    processes.split(',').map(str::trim).any(|process| {
+        process.parse::<u32>().map_or(true, |pid| Path::new("/proc").join(pid.to_string()).exists())
+    })
+}
+
+/// Whether `count` unprocessed traces of `bytes` are over the backlog limits of `config`.
+fn is_backlog_over_limit(config: &Config, count: u32, bytes: u64) -> bool {
+    (config.max_unprocessed_traces > 0 && count >= config.max_unprocessed_traces)
+        || (config.max_unprocessed_trace_mb > 0
+            && bytes > config.max_unprocessed_trace_mb * 1024 * 1024)
+}
+
+fn in_boot_warmup(config: &Config) -> bool {
+    time_since_boot() < config.boot_warmup_period
+}
+
 /// Run if space usage is under limit.
 fn check_space_limit(path: &Path, config: &Config) -> Result<bool> {
     // Returns the size of a directory, non-recursive.
@@ -218,3 +1217,204 @@ fn check_space_limit(path: &Path, config: &Config) -> Result<bool> {
     }
     Ok(true)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex, Once, RwLock};
+    use std::time::{Duration, Instant};
+
+    use super::{
+        is_backlog_over_limit, ChargeBudget, ProviderDirs, Recorder, Scheduler, POST_OTA_ANNOTATION,
+    };
+    use crate::blackout::BACKLOG_REASON;
+    use crate::charge_budget::MemoryStore;
+    use crate::config::Config;
+    use crate::logging_trace_provider::LoggingTraceProvider;
+    use crate::oneshot_queue::{OneShotQueue, OneShotTrace, Priority};
+    use crate::provider_chain::ProviderChain;
+    use crate::scheduler_state::State;
+    use crate::trace_metadata::{is_metadata, new_correlation_id, Annotations, TraceMetadata};
+
+    /// A recorder with the logging trace provider, keeping its traces and profiles under `root`.
+    fn test_recorder(config: &Config, root: &Path) -> Recorder {
+        Recorder {
+            trace_provider: Arc::new(Mutex::new(Box::new(LoggingTraceProvider {}))),
+            dirs: RwLock::new(ProviderDirs {
+                provider: "logging",
+                trace_dir: root.join("trace"),
+                profile_dir: root.join("output"),
+            }),
+            state: Default::default(),
+            blackouts: Default::default(),
+            active_trace: Mutex::new(None),
+            last_trace_end: Mutex::new(None),
+            kernel_warnings: Default::default(),
+            charge_budget: ChargeBudget::load(Box::<MemoryStore>::default()),
+            audits: Default::default(),
+            intensive_mode: Default::default(),
+            clock_watch: Default::default(),
+            provider_errors: Default::default(),
+            provider_chain: ProviderChain::load(config),
+            perf_session_conflicts: Default::default(),
+        }
+    }
+
+    fn test_trace() -> OneShotTrace {
+        OneShotTrace {
+            tag: "test".to_string(),
+            processes: String::new(),
+            sampling_period: Duration::from_millis(10),
+            expiry: Duration::from_secs(1),
+            annotations: Annotations::new(),
+            priority: Priority::Normal,
+            correlation_id: new_correlation_id(),
+        }
+    }
+
+    #[test]
+    fn oneshot_is_blocked_while_disabled() {
+        let config = Config::from_env().unwrap();
+        let root = temp_dir().join(format!("profcollectd_scheduler_{}", new_correlation_id()));
+        let recorder = test_recorder(&config, &root);
+        fs::create_dir_all(recorder.trace_dir()).unwrap();
+        recorder.state.disable("test");
+
+        let blocked_by = recorder.try_oneshot(&config, &test_trace(), &mut false);
+        assert_eq!(blocked_by.as_deref(), Some("collection disabled"));
+        assert_eq!(fs::read_dir(recorder.trace_dir()).unwrap().count(), 0);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn blackouts_suspend_collection() {
+        let config = Config::from_env().unwrap();
+        let root = temp_dir().join(format!("profcollectd_scheduler_{}", new_correlation_id()));
+        let recorder = test_recorder(&config, &root);
+        fs::create_dir_all(recorder.trace_dir()).unwrap();
+        assert!(!recorder.suppress(&config, "test"));
+
+        recorder.blackouts.begin("lockdown", None);
+        recorder.sync_suspended();
+        assert_eq!(recorder.state.state(), State::Suspended);
+        assert!(recorder.suppress(&config, "test"));
+
+        recorder.blackouts.end("lockdown").unwrap();
+        recorder.sync_suspended();
+        assert_eq!(recorder.state.state(), State::Idle);
+        assert!(!recorder.suppress(&config, "test"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn oneshot_blocked_by_blackout_is_counted_once() {
+        let config = Config::from_env().unwrap();
+        let root = temp_dir().join(format!("profcollectd_scheduler_{}", new_correlation_id()));
+        let recorder = test_recorder(&config, &root);
+        fs::create_dir_all(recorder.trace_dir()).unwrap();
+        recorder.blackouts.begin("test", None);
+
+        // Retries of a queued request share its flag.
+        let mut counted_as_suppressed = false;
+        for _ in 0..3 {
+            let blocked_by =
+                recorder.try_oneshot(&config, &test_trace(), &mut counted_as_suppressed);
+            assert_eq!(blocked_by.as_deref(), Some("blackout: test"));
+        }
+        assert!(counted_as_suppressed);
+        recorder.try_oneshot(&config, &test_trace(), &mut false);
+        assert!(recorder.blackouts.status().contains("traces suppressed by blackouts: 2"));
+        assert_eq!(fs::read_dir(recorder.trace_dir()).unwrap().count(), 0);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn storage_errors_do_not_disable_collection() {
+        let config = Config::from_env().unwrap();
+        let root = temp_dir().join(format!("profcollectd_scheduler_{}", new_correlation_id()));
+        let recorder = test_recorder(&config, &root);
+
+        // The trace directory does not exist, so its size cannot be read.
+        assert!(!recorder.has_trace_space(&config));
+        assert!(!recorder.state.is_disabled());
+        fs::create_dir_all(recorder.trace_dir()).unwrap();
+        assert!(recorder.has_trace_space(&config));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn backlog_limits() {
+        let mut config = Config::from_env().unwrap();
+        config.max_unprocessed_traces = 3;
+        config.max_unprocessed_trace_mb = 1;
+        assert!(!is_backlog_over_limit(&config, 2, 1024 * 1024));
+        assert!(is_backlog_over_limit(&config, 3, 0));
+        assert!(is_backlog_over_limit(&config, 1, 1024 * 1024 + 1));
+
+        // A limit of zero is no limit.
+        config.max_unprocessed_traces = 0;
+        config.max_unprocessed_trace_mb = 0;
+        assert!(!is_backlog_over_limit(&config, 1000, u64::MAX));
+    }
+
+    #[test]
+    fn backlog_pauses_collection_until_processed() {
+        let mut config = Config::from_env().unwrap();
+        config.max_unprocessed_traces = 2;
+        config.max_unprocessed_trace_mb = 0;
+        let root = temp_dir().join(format!("profcollectd_scheduler_{}", new_correlation_id()));
+        let recorder = test_recorder(&config, &root);
+        let trace_dir = recorder.trace_dir();
+        fs::create_dir_all(&trace_dir).unwrap();
+
+        // Metadata sidecars are not part of the backlog.
+        fs::write(trace_dir.join("a.logging"), "trace").unwrap();
+        fs::write(trace_dir.join("a.json"), "{}").unwrap();
+        assert!(!recorder.suppress(&config, "test"));
+        assert!(!recorder.blackouts.is_active(BACKLOG_REASON));
+
+        fs::write(trace_dir.join("b.logging"), "trace").unwrap();
+        assert!(recorder.suppress(&config, "test"));
+        assert!(recorder.blackouts.is_active(BACKLOG_REASON));
+        assert_eq!(recorder.state.state(), State::Suspended);
+
+        fs::remove_file(trace_dir.join("b.logging")).unwrap();
+        assert!(!recorder.suppress(&config, "test"));
+        assert!(!recorder.blackouts.is_active(BACKLOG_REASON));
+        assert_eq!(recorder.state.state(), State::Idle);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn post_ota_traces_keep_their_tag() {
+        let config = Config::from_env().unwrap();
+        let root = temp_dir().join(format!("profcollectd_scheduler_{}", new_correlation_id()));
+        let scheduler = Scheduler {
+            termination_ch: None,
+            recorder: Arc::new(test_recorder(&config, &root)),
+            provider_ready_callbacks: Default::default(),
+            post_ota_deadline: Some(Instant::now() + Duration::from_secs(3600)),
+            oneshot_queue: Arc::new(OneShotQueue::default()),
+            oneshot_worker: Once::new(),
+        };
+        let trace_dir = scheduler.recorder.trace_dir();
+        fs::create_dir_all(&trace_dir).unwrap();
+
+        scheduler.trace_once(&config, test_trace()).unwrap();
+        let metadata: Vec<TraceMetadata> = fs::read_dir(&trace_dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| is_metadata(p))
+            .map(|p| serde_json::from_str(&fs::read_to_string(p).unwrap()).unwrap())
+            .collect();
+        assert_eq!(metadata.len(), 1);
+        assert_eq!(metadata[0].tag, "test");
+        assert_eq!(
+            metadata[0].annotations.get(POST_OTA_ANNOTATION).map(String::as_str),
+            Some("true")
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
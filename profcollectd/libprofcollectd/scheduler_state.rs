@@ -0,0 +1,217 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Explicit state machine of the tracing scheduler.
+
+use anyhow::{ensure, Result};
+use std::fmt;
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum State {
+    /// Periodic collection is not scheduled, and nothing is running.
+    Idle,
+    /// Periodic collection is scheduled, and nothing is running.
+    Scheduled,
+    /// A trace is being recorded.
+    Tracing,
+    /// Traces are being processed.
+    Processing,
+    /// Collection is paused, e.g. by a blackout. Traces can still be processed.
+    Suspended,
+    /// Collection is disabled until profcollectd restarts. Traces can still be processed.
+    Disabled,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            State::Idle => "idle",
+            State::Scheduled => "scheduled",
+            State::Tracing => "tracing",
+            State::Processing => "processing",
+            State::Suspended => "suspended",
+            State::Disabled => "disabled",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn is_valid_transition(from: State, to: State) -> bool {
+    use State::*;
+    match from {
+        Idle | Scheduled => true,
+        Tracing | Processing => matches!(to, Idle | Scheduled | Suspended | Disabled),
+        Suspended => matches!(to, Idle | Scheduled | Processing | Disabled),
+        Disabled => to == Processing,
+    }
+}
+
+/// The conditions the state is derived from.
+#[derive(Clone, Copy, Default)]
+struct Conditions {
+    scheduled: bool,
+    suspended: bool,
+    disabled: bool,
+    /// Tracing or Processing, None if nothing is running.
+    activity: Option<State>,
+}
+
+impl Conditions {
+    fn state(&self) -> State {
+        if let Some(activity) = self.activity {
+            activity
+        } else if self.disabled {
+            State::Disabled
+        } else if self.suspended {
+            State::Suspended
+        } else if self.scheduled {
+            State::Scheduled
+        } else {
+            State::Idle
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct StateMachine {
+    conditions: Mutex<Conditions>,
+}
+
+impl StateMachine {
+    pub fn state(&self) -> State {
+        self.conditions.lock().unwrap().state()
+    }
+
+    /// Whether periodic collection is scheduled, whatever is running.
+    pub fn is_scheduled(&self) -> bool {
+        self.conditions.lock().unwrap().scheduled
+    }
+
+    /// Whether collection is disabled, whatever is running.
+    pub fn is_disabled(&self) -> bool {
+        self.conditions.lock().unwrap().disabled
+    }
+
+    pub fn set_scheduled(&self, scheduled: bool) -> Result<()> {
+        self.update(|c| {
+            ensure!(
+                c.scheduled != scheduled,
+                "Already {}.",
+                if scheduled { "scheduled" } else { "unscheduled" }
+            );
+            c.scheduled = scheduled;
+            Ok(())
+        })
+    }
+
+    pub fn set_suspended(&self, suspended: bool) -> Result<()> {
+        self.update(|c| {
+            c.suspended = suspended;
+            Ok(())
+        })
+    }
+
+    /// Disable collection until profcollectd restarts.
+    pub fn disable(&self, reason: &str) {
+        log::error!("Collection disabled: {}", reason);
+        self.update(|c| {
+            c.disabled = true;
+            Ok(())
+        })
+        .expect("Collection can always be disabled.");
+    }
+
+    /// Enter `activity`, Tracing or Processing, until the returned guard is dropped.
+    pub fn begin(&self, activity: State) -> Result<ActivityGuard<'_>> {
+        self.update(|c| {
+            ensure!(
+                matches!(activity, State::Tracing | State::Processing),
+                "{} is not an activity.",
+                activity
+            );
+            ensure!(c.activity.is_none(), "Cannot start {} while {}.", activity, c.state());
+            c.activity = Some(activity);
+            Ok(())
+        })?;
+        Ok(ActivityGuard { machine: self })
+    }
+
+    /// Apply `change` to the conditions if the resulting state transition is valid.
+    fn update<F>(&self, change: F) -> Result<()>
+    where
+        F: FnOnce(&mut Conditions) -> Result<()>,
+    {
+        let mut conditions = self.conditions.lock().unwrap();
+        let mut next = *conditions;
+        change(&mut next)?;
+        let (from, to) = (conditions.state(), next.state());
+        if from != to {
+            ensure!(is_valid_transition(from, to), "Invalid state transition {} -> {}.", from, to);
+            log::info!("Scheduler state: {} -> {}", from, to);
+        }
+        *conditions = next;
+        Ok(())
+    }
+}
+
+/// Leaves the activity when dropped.
+pub struct ActivityGuard<'a> {
+    machine: &'a StateMachine,
+}
+
+impl Drop for ActivityGuard<'_> {
+    fn drop(&mut self) {
+        self.machine
+            .update(|c| {
+                c.activity = None;
+                Ok(())
+            })
+            .expect("An activity can always end.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{State, StateMachine};
+
+    #[test]
+    fn schedules_once() {
+        let machine = StateMachine::default();
+        machine.set_scheduled(true).unwrap();
+        assert!(machine.is_scheduled());
+        assert_eq!(machine.state(), State::Scheduled);
+        assert!(machine.set_scheduled(true).is_err());
+        machine.set_scheduled(false).unwrap();
+        assert!(!machine.is_scheduled());
+        assert!(machine.set_scheduled(false).is_err());
+    }
+
+    #[test]
+    fn stays_disabled_through_processing() {
+        let machine = StateMachine::default();
+        machine.set_scheduled(true).unwrap();
+        machine.disable("test");
+        assert!(machine.begin(State::Tracing).is_err());
+        let processing = machine.begin(State::Processing).unwrap();
+        assert_eq!(machine.state(), State::Processing);
+        assert!(machine.is_disabled());
+        drop(processing);
+        assert_eq!(machine.state(), State::Disabled);
+        // Still scheduled, the periodic worker keeps running and skips its traces.
+        assert!(machine.is_scheduled());
+    }
+}
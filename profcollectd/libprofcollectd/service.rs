@@ -18,29 +18,100 @@
 
 use anyhow::{anyhow, Context, Error, Result};
 use binder::Result as BinderResult;
-use binder::{SpIBinder, Status};
-use profcollectd_aidl_interface::aidl::com::android::server::profcollect::IProfCollectd::IProfCollectd;
+use binder::{SpIBinder, Status, StatusCode, ThreadState};
+use profcollectd_aidl_interface::aidl::com::android::server::profcollect::HardwareCapabilities::HardwareCapabilities;
+use profcollectd_aidl_interface::aidl::com::android::server::profcollect::IProfCollectd::{
+    IProfCollectd, ERROR_BUSY, ERROR_FAILED, FEATURE_ARM_BOOT_TRACE, FEATURE_BACKUP_STATE,
+    FEATURE_BLACKOUT, FEATURE_CALL_STATE, FEATURE_CLEAR_PRIVATE_DATA, FEATURE_COMMAND_QUEUE,
+    FEATURE_DIAGNOSTICS_BUNDLE, FEATURE_HARDWARE_CAPABILITIES, FEATURE_INTENSIVE_MODE,
+    FEATURE_PROFILE_SUMMARY, FEATURE_PROVIDER_STATUS_CALLBACK, FEATURE_REPORT_CHUNKS,
+    FEATURE_RUN_CLEANUP, FEATURE_SCHEDULE_SIMULATION, FEATURE_STORAGE_USAGE, FEATURE_TAG_REPORTS,
+    FEATURE_TRACE_ONCE, FEATURE_TRACE_PRIORITY, FEATURE_ZYGOTE_PHASE,
+};
 use profcollectd_aidl_interface::aidl::com::android::server::profcollect::IProviderStatusCallback::IProviderStatusCallback;
-use std::ffi::CString;
-use std::fs::{read_dir, read_to_string, remove_file, write};
+use profcollectd_aidl_interface::aidl::com::android::server::profcollect::ProfileFreshness::ProfileFreshness;
+use profcollectd_aidl_interface::aidl::com::android::server::profcollect::ReportResult::ReportResult;
+use profcollectd_aidl_interface::aidl::com::android::server::profcollect::TagStorageUsage::TagStorageUsage;
+use profcollectd_aidl_interface::aidl::com::android::server::profcollect::TracePriority::TracePriority;
+use profcollectd_aidl_interface::aidl::com::android::server::profcollect::TraceRequest::TraceRequest;
+use profcollectd_aidl_interface::aidl::com::android::server::profcollect::ZygotePhase::ZygotePhase;
+use std::ffi::{CStr, CString};
+use std::fs::{read_to_string, write};
+use std::io::Write;
 use std::str::FromStr;
 use std::sync::{Mutex, MutexGuard};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use crate::command_queue::{Busy, CommandGuard, CommandQueue};
 use crate::config::{
-    clear_data, Config, CONFIG_FILE, PROFILE_OUTPUT_DIR, REPORT_OUTPUT_DIR, REPORT_RETENTION_SECS,
+    clear_data, get_sampling_period, reset_report_identifiers, set_boot_trace_flag,
+    take_boot_trace_flag, take_post_ota_flag, Config, DeviceIdentifiers, CONFIG_FILE,
+    PROFILE_OUTPUT_DIR, REPORT_OUTPUT_DIR,
+};
+use crate::diagnostics::build_diagnostics_bundle;
+use crate::hardware::get_capabilities;
+use crate::oneshot_queue::{OneShotTrace, Priority};
+use crate::profile_summary::DEFAULT_SUMMARY_ENTRIES;
+use crate::report::{
+    evict_reports, freshness_status, get_profile_freshness, pack_report, pack_report_for_tags,
+    read_report_chunk, ReportInfo,
 };
-use crate::report::{get_report_ts, pack_report};
-use crate::scheduler::Scheduler;
+use crate::sched_policy::run_in_background;
+use crate::scheduler::{Scheduler, StatusReporter};
+use crate::trace_metadata::{check_annotations, new_correlation_id, Annotations};
+use crate::zygote;
+
+/// Optional features of the interface implemented by this service.
+const SUPPORTED_FEATURES: i64 = FEATURE_PROVIDER_STATUS_CALLBACK
+    | FEATURE_TRACE_ONCE
+    | FEATURE_TRACE_PRIORITY
+    | FEATURE_BLACKOUT
+    | FEATURE_CALL_STATE
+    | FEATURE_INTENSIVE_MODE
+    | FEATURE_DIAGNOSTICS_BUNDLE
+    | FEATURE_RUN_CLEANUP
+    | FEATURE_STORAGE_USAGE
+    | FEATURE_REPORT_CHUNKS
+    | FEATURE_HARDWARE_CAPABILITIES
+    | FEATURE_BACKUP_STATE
+    | FEATURE_SCHEDULE_SIMULATION
+    | FEATURE_ARM_BOOT_TRACE
+    | FEATURE_ZYGOTE_PHASE
+    | FEATURE_COMMAND_QUEUE
+    | FEATURE_TAG_REPORTS
+    | FEATURE_CLEAR_PRIVATE_DATA
+    | FEATURE_PROFILE_SUMMARY;
 
 pub fn err_to_binder_status(msg: Error) -> Status {
     let msg = format!("{:#?}", msg);
     let msg = CString::new(msg).expect("Failed to convert to CString");
-    Status::new_service_specific_error(1, Some(&msg))
+    Status::new_service_specific_error(ERROR_FAILED, Some(&msg))
+}
+
+fn busy_to_binder_status(busy: Busy) -> Status {
+    let msg = CString::new(busy.to_string()).expect("Failed to convert to CString");
+    Status::new_service_specific_error(ERROR_BUSY, Some(&msg))
+}
+
+fn to_report_result(report: ReportInfo, start_time: Instant) -> ReportResult {
+    ReportResult {
+        name: report.name,
+        sizeBytes: report.size.try_into().unwrap_or(i64::MAX),
+        profileCount: report.profile_count.try_into().unwrap_or(i32::MAX),
+        durationMs: start_time.elapsed().as_millis().try_into().unwrap_or(i64::MAX),
+        truncated: report.truncated,
+        split: report.split,
+    }
 }
 
 pub struct ProfcollectdBinderService {
     lock: Mutex<Lock>,
+    /// The calls changing the collection state, run one at a time.
+    commands: CommandQueue,
+    /// Reads the status of the scheduler without the lock, which is held by the running command.
+    status: StatusReporter,
+    /// Whether the boot is traced on this start.
+    boot_trace: bool,
 }
 
 struct Lock {
@@ -48,10 +119,15 @@ struct Lock {
     scheduler: Scheduler,
 }
 
-impl binder::Interface for ProfcollectdBinderService {}
+impl binder::Interface for ProfcollectdBinderService {
+    fn dump(&self, writer: &mut dyn Write, _args: &[&CStr]) -> Result<(), StatusCode> {
+        writer.write_all(self.status().as_bytes()).map_err(|_| StatusCode::UNKNOWN_ERROR)
+    }
+}
 
 impl IProfCollectd for ProfcollectdBinderService {
     fn schedule(&self) -> BinderResult<()> {
+        let _command = self.admit("schedule")?;
         let lock = &mut *self.lock();
         lock.scheduler
             .schedule_periodic(&lock.config)
@@ -59,6 +135,7 @@ impl IProfCollectd for ProfcollectdBinderService {
             .map_err(err_to_binder_status)
     }
     fn terminate(&self) -> BinderResult<()> {
+        let _command = self.admit("terminate")?;
         self.lock()
             .scheduler
             .terminate_periodic()
@@ -66,6 +143,7 @@ impl IProfCollectd for ProfcollectdBinderService {
             .map_err(err_to_binder_status)
     }
     fn trace_system(&self, tag: &str) -> BinderResult<()> {
+        let _command = self.admit("trace_system")?;
         let lock = &mut *self.lock();
         lock.scheduler
             .trace_system(&lock.config, tag)
@@ -73,29 +151,75 @@ impl IProfCollectd for ProfcollectdBinderService {
             .map_err(err_to_binder_status)
     }
     fn trace_process(&self, tag: &str, process: &str, duration: f32) -> BinderResult<()> {
+        let _command = self.admit("trace_process")?;
         let lock = &mut *self.lock();
         lock.scheduler
             .trace_process(&lock.config, tag, process, duration)
             .context("Failed to perform process trace.")
             .map_err(err_to_binder_status)
     }
-    fn process(&self) -> BinderResult<()> {
+    fn trace_once(&self, request: &TraceRequest) -> BinderResult<()> {
+        let sampling_period = match u64::try_from(request.samplingPeriodMs) {
+            Ok(ms) if ms > 0 => Duration::from_millis(ms),
+            _ => get_sampling_period(),
+        };
+        let annotations: Annotations =
+            request.annotations.iter().map(|a| (a.key.clone(), a.value.clone())).collect();
+        check_annotations(&annotations).map_err(err_to_binder_status)?;
+        let trace = OneShotTrace {
+            tag: request.tag.clone(),
+            processes: request.processes.clone(),
+            sampling_period,
+            expiry: Duration::from_secs(u64::try_from(request.expirySecs).unwrap_or(0)),
+            annotations,
+            priority: match request.priority {
+                TracePriority::LOW => Priority::Low,
+                TracePriority::CRITICAL => Priority::Critical,
+                _ => Priority::Normal,
+            },
+            correlation_id: new_correlation_id(),
+        };
+        let _command = self.admit("trace_once")?;
         let lock = &mut *self.lock();
         lock.scheduler
-            .process(&lock.config)
-            .context("Failed to process profiles.")
+            .trace_once(&lock.config, trace)
+            .context("Failed to perform one-shot trace.")
             .map_err(err_to_binder_status)
     }
-    fn report(&self, usage_setting: i32) -> BinderResult<String> {
-        self.process()?;
+    fn process(&self) -> BinderResult<()> {
+        let _command = self.admit("process")?;
+        self.process_traces()
+    }
+    fn report(&self, usage_setting: i32) -> BinderResult<ReportResult> {
+        let start_time = Instant::now();
+        let _command = self.admit("report")?;
+        self.process_traces()?;
 
         let lock = &mut *self.lock();
-        pack_report(&PROFILE_OUTPUT_DIR, &REPORT_OUTPUT_DIR, &lock.config, usage_setting)
-            .context("Failed to create profile report.")
-            .map_err(err_to_binder_status)
+        let config = &lock.config;
+        let report = run_in_background(config, || {
+            pack_report(&PROFILE_OUTPUT_DIR, &REPORT_OUTPUT_DIR, config, usage_setting)
+        })
+        .context("Failed to create profile report.")
+        .map_err(err_to_binder_status)?;
+        Ok(to_report_result(report, start_time))
+    }
+    fn report_for_tags(&self, tags: &[String]) -> BinderResult<ReportResult> {
+        let start_time = Instant::now();
+        let _command = self.admit("report_for_tags")?;
+        self.process_traces()?;
+
+        let lock = &mut *self.lock();
+        let config = &lock.config;
+        let report = run_in_background(config, || {
+            pack_report_for_tags(&PROFILE_OUTPUT_DIR, &REPORT_OUTPUT_DIR, config, tags)
+        })
+        .context(format!("Failed to create profile report for {}.", tags.join(",")))
+        .map_err(err_to_binder_status)?;
+        Ok(to_report_result(report, start_time))
     }
     fn get_supported_provider(&self) -> BinderResult<String> {
-        Ok(self.lock().scheduler.get_trace_provider_name().to_string())
+        Ok(self.status.provider_name().to_string())
     }
 
     fn registerProviderStatusCallback(
@@ -121,11 +245,183 @@ impl IProfCollectd for ProfcollectdBinderService {
         }));
         Ok(())
     }
+
+    fn begin_blackout(&self, reason: &str, duration_secs: i32) -> BinderResult<()> {
+        let duration =
+            u64::try_from(duration_secs).ok().filter(|d| *d > 0).map(Duration::from_secs);
+        let _command = self.admit("begin_blackout")?;
+        self.lock().scheduler.begin_blackout(reason, duration);
+        Ok(())
+    }
+
+    fn end_blackout(&self, reason: &str) -> BinderResult<()> {
+        let _command = self.admit("end_blackout")?;
+        self.lock()
+            .scheduler
+            .end_blackout(reason)
+            .context("Failed to end blackout.")
+            .map_err(err_to_binder_status)
+    }
+
+    fn notify_backup_state(&self, in_progress: bool) -> BinderResult<()> {
+        let _command = self.admit("notify_backup_state")?;
+        self.lock().scheduler.set_backup_in_progress(in_progress);
+        Ok(())
+    }
+
+    fn notify_zygote_phase(&self, phase: ZygotePhase, pid: i32) -> BinderResult<()> {
+        let phase = match phase {
+            ZygotePhase::PRELOAD => zygote::ZygotePhase::Preload,
+            ZygotePhase::FORK => zygote::ZygotePhase::Fork,
+            _ => return Err(err_to_binder_status(anyhow!("Unknown zygote phase {:?}.", phase))),
+        };
+        let pid = u32::try_from(pid)
+            .map_err(|_| err_to_binder_status(anyhow!("Invalid pid {}.", pid)))?;
+        let caller_uid = ThreadState::get_calling_uid();
+        ThreadState::with_calling_sid(|sid| {
+            let context = sid.and_then(|sid| sid.to_str().ok());
+            zygote::check_caller(caller_uid, context)
+        })
+        .context("Zygote phase hint rejected.")
+        .map_err(err_to_binder_status)?;
+        let zygotes = zygote::zygote_pids();
+        let _command = self.admit("notify_zygote_phase")?;
+        let lock = &*self.lock();
+        lock.scheduler
+            .trace_zygote_phase(&lock.config, phase, pid, &zygotes)
+            .context("Failed to trace zygote.")
+            .map_err(err_to_binder_status)
+    }
+
+    fn arm_boot_trace(&self, enable: bool) -> BinderResult<()> {
+        let _command = self.admit("arm_boot_trace")?;
+        set_boot_trace_flag(enable)
+            .context("Failed to arm the boot trace.")
+            .map_err(err_to_binder_status)
+    }
+
+    fn set_intensive_mode(&self, duration_secs: i32) -> BinderResult<()> {
+        let _command = self.admit("set_intensive_mode")?;
+        let lock = &*self.lock();
+        let duration = Duration::from_secs(u64::try_from(duration_secs).unwrap_or(0));
+        lock.scheduler.set_intensive_mode(&lock.config, duration);
+        Ok(())
+    }
+
+    fn get_status(&self) -> BinderResult<String> {
+        Ok(self.status())
+    }
+
+    fn simulate_schedule(&self) -> BinderResult<String> {
+        let lock = &*self.lock();
+        Ok(lock.scheduler.simulate_schedule(&lock.config))
+    }
+
+    fn summarize_profiles(&self, max_entries: i32) -> BinderResult<String> {
+        let max_entries =
+            usize::try_from(max_entries).ok().filter(|&n| n > 0).unwrap_or(DEFAULT_SUMMARY_ENTRIES);
+        let _command = self.admit("summarize_profiles")?;
+        let lock = &*self.lock();
+        lock.scheduler
+            .summarize_profiles(&lock.config, max_entries)
+            .context("Failed to summarize profiles.")
+            .map_err(err_to_binder_status)
+    }
+
+    fn get_diagnostics_bundle(&self) -> BinderResult<Vec<u8>> {
+        build_diagnostics_bundle(&self.status(), self.status.config())
+            .context("Failed to build diagnostics bundle.")
+            .map_err(err_to_binder_status)
+    }
+
+    fn run_cleanup(&self) -> BinderResult<i64> {
+        let _command = self.admit("run_cleanup")?;
+        let lock = &mut *self.lock();
+        let freed = lock
+            .scheduler
+            .run_cleanup(&lock.config)
+            .and_then(|freed| Ok(freed + evict_reports(&REPORT_OUTPUT_DIR, &lock.config)?))
+            .context("Failed to clean up.")
+            .map_err(err_to_binder_status)?;
+        Ok(freed.try_into().unwrap_or(i64::MAX))
+    }
+
+    fn clear_private_data(&self) -> BinderResult<()> {
+        let _command = self.admit("clear_private_data")?;
+        let _lock = self.lock();
+        clear_data()
+            .and_then(|_| reset_report_identifiers(&DeviceIdentifiers))
+            .context("Failed to clear private data.")
+            .map_err(err_to_binder_status)
+    }
+
+    fn get_storage_usage(&self) -> BinderResult<Vec<TagStorageUsage>> {
+        let usage = self
+            .lock()
+            .scheduler
+            .get_storage_usage()
+            .context("Failed to get storage usage.")
+            .map_err(err_to_binder_status)?;
+        let to_i64 = |bytes: u64| bytes.try_into().unwrap_or(i64::MAX);
+        Ok(usage
+            .into_iter()
+            .map(|(tag, usage)| TagStorageUsage {
+                tag,
+                traceBytes: to_i64(usage.trace_bytes),
+                profileBytes: to_i64(usage.profile_bytes),
+            })
+            .collect())
+    }
+
+    fn read_report_chunk(&self, name: &str, offset: i64, length: i32) -> BinderResult<Vec<u8>> {
+        let (offset, length) = match (u64::try_from(offset), usize::try_from(length)) {
+            (Ok(offset), Ok(length)) => (offset, length),
+            _ => {
+                let e = anyhow!("Invalid offset {} or length {}.", offset, length);
+                return Err(err_to_binder_status(e));
+            }
+        };
+        let _lock = self.lock();
+        read_report_chunk(&REPORT_OUTPUT_DIR, name, offset, length)
+            .context(format!("Failed to read report {}.", name))
+            .map_err(err_to_binder_status)
+    }
+
+    fn get_hardware_capabilities(&self) -> BinderResult<HardwareCapabilities> {
+        // The capabilities are probed through simpleperf, like every other simpleperf call.
+        let _lock = self.lock();
+        let capabilities = get_capabilities();
+        Ok(HardwareCapabilities {
+            pmuEvents: capabilities.pmu_events,
+            etm: capabilities.etm,
+            etmFeatures: capabilities.etm_features,
+            etmSinks: capabilities.etm_sinks,
+            spe: capabilities.spe,
+            speFeatures: capabilities.spe_features,
+            lbr: capabilities.lbr,
+        })
+    }
+
+    fn get_interface_capabilities(&self) -> BinderResult<i64> {
+        Ok(SUPPORTED_FEATURES)
+    }
+
+    fn get_profile_freshness(&self) -> BinderResult<ProfileFreshness> {
+        let _lock = self.lock();
+        let (profile_age, report_age) =
+            get_profile_freshness(&PROFILE_OUTPUT_DIR, &REPORT_OUTPUT_DIR)
+                .context("Failed to get profile freshness.")
+                .map_err(err_to_binder_status)?;
+        let to_secs = |age: Option<Duration>| age.map_or(-1, |a| a.as_secs() as i64);
+        Ok(ProfileFreshness {
+            profileAgeSecs: to_secs(profile_age),
+            reportAgeSecs: to_secs(report_age),
+        })
+    }
 }
 
 impl ProfcollectdBinderService {
     pub fn new() -> Result<Self> {
-        let new_scheduler = Scheduler::new()?;
         let new_config = Config::from_env()?;
 
         let config_changed = read_to_string(*CONFIG_FILE)
@@ -139,39 +435,72 @@ impl ProfcollectdBinderService {
             clear_data()?;
 
             write(*CONFIG_FILE, new_config.to_string())?;
+        }
+
+        // The provider is picked after the data is cleared on config changes, which forgets the
+        // failed providers.
+        let mut new_scheduler = Scheduler::new(&new_config)?;
+        if config_changed {
             new_scheduler.clear_trace_log()?;
         }
 
-        // Clear profile reports out of rentention period.
-        for report in read_dir(*REPORT_OUTPUT_DIR)? {
-            let report = report?.path();
-            let report_name = report
-                .file_stem()
-                .and_then(|f| f.to_str())
-                .ok_or_else(|| anyhow!("Malformed path {}", report.display()))?;
-            let report_ts = get_report_ts(report_name);
-            if let Err(e) = report_ts {
-                log::error!(
-                    "Cannot decode creation timestamp for report {}, caused by {}, deleting",
-                    report_name,
-                    e
+        new_scheduler.set_trace_clock(&new_config);
+        new_scheduler.set_trace_log(&new_config);
+        new_scheduler.start_audits(&new_config);
+
+        let boot_trace = match take_boot_trace_flag()? {
+            Some(armed) => {
+                log::info!(
+                    "Boot trace {} for this start.",
+                    if armed { "armed" } else { "disarmed" }
                 );
-                remove_file(report)?;
-                continue;
-            }
-            let report_age = report_ts.unwrap().elapsed()?;
-            if report_age > Duration::from_secs(REPORT_RETENTION_SECS) {
-                log::info!("Report {} past rentention period, deleting", report_name);
-                remove_file(report)?;
+                armed
             }
+            None => new_config.boot_trace,
+        };
+
+        if take_post_ota_flag()? {
+            new_scheduler.start_post_ota_window(&new_config);
         }
 
+        // Clear profile reports out of rentention period, or over the storage limit.
+        evict_reports(&REPORT_OUTPUT_DIR, &new_config)?;
+
         Ok(ProfcollectdBinderService {
+            status: new_scheduler.status_reporter(&new_config),
             lock: Mutex::new(Lock { scheduler: new_scheduler, config: new_config }),
+            commands: CommandQueue::default(),
+            boot_trace,
         })
     }
 
+    /// Whether the boot is traced on this start, from the boot_trace config unless the boot trace
+    /// was armed or disarmed for it.
+    pub fn boot_trace(&self) -> bool {
+        self.boot_trace
+    }
+
     fn lock(&self) -> MutexGuard<Lock> {
         self.lock.lock().unwrap()
     }
+
+    /// Wait for the turn of `command` in the command queue, see `command_queue`.
+    fn admit(&self, command: &'static str) -> BinderResult<CommandGuard<'_>> {
+        self.commands.admit(command).map_err(busy_to_binder_status)
+    }
+
+    /// Process the traces, in the turn of the running command.
+    fn process_traces(&self) -> BinderResult<()> {
+        let lock = &mut *self.lock();
+        lock.scheduler
+            .process(&lock.config)
+            .context("Failed to process profiles.")
+            .map_err(err_to_binder_status)
+    }
+
+    fn status(&self) -> String {
+        self.status.status()
+            + &freshness_status(&PROFILE_OUTPUT_DIR, &REPORT_OUTPUT_DIR)
+            + &self.commands.status()
+    }
 }
@@ -16,18 +16,33 @@
 
 //! Trace provider backed by ARM Coresight ETM, using simpleperf tool.
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use std::fs::{read_dir, remove_file};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Mutex;
 use std::time::Duration;
-use trace_provider::TraceProvider;
+use trace_provider::{ProfileFormat, TraceProvider};
 
+use crate::config::{get_etm_aux_buffer_kb, set_etm_aux_buffer_kb, Config, TraceClock};
+use crate::in_memory_trace::InMemoryTrace;
+use crate::provider_error::run_simpleperf;
 use crate::trace_provider;
 
 static ETM_TRACEFILE_EXTENSION: &str = "etmtrace";
-static ETM_PROFILE_EXTENSION: &str = "data";
 
-pub struct SimpleperfEtmTraceProvider {}
+/// Aux buffer size used by simpleperf when none is given.
+const DEFAULT_AUX_BUFFER_KB: u64 = 4096;
+/// Number of consecutive captures well under the target data loss before the aux buffer is shrunk.
+const SHRINK_AFTER_CAPTURES: u32 = 10;
+
+pub struct SimpleperfEtmTraceProvider {
+    /// Aux buffer size tuned from the observed data loss, 0 to use the simpleperf default.
+    aux_buffer_kb: AtomicU64,
+    /// Consecutive captures with data loss well under the target.
+    low_loss_captures: AtomicU32,
+    clock: Mutex<TraceClock>,
+}
 
 impl TraceProvider for SimpleperfEtmTraceProvider {
     fn get_name(&self) -> &'static str {
@@ -42,15 +57,20 @@ impl TraceProvider for SimpleperfEtmTraceProvider {
         &self,
         trace_dir: &Path,
         tag: &str,
+        correlation_id: &str,
+        in_memory: Option<&InMemoryTrace>,
         sampling_period: &Duration,
         binary_filter: &str,
-    ) {
-        let trace_file = trace_provider::get_path(trace_dir, tag, ETM_TRACEFILE_EXTENSION);
+    ) -> Result<Box<Path>> {
+        let trace_file =
+            trace_provider::get_path(trace_dir, tag, correlation_id, ETM_TRACEFILE_EXTENSION);
+        let output = trace_provider::output_path(&trace_file, in_memory);
         // Record ETM data for kernel space only when it's not filtered out by binary_filter. So we
         // can get more ETM data for user space when ETM data for kernel space isn't needed.
         let event_name = if binary_filter.contains("kernel") { "cs-etm" } else { "cs-etm:u" };
         let duration: String = sampling_period.as_secs_f64().to_string();
-        let args: Vec<&str> = vec![
+        let aux_buffer_size = self.get_aux_buffer_size();
+        let mut args: Vec<&str> = vec![
             "-a",
             "-e",
             event_name,
@@ -62,22 +82,35 @@ impl TraceProvider for SimpleperfEtmTraceProvider {
             "--no-dump-symbols",
             "--no-dump-kernel-symbols",
             "-o",
-            trace_file.to_str().unwrap(),
+            output.to_str().unwrap(),
         ];
-        simpleperf_profcollect::run_record_cmd(&args);
+        if let Some(size) = aux_buffer_size.as_deref() {
+            args.extend(["--aux-buffer-size", size]);
+        }
+        if let Some(clockid) = self.clock.lock().unwrap().clockid() {
+            args.extend(["--clockid", clockid]);
+        }
+        run_simpleperf("record", || simpleperf_profcollect::run_record_cmd(&args))
+            .context("Failed to record ETM data.")?;
+        Ok(trace_file)
     }
 
     fn trace_process(
         &self,
         trace_dir: &Path,
         tag: &str,
+        correlation_id: &str,
+        in_memory: Option<&InMemoryTrace>,
         sampling_period: &Duration,
         processes: &str,
-    ) {
-        let trace_file = trace_provider::get_path(trace_dir, tag, ETM_TRACEFILE_EXTENSION);
+    ) -> Result<Box<Path>> {
+        let trace_file =
+            trace_provider::get_path(trace_dir, tag, correlation_id, ETM_TRACEFILE_EXTENSION);
+        let output = trace_provider::output_path(&trace_file, in_memory);
         let event_name = "cs-etm:u";
         let duration: String = sampling_period.as_secs_f64().to_string();
-        let args: Vec<&str> = vec![
+        let aux_buffer_size = self.get_aux_buffer_size();
+        let mut args: Vec<&str> = vec![
             "-p",
             processes,
             "-e",
@@ -87,12 +120,75 @@ impl TraceProvider for SimpleperfEtmTraceProvider {
             "-z",
             "--no-dump-symbols",
             "-o",
-            trace_file.to_str().unwrap(),
+            output.to_str().unwrap(),
         ];
-        simpleperf_profcollect::run_record_cmd(&args);
+        if let Some(size) = aux_buffer_size.as_deref() {
+            args.extend(["--aux-buffer-size", size]);
+        }
+        if let Some(clockid) = self.clock.lock().unwrap().clockid() {
+            args.extend(["--clockid", clockid]);
+        }
+        run_simpleperf("record", || simpleperf_profcollect::run_record_cmd(&args))
+            .context("Failed to record ETM data.")?;
+        Ok(trace_file)
+    }
+
+    fn get_data_loss(&self, trace_file: &Path) -> Option<f64> {
+        // Aux data lost in user space is not part of the traced size.
+        let (traced, lost) = simpleperf_profcollect::get_aux_data_stat(trace_file)?;
+        let total = traced + lost;
+        (total > 0).then(|| lost as f64 * 100.0 / total as f64)
+    }
+
+    fn adjust_to_data_loss(&self, config: &Config, data_loss_percent: f64) {
+        let target = config.etm_target_data_loss_percent as f64;
+        let min_kb = config.etm_aux_buffer_min_kb;
+        let max_kb = config.etm_aux_buffer_max_kb.max(min_kb);
+        let current_kb = match self.aux_buffer_kb.load(Ordering::Relaxed) {
+            0 => DEFAULT_AUX_BUFFER_KB,
+            kb => kb,
+        };
+
+        // Grow quickly on lossy captures, but only shrink after a sustained run of clean captures
+        // to avoid oscillating around the target.
+        let adjusted_kb = if data_loss_percent > target {
+            self.low_loss_captures.store(0, Ordering::Relaxed);
+            current_kb * 2
+        } else if data_loss_percent < target / 2.0 {
+            if self.low_loss_captures.fetch_add(1, Ordering::Relaxed) + 1 < SHRINK_AFTER_CAPTURES {
+                current_kb
+            } else {
+                self.low_loss_captures.store(0, Ordering::Relaxed);
+                current_kb / 2
+            }
+        } else {
+            self.low_loss_captures.store(0, Ordering::Relaxed);
+            current_kb
+        };
+        let adjusted_kb = round_aux_buffer_kb(adjusted_kb.clamp(min_kb, max_kb), min_kb);
+
+        if self.aux_buffer_kb.swap(adjusted_kb, Ordering::Relaxed) == adjusted_kb {
+            return;
+        }
+        log::info!(
+            "ETM data loss {:.1}% (target {}%), aux buffer size set to {}KB.",
+            data_loss_percent,
+            target,
+            adjusted_kb
+        );
+        if let Err(e) = set_etm_aux_buffer_kb(adjusted_kb) {
+            log::error!("Failed to persist ETM aux buffer size: {:?}", e);
+        }
     }
 
-    fn process(&self, trace_dir: &Path, profile_dir: &Path, binary_filter: &str) -> Result<()> {
+    fn process(
+        &self,
+        trace_dir: &Path,
+        profile_dir: &Path,
+        binary_filter: &str,
+        hide_kernel_addresses: bool,
+        formats: &[ProfileFormat],
+    ) -> Result<()> {
         let is_etm_extension = |file: &PathBuf| {
             file.extension()
                 .and_then(|f| f.to_str())
@@ -101,26 +197,33 @@ impl TraceProvider for SimpleperfEtmTraceProvider {
         };
 
         let process_trace_file = |trace_file: PathBuf| {
-            let mut profile_file = PathBuf::from(profile_dir);
-            profile_file.push(
-                trace_file
-                    .file_name()
-                    .ok_or_else(|| anyhow!("Malformed trace path: {}", trace_file.display()))?,
-            );
-            profile_file.set_extension(ETM_PROFILE_EXTENSION);
-
-            let args: Vec<&str> = vec![
-                "-i",
-                trace_file.to_str().unwrap(),
-                "-o",
-                profile_file.to_str().unwrap(),
-                "--output",
-                "branch-list",
-                "--binary",
-                binary_filter,
-                "--exclude-perf",
-            ];
-            simpleperf_profcollect::run_inject_cmd(&args);
+            let file_name = trace_file
+                .file_name()
+                .ok_or_else(|| anyhow!("Malformed trace path: {}", trace_file.display()))?;
+            for format in formats {
+                let mut profile_file = profile_dir.join(file_name);
+                profile_file.set_extension(format.extension());
+
+                let mut args: Vec<&str> = vec![
+                    "-i",
+                    trace_file.to_str().unwrap(),
+                    "-o",
+                    profile_file.to_str().unwrap(),
+                    "--output",
+                    format.inject_output(),
+                    "--binary",
+                    binary_filter,
+                    "--exclude-perf",
+                ];
+                if hide_kernel_addresses {
+                    args.push("--hide-kernel-addresses");
+                }
+                if let Err(e) =
+                    run_simpleperf("inject", || simpleperf_profcollect::run_inject_cmd(&args))
+                {
+                    log::error!("Failed to process {}: {}", trace_file.display(), e);
+                }
+            }
             remove_file(&trace_file)?;
             Ok(())
         };
@@ -133,6 +236,34 @@ impl TraceProvider for SimpleperfEtmTraceProvider {
             .try_for_each(process_trace_file)
     }
 
+    fn merge(
+        &self,
+        profiles: &[PathBuf],
+        binary_filter: &str,
+        format: ProfileFormat,
+        output: &Path,
+    ) -> Result<()> {
+        let inputs: Vec<&str> = profiles.iter().map(|p| p.to_str().unwrap()).collect();
+        let inputs = inputs.join(",");
+        let args: Vec<&str> = vec![
+            "-i",
+            &inputs,
+            "-o",
+            output.to_str().unwrap(),
+            "--output",
+            format.inject_output(),
+            "--binary",
+            binary_filter,
+        ];
+        run_simpleperf("inject", || simpleperf_profcollect::run_inject_cmd(&args))
+            .context("Failed to merge ETM profiles.")?;
+        Ok(())
+    }
+
+    fn set_trace_clock(&self, clock: TraceClock) {
+        *self.clock.lock().unwrap() = clock;
+    }
+
     fn set_log_file(&self, filename: &Path) {
         simpleperf_profcollect::set_log_file(filename);
     }
@@ -143,7 +274,94 @@ impl TraceProvider for SimpleperfEtmTraceProvider {
 }
 
 impl SimpleperfEtmTraceProvider {
+    pub fn new() -> Self {
+        SimpleperfEtmTraceProvider {
+            aux_buffer_kb: AtomicU64::new(get_etm_aux_buffer_kb()),
+            low_loss_captures: AtomicU32::new(0),
+            clock: Mutex::new(TraceClock::Perf),
+        }
+    }
+
+    fn get_aux_buffer_size(&self) -> Option<String> {
+        match self.aux_buffer_kb.load(Ordering::Relaxed) {
+            0 => None,
+            kb => Some((kb * 1024).to_string()),
+        }
+    }
+
     pub fn supported() -> bool {
         simpleperf_profcollect::is_etm_driver_available()
     }
 }
+
+/// Round `size_kb` down to a power of 2, as required of aux buffer sizes, or up if that would take
+/// it under `min_kb`.
+fn round_aux_buffer_kb(size_kb: u64, min_kb: u64) -> u64 {
+    let rounded = if size_kb.is_power_of_two() { size_kb } else { size_kb.next_power_of_two() / 2 };
+    if rounded < min_kb {
+        min_kb.next_power_of_two()
+    } else {
+        rounded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+    use std::sync::Mutex;
+
+    use super::{round_aux_buffer_kb, SimpleperfEtmTraceProvider, SHRINK_AFTER_CAPTURES};
+    use crate::config::{get_etm_aux_buffer_kb, set_etm_aux_buffer_kb, Config, TraceClock};
+    use crate::trace_provider::TraceProvider;
+
+    #[test]
+    fn aux_buffer_is_rounded_within_the_minimum() {
+        assert_eq!(round_aux_buffer_kb(8192, 4096), 8192);
+        assert_eq!(round_aux_buffer_kb(12000, 4096), 8192);
+        // Rounding down would go under the minimum.
+        assert_eq!(round_aux_buffer_kb(6000, 6000), 8192);
+        assert_eq!(round_aux_buffer_kb(3000, 3000), 4096);
+    }
+
+    #[test]
+    fn aux_buffer_follows_the_data_loss() {
+        let persisted_kb = get_etm_aux_buffer_kb();
+        let mut config = Config::from_env().unwrap();
+        config.etm_target_data_loss_percent = 2;
+        config.etm_aux_buffer_min_kb = 4096;
+        config.etm_aux_buffer_max_kb = 16384;
+        let provider = SimpleperfEtmTraceProvider {
+            aux_buffer_kb: AtomicU64::new(0),
+            low_loss_captures: AtomicU32::new(0),
+            clock: Mutex::new(TraceClock::Perf),
+        };
+        let aux_buffer_kb = || provider.aux_buffer_kb.load(Ordering::Relaxed);
+
+        // Lossy captures grow the buffer up to the maximum.
+        provider.adjust_to_data_loss(&config, 5.0);
+        assert_eq!(aux_buffer_kb(), 8192);
+        provider.adjust_to_data_loss(&config, 5.0);
+        provider.adjust_to_data_loss(&config, 5.0);
+        assert_eq!(aux_buffer_kb(), 16384);
+
+        // Only a full run of clean captures shrinks it, and a capture near the target restarts it.
+        for _ in 1..SHRINK_AFTER_CAPTURES {
+            provider.adjust_to_data_loss(&config, 0.0);
+        }
+        provider.adjust_to_data_loss(&config, 1.5);
+        for _ in 1..SHRINK_AFTER_CAPTURES {
+            provider.adjust_to_data_loss(&config, 0.0);
+        }
+        assert_eq!(aux_buffer_kb(), 16384);
+        provider.adjust_to_data_loss(&config, 0.0);
+        assert_eq!(aux_buffer_kb(), 8192);
+
+        // It never shrinks under the minimum.
+        for _ in 0..SHRINK_AFTER_CAPTURES * 3 {
+            provider.adjust_to_data_loss(&config, 0.0);
+        }
+        assert_eq!(aux_buffer_kb(), 4096);
+
+        set_etm_aux_buffer_kb(persisted_kb).unwrap();
+    }
+}
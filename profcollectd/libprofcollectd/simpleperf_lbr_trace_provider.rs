@@ -15,18 +15,23 @@
 //
 
 //! Trace provider backed by Intel LBR, using simpleperf tool.
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use std::fs::{read_dir, remove_file};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use std::time::Duration;
-use trace_provider::TraceProvider;
+use trace_provider::{ProfileFormat, TraceProvider};
 
+use crate::config::{Config, TraceClock};
+use crate::in_memory_trace::InMemoryTrace;
+use crate::provider_error::run_simpleperf;
 use crate::trace_provider;
 
 static LBR_TRACEFILE_EXTENSION: &str = "lbrtrace";
-static LBR_PROFILE_EXTENSION: &str = "data";
 
-pub struct SimpleperfLbrTraceProvider {}
+pub struct SimpleperfLbrTraceProvider {
+    clock: Mutex<TraceClock>,
+}
 
 impl TraceProvider for SimpleperfLbrTraceProvider {
     fn get_name(&self) -> &'static str {
@@ -41,16 +46,20 @@ impl TraceProvider for SimpleperfLbrTraceProvider {
         &self,
         trace_dir: &Path,
         tag: &str,
+        correlation_id: &str,
+        in_memory: Option<&InMemoryTrace>,
         sampling_period: &Duration,
         binary_filter: &str,
-    ) {
-        let trace_file = trace_provider::get_path(trace_dir, tag, LBR_TRACEFILE_EXTENSION);
+    ) -> Result<Box<Path>> {
+        let trace_file =
+            trace_provider::get_path(trace_dir, tag, correlation_id, LBR_TRACEFILE_EXTENSION);
+        let output = trace_provider::output_path(&trace_file, in_memory);
         // Record ETM data for kernel space only when it's not filtered out by binary_filter. So we
         // can get more ETM data for user space when ETM data for kernel space isn't needed.
         let event_name =
             if binary_filter.contains("kernel") { "cpu-cycles" } else { "cpu-cycles:u" };
         let duration: String = sampling_period.as_secs_f64().to_string();
-        let args: Vec<&str> = vec![
+        let mut args: Vec<&str> = vec![
             "-a",
             "-e",
             event_name,
@@ -63,22 +72,31 @@ impl TraceProvider for SimpleperfLbrTraceProvider {
             "--no-dump-symbols",
             "--no-dump-kernel-symbols",
             "-o",
-            trace_file.to_str().unwrap(),
+            output.to_str().unwrap(),
         ];
-        simpleperf_profcollect::run_record_cmd(&args);
+        if let Some(clockid) = self.clock.lock().unwrap().clockid() {
+            args.extend(["--clockid", clockid]);
+        }
+        run_simpleperf("record", || simpleperf_profcollect::run_record_cmd(&args))
+            .context("Failed to record LBR data.")?;
+        Ok(trace_file)
     }
 
     fn trace_process(
         &self,
         trace_dir: &Path,
         tag: &str,
+        correlation_id: &str,
+        in_memory: Option<&InMemoryTrace>,
         sampling_period: &Duration,
         processes: &str,
-    ) {
-        let trace_file = trace_provider::get_path(trace_dir, tag, LBR_TRACEFILE_EXTENSION);
+    ) -> Result<Box<Path>> {
+        let trace_file =
+            trace_provider::get_path(trace_dir, tag, correlation_id, LBR_TRACEFILE_EXTENSION);
+        let output = trace_provider::output_path(&trace_file, in_memory);
         let event_name = "cpu-cycles:u";
         let duration: String = sampling_period.as_secs_f64().to_string();
-        let args: Vec<&str> = vec![
+        let mut args: Vec<&str> = vec![
             "-p",
             processes,
             "-e",
@@ -88,12 +106,30 @@ impl TraceProvider for SimpleperfLbrTraceProvider {
             "-b",
             "--no-dump-symbols",
             "-o",
-            trace_file.to_str().unwrap(),
+            output.to_str().unwrap(),
         ];
-        simpleperf_profcollect::run_record_cmd(&args);
+        if let Some(clockid) = self.clock.lock().unwrap().clockid() {
+            args.extend(["--clockid", clockid]);
+        }
+        run_simpleperf("record", || simpleperf_profcollect::run_record_cmd(&args))
+            .context("Failed to record LBR data.")?;
+        Ok(trace_file)
     }
 
-    fn process(&self, trace_dir: &Path, profile_dir: &Path, binary_filter: &str) -> Result<()> {
+    fn get_data_loss(&self, _trace_file: &Path) -> Option<f64> {
+        None
+    }
+
+    fn adjust_to_data_loss(&self, _config: &Config, _data_loss_percent: f64) {}
+
+    fn process(
+        &self,
+        trace_dir: &Path,
+        profile_dir: &Path,
+        binary_filter: &str,
+        hide_kernel_addresses: bool,
+        formats: &[ProfileFormat],
+    ) -> Result<()> {
         let is_lbr_extension = |file: &PathBuf| {
             file.extension()
                 .and_then(|f| f.to_str())
@@ -102,25 +138,32 @@ impl TraceProvider for SimpleperfLbrTraceProvider {
         };
 
         let process_trace_file = |trace_file: PathBuf| {
-            let mut profile_file = PathBuf::from(profile_dir);
-            profile_file.push(
-                trace_file
-                    .file_name()
-                    .ok_or_else(|| anyhow!("Malformed trace path: {}", trace_file.display()))?,
-            );
-            profile_file.set_extension(LBR_PROFILE_EXTENSION);
-
-            let args: Vec<&str> = vec![
-                "-i",
-                trace_file.to_str().unwrap(),
-                "-o",
-                profile_file.to_str().unwrap(),
-                "--output",
-                "branch-list",
-                "--binary",
-                binary_filter,
-            ];
-            simpleperf_profcollect::run_inject_cmd(&args);
+            let file_name = trace_file
+                .file_name()
+                .ok_or_else(|| anyhow!("Malformed trace path: {}", trace_file.display()))?;
+            for format in formats {
+                let mut profile_file = profile_dir.join(file_name);
+                profile_file.set_extension(format.extension());
+
+                let mut args: Vec<&str> = vec![
+                    "-i",
+                    trace_file.to_str().unwrap(),
+                    "-o",
+                    profile_file.to_str().unwrap(),
+                    "--output",
+                    format.inject_output(),
+                    "--binary",
+                    binary_filter,
+                ];
+                if hide_kernel_addresses {
+                    args.push("--hide-kernel-addresses");
+                }
+                if let Err(e) =
+                    run_simpleperf("inject", || simpleperf_profcollect::run_inject_cmd(&args))
+                {
+                    log::error!("Failed to process {}: {}", trace_file.display(), e);
+                }
+            }
             remove_file(&trace_file)?;
             Ok(())
         };
@@ -133,6 +176,34 @@ impl TraceProvider for SimpleperfLbrTraceProvider {
             .try_for_each(process_trace_file)
     }
 
+    fn merge(
+        &self,
+        profiles: &[PathBuf],
+        binary_filter: &str,
+        format: ProfileFormat,
+        output: &Path,
+    ) -> Result<()> {
+        let inputs: Vec<&str> = profiles.iter().map(|p| p.to_str().unwrap()).collect();
+        let inputs = inputs.join(",");
+        let args: Vec<&str> = vec![
+            "-i",
+            &inputs,
+            "-o",
+            output.to_str().unwrap(),
+            "--output",
+            format.inject_output(),
+            "--binary",
+            binary_filter,
+        ];
+        run_simpleperf("inject", || simpleperf_profcollect::run_inject_cmd(&args))
+            .context("Failed to merge LBR profiles.")?;
+        Ok(())
+    }
+
+    fn set_trace_clock(&self, clock: TraceClock) {
+        *self.clock.lock().unwrap() = clock;
+    }
+
     fn set_log_file(&self, filename: &Path) {
         simpleperf_profcollect::set_log_file(filename);
     }
@@ -143,6 +214,10 @@ impl TraceProvider for SimpleperfLbrTraceProvider {
 }
 
 impl SimpleperfLbrTraceProvider {
+    pub fn new() -> Self {
+        SimpleperfLbrTraceProvider { clock: Mutex::new(TraceClock::Perf) }
+    }
+
     pub fn supported() -> bool {
         simpleperf_profcollect::is_lbr_available()
     }
@@ -0,0 +1,138 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Storage accounting of traces and profiles, by the tag of the event that triggered them.
+
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs::{read_dir, remove_file};
+use std::path::{Path, PathBuf};
+
+use crate::trace_metadata::{is_correlation_id, is_metadata};
+
+/// Bytes of traces and profiles attributable to a tag.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TagUsage {
+    pub trace_bytes: u64,
+    pub profile_bytes: u64,
+}
+
+/// Get the tag of a trace or profile file, or of its metadata sidecar. Files are named
+/// `<timestamp>_<tag>_<correlation id>.<ext>` with a basic ISO 8601 UTC timestamp, see
+/// `trace_provider::get_path`. Files named before correlation IDs have no ID.
+pub fn get_tag(file: &Path) -> Option<&str> {
+    let (_, name) = file.file_stem()?.to_str()?.split_once('_')?;
+    let tag = match name.rsplit_once('_') {
+        Some((tag, id)) if is_correlation_id(id) => tag,
+        _ => name,
+    };
+    Some(tag).filter(|t| !t.is_empty())
+}
+
+/// Get the bytes of the tagged files in `dir` by tag, non-recursive.
+pub fn usage_by_tag(dir: &Path) -> Result<BTreeMap<String, u64>> {
+    let mut usage = BTreeMap::new();
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        let metadata = path.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        if let Some(tag) = get_tag(&path) {
+            *usage.entry(tag.to_string()).or_insert(0) += metadata.len();
+        }
+    }
+    Ok(usage)
+}
+
+/// Get the storage used by traces and profiles by tag.
+pub fn get_storage_usage(
+    trace_dir: &Path,
+    profile_dir: &Path,
+) -> Result<BTreeMap<String, TagUsage>> {
+    let mut usage: BTreeMap<String, TagUsage> = BTreeMap::new();
+    for (tag, bytes) in usage_by_tag(trace_dir)? {
+        usage.entry(tag).or_default().trace_bytes = bytes;
+    }
+    for (tag, bytes) in usage_by_tag(profile_dir)? {
+        usage.entry(tag).or_default().profile_bytes = bytes;
+    }
+    Ok(usage)
+}
+
+/// Human-readable storage usage, one line per tag.
+pub fn status(usage: &BTreeMap<String, TagUsage>) -> String {
+    let mut status = String::new();
+    for (tag, usage) in usage {
+        writeln!(
+            status,
+            "storage: {} (traces {} bytes, profiles {} bytes)",
+            tag, usage.trace_bytes, usage.profile_bytes
+        )
+        .unwrap();
+    }
+    status
+}
+
+/// Get the number and bytes of the unprocessed traces in `trace_dir`, not counting metadata.
+pub fn get_backlog(trace_dir: &Path) -> Result<(u32, u64)> {
+    let (mut count, mut bytes) = (0, 0);
+    for entry in read_dir(trace_dir)? {
+        let path = entry?.path();
+        let metadata = path.metadata()?;
+        if metadata.is_file() && !is_metadata(&path) {
+            count += 1;
+            bytes += metadata.len();
+        }
+    }
+    Ok((count, bytes))
+}
+
+/// Get the bytes of the unprocessed traces in `trace_dir` by tag, not counting metadata.
+pub fn get_backlog_by_tag(trace_dir: &Path) -> Result<BTreeMap<String, u64>> {
+    let mut backlog = BTreeMap::new();
+    for entry in read_dir(trace_dir)? {
+        let path = entry?.path();
+        let metadata = path.metadata()?;
+        if !metadata.is_file() || is_metadata(&path) {
+            continue;
+        }
+        if let Some(tag) = get_tag(&path) {
+            *backlog.entry(tag.to_string()).or_insert(0) += metadata.len();
+        }
+    }
+    Ok(backlog)
+}
+
+/// Remove the metadata sidecars in `profile_dir` whose profile is gone. Returns the number of bytes
+/// freed.
+pub fn remove_orphaned_metadata(profile_dir: &Path, keep: &Path) -> Result<u64> {
+    let files: Vec<PathBuf> = read_dir(profile_dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|e| e.is_file())
+        .collect();
+    let mut freed = 0;
+    for metadata in files.iter().filter(|f| is_metadata(f) && *f != keep) {
+        if files.iter().any(|f| !is_metadata(f) && f.file_stem() == metadata.file_stem()) {
+            continue;
+        }
+        freed += metadata.metadata()?.len();
+        remove_file(metadata)?;
+    }
+    Ok(freed)
+}
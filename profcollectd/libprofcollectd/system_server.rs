@@ -0,0 +1,65 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Selection of system_server and the system processes forked from zygote, whose profiles feed
+//! boot image profile generation.
+
+use anyhow::Result;
+use std::fs::{read_dir, read_to_string};
+
+/// Tag given to traces of system_server and the system processes forked from zygote.
+pub const SYSTEM_SERVER_TAG: &str = "system_server";
+
+/// The uid of system processes, AID_SYSTEM.
+const SYSTEM_UID: u32 = 1000;
+
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub ppid: u32,
+    pub uid: u32,
+    pub name: String,
+}
+
+pub fn read_process_info(pid: u32) -> Option<ProcessInfo> {
+    let status = read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let field = |key: &str| {
+        status.lines().find_map(|line| line.strip_prefix(key)).map(|value| value.trim().to_string())
+    };
+    // Uid: real, effective, saved set and filesystem uids.
+    let uid = field("Uid:")?.split_whitespace().next()?.parse().ok()?;
+    let ppid = field("PPid:")?.parse().ok()?;
+    // The name in status is truncated, and zygote children are renamed through their cmdline.
+    let cmdline = read_to_string(format!("/proc/{}/cmdline", pid)).ok()?;
+    let name = cmdline.split('\0').next().unwrap_or_default().to_string();
+    Some(ProcessInfo { pid, ppid, uid, name })
+}
+
+/// Returns the comma separated pids of system_server and the system processes forked from zygote.
+pub fn get_system_server_processes() -> Result<String> {
+    let processes: Vec<ProcessInfo> = read_dir("/proc")?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().to_str().and_then(|name| name.parse().ok()))
+        .filter_map(read_process_info)
+        .collect();
+    let zygotes: Vec<u32> =
+        processes.iter().filter(|p| p.name.starts_with("zygote")).map(|p| p.pid).collect();
+    let pids: Vec<String> = processes
+        .iter()
+        .filter(|p| p.name == "system_server" || (p.uid == SYSTEM_UID && zygotes.contains(&p.ppid)))
+        .map(|p| p.pid.to_string())
+        .collect();
+    Ok(pids.join(","))
+}
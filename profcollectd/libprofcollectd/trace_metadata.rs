@@ -0,0 +1,431 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Provenance metadata recorded alongside every trace.
+
+use anyhow::{bail, ensure, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{read_dir, read_to_string, remove_file, rename, write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::apex::{get_apex_versions, ApexVersions};
+use crate::config::{Config, TraceClock};
+use crate::kernel_addresses::ScrubDecision;
+
+static METADATA_EXTENSION: &str = "json";
+
+/// Maximum number of annotations of a trace.
+const MAX_ANNOTATIONS: usize = 8;
+/// Maximum length in bytes of an annotation key or value.
+const MAX_ANNOTATION_LENGTH: usize = 64;
+
+/// Generate the ID of a new trace, identifying it from the request to the report.
+pub fn new_correlation_id() -> String {
+    format!("{:016x}", rand::thread_rng().gen::<u64>())
+}
+
+pub fn is_correlation_id(id: &str) -> bool {
+    id.len() == 16 && id.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// Key-value annotations attached to a trace by the caller requesting it.
+pub type Annotations = BTreeMap<String, String>;
+
+/// Check that annotations stay within the limits, so that they do not bloat metadata and reports.
+pub fn check_annotations(annotations: &Annotations) -> Result<()> {
+    ensure!(annotations.len() <= MAX_ANNOTATIONS, "Too many annotations.");
+    for (key, value) in annotations {
+        ensure!(!key.is_empty(), "Empty annotation key.");
+        ensure!(
+            key.len() <= MAX_ANNOTATION_LENGTH && value.len() <= MAX_ANNOTATION_LENGTH,
+            "Annotation {} is too long.",
+            key
+        );
+    }
+    Ok(())
+}
+
+/// Add the annotations of `from` missing from `into`, those of `into` taking precedence, within the
+/// limit on the number of annotations. The annotations over the limit are left out.
+pub fn merge_annotations(into: &mut Annotations, from: &Annotations) {
+    for (key, value) in from {
+        if into.len() == MAX_ANNOTATIONS && !into.contains_key(key) {
+            log::info!("Annotation {} left out, over {} annotations.", key, MAX_ANNOTATIONS);
+            continue;
+        }
+        into.entry(key.clone()).or_insert_with(|| value.clone());
+    }
+}
+
+/// Metadata sidecar of a trace. It is written next to the trace file and moved next to the
+/// profile once the trace is processed, so that it is included in reports.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct TraceMetadata {
+    /// The tag of the trace event.
+    pub tag: String,
+    /// ID generated when the trace was initiated, also found in the trace file name and in logs.
+    pub correlation_id: String,
+    /// Tags and correlation IDs of the trace requests merged into this trace while it was being
+    /// recorded.
+    pub merged_tags: Vec<String>,
+    pub merged_correlation_ids: Vec<String>,
+    /// Name of the trace provider that recorded the trace.
+    pub provider: String,
+    /// Position of the provider in the provider chain, 1 for the preferred provider. None if the
+    /// provider is not part of the chain.
+    pub provider_rung: Option<u32>,
+    /// Start time of the trace, in milliseconds since the Unix epoch.
+    pub start_time_ms: u64,
+    /// End time of the trace, in milliseconds since the Unix epoch.
+    pub end_time_ms: u64,
+    /// Start and end time of the trace, in RFC 3339 UTC.
+    pub start_time: String,
+    pub end_time: String,
+    /// State of the device when the trace started.
+    pub device_state: DeviceState,
+    /// Digest of the config the trace was collected under.
+    pub config_digest: String,
+    /// Estimated percentage of trace data lost during the capture, None if the provider does not
+    /// report data loss. Lossy captures can be down-weighted when merging profiles.
+    pub data_loss_percent: Option<f64>,
+    /// Annotations attached by the callers requesting the trace.
+    pub annotations: Annotations,
+    /// Clock of the sample timestamps in the trace.
+    pub clock: TraceClock,
+    /// Readings of the clocks when the trace started and ended, mapping trace timestamps to the
+    /// clock domains of other telemetry, e.g. batterystats and perfetto traces.
+    pub start_clocks: ClockSnapshot,
+    pub end_clocks: ClockSnapshot,
+    /// Versions of the active APEXes when the trace was recorded. Profiles are only merged with
+    /// profiles of the same APEX versions.
+    pub apex_versions: ApexVersions,
+    /// Whether the traced processes exited during the trace, ending it before its sampling
+    /// period. Always false for system-wide traces.
+    pub processes_exited: bool,
+    /// Whether kernel addresses were scrubbed from the profile, recorded when the trace is
+    /// processed. None until then.
+    pub kernel_addresses: Option<ScrubDecision>,
+    /// Score of the profile, set by the score processing stage, see `processing::profile_score`.
+    /// None if the stage is not configured.
+    pub score: Option<f64>,
+}
+
+/// Readings of the realtime, monotonic and boottime clocks, taken back to back.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct ClockSnapshot {
+    pub realtime_ns: u64,
+    pub monotonic_ns: u64,
+    pub boottime_ns: u64,
+}
+
+impl ClockSnapshot {
+    pub fn now() -> Self {
+        ClockSnapshot {
+            realtime_ns: read_clock(libc::CLOCK_REALTIME),
+            monotonic_ns: read_clock(libc::CLOCK_MONOTONIC),
+            boottime_ns: read_clock(libc::CLOCK_BOOTTIME),
+        }
+    }
+}
+
+/// Read `clock` in nanoseconds, 0 if it cannot be read.
+fn read_clock(clock: libc::clockid_t) -> u64 {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // SAFETY: ts is a valid timespec the clock is read into.
+    if unsafe { libc::clock_gettime(clock, &mut ts) } != 0 {
+        return 0;
+    }
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+/// Time since boot, including time spent in suspend.
+pub fn time_since_boot() -> Duration {
+    Duration::from_nanos(read_clock(libc::CLOCK_BOOTTIME))
+}
+
+/// CPU time spent by all threads of profcollectd.
+pub fn process_cpu_time() -> Duration {
+    Duration::from_nanos(read_clock(libc::CLOCK_PROCESS_CPUTIME_ID))
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct DeviceState {
+    /// Battery level in percent.
+    pub battery_level: Option<u32>,
+    /// Whether the device is connected to a power supply.
+    pub charging: Option<bool>,
+    /// Time since boot, in seconds.
+    pub uptime_secs: Option<u64>,
+}
+
+impl DeviceState {
+    fn current() -> Self {
+        let read = |path: &str| read_to_string(path).ok().map(|s| s.trim().to_string());
+        DeviceState {
+            battery_level: read("/sys/class/power_supply/battery/capacity")
+                .and_then(|s| s.parse().ok()),
+            charging: read("/sys/class/power_supply/battery/status")
+                .map(|s| s == "Charging" || s == "Full"),
+            uptime_secs: read("/proc/uptime")
+                .and_then(|s| s.split('.').next().and_then(|s| s.parse().ok())),
+        }
+    }
+}
+
+impl TraceMetadata {
+    /// Start the metadata of a trace which is about to be recorded.
+    pub fn new(tag: &str, correlation_id: &str, provider: &str, config: &Config) -> Self {
+        let now = SystemTime::now();
+        TraceMetadata {
+            tag: tag.to_string(),
+            correlation_id: correlation_id.to_string(),
+            merged_tags: Vec::new(),
+            merged_correlation_ids: Vec::new(),
+            provider: provider.to_string(),
+            provider_rung: None,
+            start_time_ms: to_ms(now),
+            end_time_ms: to_ms(now),
+            start_time: to_rfc3339(now),
+            end_time: to_rfc3339(now),
+            device_state: DeviceState::current(),
+            config_digest: config.digest(),
+            data_loss_percent: None,
+            annotations: Annotations::new(),
+            clock: config.trace_clock,
+            start_clocks: ClockSnapshot::now(),
+            end_clocks: ClockSnapshot::now(),
+            apex_versions: get_apex_versions(),
+            processes_exited: false,
+            kernel_addresses: None,
+            score: None,
+        }
+    }
+
+    /// Mark the trace as finished and write the sidecar of `trace_file`. The trace is discarded if
+    /// an APEX was updated while it was recorded, as its samples may come from either version.
+    pub fn finish(mut self, trace_file: &Path) -> Result<()> {
+        if self.apexes_updated() {
+            remove_file(trace_file)?;
+            bail!("APEXes were updated during the trace, discarded {}.", trace_file.display());
+        }
+        let now = SystemTime::now();
+        self.end_time_ms = to_ms(now);
+        self.end_time = to_rfc3339(now);
+        self.end_clocks = ClockSnapshot::now();
+        write(get_metadata_path(trace_file), serde_json::to_string(&self)?)?;
+        Ok(())
+    }
+
+    /// Merge the metadata of `other`, a later trace whose profile is merged into the profile of
+    /// this one. Its requests are listed as merged into this trace, which ends when `other` ends.
+    /// The device state and start clocks remain those of the first trace.
+    pub fn absorb(&mut self, other: TraceMetadata) {
+        self.merged_correlation_ids.push(other.correlation_id);
+        self.merged_correlation_ids.extend(other.merged_correlation_ids);
+        for tag in other.merged_tags {
+            if tag != self.tag && !self.merged_tags.contains(&tag) {
+                self.merged_tags.push(tag);
+            }
+        }
+        if other.end_time_ms > self.end_time_ms {
+            self.end_time_ms = other.end_time_ms;
+            self.end_time = other.end_time;
+            self.end_clocks = other.end_clocks;
+        }
+        self.data_loss_percent = match (self.data_loss_percent, other.data_loss_percent) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+        merge_annotations(&mut self.annotations, &other.annotations);
+        self.processes_exited |= other.processes_exited;
+    }
+
+    /// Whether an APEX was updated since the trace started.
+    pub fn apexes_updated(&self) -> bool {
+        get_apex_versions() != self.apex_versions
+    }
+}
+
+fn to_ms(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis().try_into().unwrap_or(u64::MAX))
+}
+
+/// Format `time` in RFC 3339 UTC, e.g. 2026-10-14T09:30:00Z. Timestamps in metadata and
+/// manifests use this format, so that they order the same regardless of the device timezone.
+pub fn to_rfc3339(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Format `time` in ISO 8601 basic format UTC, e.g. 20261014T093000Z. Timestamps in file names,
+/// and so in report entries, use this format: it orders like RFC 3339, without the `:` which
+/// hosts such as Windows reject in file names.
+pub fn to_basic_timestamp(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Get the path of the metadata sidecar of a trace or profile file.
+pub fn get_metadata_path(file: &Path) -> PathBuf {
+    file.with_extension(METADATA_EXTENSION)
+}
+
+pub fn is_metadata(file: &Path) -> bool {
+    file.extension().and_then(|f| f.to_str()).filter(|ext| ext == &METADATA_EXTENSION).is_some()
+}
+
+/// Move the sidecars of processed traces next to their profiles, recording `kernel_addresses` in
+/// them if the decision the traces were processed under is known. Sidecars of traces which did
+/// not produce a profile are removed.
+pub fn move_processed_metadata(
+    trace_dir: &Path,
+    profile_dir: &Path,
+    kernel_addresses: Option<ScrubDecision>,
+) -> Result<()> {
+    let list_files = |dir: &Path| -> Result<Vec<PathBuf>> {
+        Ok(read_dir(dir)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|e| e.is_file())
+            .collect())
+    };
+    let traces = list_files(trace_dir)?;
+    let profiles = list_files(profile_dir)?;
+    let has_data = |files: &[PathBuf], metadata: &Path| {
+        files.iter().any(|f| !is_metadata(f) && f.file_stem() == metadata.file_stem())
+    };
+
+    for metadata in traces.iter().filter(|f| is_metadata(f)) {
+        if has_data(&traces, metadata) {
+            // Not processed yet.
+            continue;
+        }
+        if has_data(&profiles, metadata) {
+            let mut profile_metadata = PathBuf::from(profile_dir);
+            profile_metadata.push(metadata.file_name().unwrap());
+            let contents = kernel_addresses.map(|decision| {
+                serde_json::from_str::<TraceMetadata>(&read_to_string(metadata)?)
+                    .map(|contents| TraceMetadata { kernel_addresses: Some(decision), ..contents })
+                    .map_err(anyhow::Error::from)
+            });
+            match contents {
+                Some(Ok(contents)) => {
+                    write(&profile_metadata, serde_json::to_string(&contents)?)?;
+                    remove_file(metadata)?;
+                }
+                Some(Err(e)) => {
+                    // Kept as is, the report leaves out the metadata it cannot read.
+                    log::error!("Malformed metadata {}: {:?}", metadata.display(), e);
+                    rename(metadata, profile_metadata)?;
+                }
+                None => rename(metadata, profile_metadata)?,
+            }
+        } else {
+            remove_file(metadata)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_annotations, get_metadata_path, merge_annotations, move_processed_metadata,
+        new_correlation_id, to_basic_timestamp, to_rfc3339, Annotations, TraceMetadata,
+        MAX_ANNOTATIONS,
+    };
+    use crate::config::{Config, KernelAddressPolicy};
+    use crate::kernel_addresses::ScrubDecision;
+    use crate::storage::get_tag;
+    use crate::trace_provider::get_path;
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn merged_annotations_stay_within_the_limit() {
+        let annotations = |prefix: &str| -> Annotations {
+            (0..MAX_ANNOTATIONS).map(|i| (format!("{}{}", prefix, i), prefix.to_string())).collect()
+        };
+        let mut merged = annotations("a");
+        merged.remove("a0");
+        merge_annotations(&mut merged, &annotations("b"));
+        assert_eq!(merged.len(), MAX_ANNOTATIONS);
+        assert!(check_annotations(&merged).is_ok());
+        assert_eq!(merged.get("b0").map(String::as_str), Some("b"));
+
+        // The annotations already there take precedence, even once at the limit.
+        let mut with_a1 = annotations("b");
+        with_a1.insert("a1".to_string(), "b".to_string());
+        merge_annotations(&mut merged, &with_a1);
+        assert_eq!(merged.len(), MAX_ANNOTATIONS);
+        assert_eq!(merged.get("a1").map(String::as_str), Some("a"));
+    }
+
+    #[test]
+    fn formats_timestamps_in_utc() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_791_979_200);
+        assert_eq!(to_rfc3339(time), "2026-10-14T12:00:00Z");
+        assert_eq!(to_basic_timestamp(time), "20261014T120000Z");
+        // Sub-second precision is dropped.
+        let time = time + Duration::from_millis(999);
+        assert_eq!(to_rfc3339(time), "2026-10-14T12:00:00Z");
+        assert_eq!(to_basic_timestamp(time), "20261014T120000Z");
+        // Basic timestamps order like the times they format.
+        let later = time + Duration::from_secs(36 * 3600 + 1);
+        assert_eq!(to_basic_timestamp(later), "20261016T000001Z");
+        assert!(to_basic_timestamp(later) > to_basic_timestamp(time));
+    }
+
+    #[test]
+    fn file_names_have_no_colon() {
+        let id = new_correlation_id();
+        let path = get_path(Path::new("/data"), "camera_launch", &id, "data");
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert!(!name.contains(':'), "{}", name);
+        assert_eq!(get_tag(&path), Some("camera_launch"));
+        assert!(name.ends_with(&format!("_camera_launch_{}.data", id)));
+    }
+
+    #[test]
+    fn records_kernel_addresses_in_processed_metadata() {
+        let root = temp_dir().join(format!("profcollectd_metadata_{}", new_correlation_id()));
+        let (trace_dir, profile_dir) = (root.join("trace"), root.join("profile"));
+        create_dir_all(&trace_dir).unwrap();
+        create_dir_all(&profile_dir).unwrap();
+        let config = Config::from_env().unwrap();
+        let name = "20261014T120000Z_tag_0123456789abcdef";
+        let metadata = TraceMetadata::new("tag", "0123456789abcdef", "logging", &config);
+        assert_eq!(metadata.kernel_addresses, None);
+        metadata.finish(&trace_dir.join(name).with_extension("logtrace")).unwrap();
+        // The trace was processed into a profile, and removed.
+        write(profile_dir.join(name).with_extension("logging"), "").unwrap();
+
+        let decision = ScrubDecision::decide(KernelAddressPolicy::KptrRestrict, Some(2));
+        move_processed_metadata(&trace_dir, &profile_dir, Some(decision)).unwrap();
+
+        assert!(!get_metadata_path(&trace_dir.join(name)).exists());
+        let moved = read_to_string(get_metadata_path(&profile_dir.join(name))).unwrap();
+        let moved: TraceMetadata = serde_json::from_str(&moved).unwrap();
+        assert_eq!(moved.kernel_addresses, Some(decision));
+        assert_eq!(moved.correlation_id, "0123456789abcdef");
+        remove_dir_all(root).unwrap();
+    }
+}
@@ -16,63 +16,171 @@
 
 //! ProfCollect trace provider trait and helper functions.
 
-use anyhow::{anyhow, Result};
-use chrono::Utc;
+use anyhow::Result;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
+use crate::config::{Config, TraceClock};
+use crate::disabled_trace_provider::{DisabledTraceProvider, DISABLED_PROVIDER_NAME};
+use crate::in_memory_trace::InMemoryTrace;
+use crate::provider_chain::ProviderChain;
 use crate::simpleperf_etm_trace_provider::SimpleperfEtmTraceProvider;
 use crate::simpleperf_lbr_trace_provider::SimpleperfLbrTraceProvider;
+use crate::trace_metadata::to_basic_timestamp;
 
 #[cfg(feature = "test")]
 use crate::logging_trace_provider::LoggingTraceProvider;
 
+/// Names of all the trace providers, whose trace and profile directories may be left over from
+/// before a provider change.
+pub const PROVIDER_NAMES: &[&str] =
+    &["simpleperf_etm", "simpleperf_lbr", "logging", DISABLED_PROVIDER_NAME];
+
+/// Format of the profiles traces are decoded into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProfileFormat {
+    /// Branch lists, which are converted to AutoFDO profiles after collection.
+    BranchList,
+    /// Profiles for BOLT post-link optimization, see `is_bolt_profile`.
+    Bolt,
+    /// Text AutoFDO profiles, only merged into for on-device summaries, see `profile_summary`.
+    AutoFdo,
+}
+
+impl ProfileFormat {
+    /// Value of the --output option of simpleperf inject.
+    pub fn inject_output(&self) -> &'static str {
+        match self {
+            ProfileFormat::BranchList => "branch-list",
+            ProfileFormat::Bolt => "bolt",
+            ProfileFormat::AutoFdo => "autofdo",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ProfileFormat::BranchList => "data",
+            ProfileFormat::Bolt => BOLT_PROFILE_EXTENSION,
+            ProfileFormat::AutoFdo => "autofdo",
+        }
+    }
+}
+
+static BOLT_PROFILE_EXTENSION: &str = "fdata";
+
+/// Whether `file` is a BOLT profile. BOLT profiles are written next to the branch-list profile of
+/// the same trace, and packed separately in reports.
+pub fn is_bolt_profile(file: &Path) -> bool {
+    file.extension().and_then(|f| f.to_str()).filter(|ext| ext == &BOLT_PROFILE_EXTENSION).is_some()
+}
+
 pub trait TraceProvider {
     fn get_name(&self) -> &'static str;
     fn is_ready(&self) -> bool;
+    /// Record a system-wide trace, returns the path of the trace file. The file is named after
+    /// `tag` and `correlation_id`, see `get_path`. If `in_memory` is given, the trace is recorded
+    /// into it instead, see `output_path`, and the returned file is not created.
     fn trace_system(
         &self,
         trace_dir: &Path,
         tag: &str,
+        correlation_id: &str,
+        in_memory: Option<&InMemoryTrace>,
         sampling_period: &Duration,
         binary_filter: &str,
-    );
+    ) -> Result<Box<Path>>;
+    /// Record a trace of the given processes, returns the path of the trace file.
     fn trace_process(
         &self,
         trace_dir: &Path,
         tag: &str,
+        correlation_id: &str,
+        in_memory: Option<&InMemoryTrace>,
         sampling_period: &Duration,
         processes: &str,
-    );
-    fn process(&self, trace_dir: &Path, profile_dir: &Path, binary_filter: &str) -> Result<()>;
+    ) -> Result<Box<Path>>;
+    /// Estimated percentage of trace data lost while recording `trace_file`, None if unknown.
+    fn get_data_loss(&self, trace_file: &Path) -> Option<f64>;
+    /// Adjust recording parameters, within the bounds set by `config`, after a capture which lost
+    /// `data_loss_percent` of its trace data.
+    fn adjust_to_data_loss(&self, config: &Config, data_loss_percent: f64);
+    /// Process the traces in `trace_dir` into a profile of each of `formats` per trace. Traces are
+    /// decoded one at a time, as the simpleperf library is not reentrant. If
+    /// `hide_kernel_addresses` is set, kernel addresses are rebased so that profiles do not reveal
+    /// the KASLR offset.
+    fn process(
+        &self,
+        trace_dir: &Path,
+        profile_dir: &Path,
+        binary_filter: &str,
+        hide_kernel_addresses: bool,
+        formats: &[ProfileFormat],
+    ) -> Result<()>;
+    /// Merge `profiles` into a single profile `output` of `format`, restricted to the binaries
+    /// matching `binary_filter`.
+    fn merge(
+        &self,
+        profiles: &[PathBuf],
+        binary_filter: &str,
+        format: ProfileFormat,
+        output: &Path,
+    ) -> Result<()>;
+    /// Select the clock used to timestamp the samples of subsequent traces.
+    fn set_trace_clock(&self, clock: TraceClock);
     fn set_log_file(&self, filename: &Path);
     fn reset_log_file(&self);
 }
 
-pub fn get_trace_provider() -> Result<Arc<Mutex<dyn TraceProvider + Send>>> {
-    if SimpleperfEtmTraceProvider::supported() {
-        log::info!("simpleperf_etm trace provider registered.");
-        return Ok(Arc::new(Mutex::new(SimpleperfEtmTraceProvider {})));
+/// Get the first provider of `chain` supported by the device, and make it the active one. Returns
+/// the disabled provider if no provider of the chain is left.
+pub fn get_trace_provider(chain: &ProviderChain) -> Box<dyn TraceProvider + Send> {
+    #[cfg(feature = "test")]
+    if LoggingTraceProvider::forced() {
+        log::info!("logging trace provider forced.");
+        return Box::new(LoggingTraceProvider {});
     }
-    if SimpleperfLbrTraceProvider::supported() {
-        log::info!("simpleperf_lbr trace provider registered.");
-        return Ok(Arc::new(Mutex::new(SimpleperfLbrTraceProvider {})));
+
+    for name in chain.candidates() {
+        let provider: Box<dyn TraceProvider + Send> = match name.as_str() {
+            "simpleperf_etm" if SimpleperfEtmTraceProvider::supported() => {
+                Box::new(SimpleperfEtmTraceProvider::new())
+            }
+            "simpleperf_lbr" if SimpleperfLbrTraceProvider::supported() => {
+                Box::new(SimpleperfLbrTraceProvider::new())
+            }
+            _ => {
+                log::info!("{} trace provider not available.", name);
+                continue;
+            }
+        };
+        log::info!("{} trace provider registered.", name);
+        chain.set_active(Some(&name));
+        return provider;
     }
+    chain.set_active(None);
 
     #[cfg(feature = "test")]
     if LoggingTraceProvider::supported() {
         log::info!("logging trace provider registered.");
-        return Ok(Arc::new(Mutex::new(LoggingTraceProvider {})));
+        return Box::new(LoggingTraceProvider {});
     }
 
-    Err(anyhow!("No trace provider found for this device."))
+    log::error!("No trace provider left for this device, collection is disabled.");
+    Box::new(DisabledTraceProvider {})
 }
 
-pub fn get_path(dir: &Path, tag: &str, ext: &str) -> Box<Path> {
-    let filename = format!("{}_{}", Utc::now().format("%Y%m%d-%H%M%S"), tag);
+/// Path of a new trace file in `dir`, named `<timestamp>_<tag>_<correlation id>.<ext>`, see
+/// `to_basic_timestamp`.
+pub fn get_path(dir: &Path, tag: &str, correlation_id: &str, ext: &str) -> Box<Path> {
+    let filename = format!("{}_{}_{}", to_basic_timestamp(SystemTime::now()), tag, correlation_id);
     let mut trace_file = PathBuf::from(dir);
     trace_file.push(filename);
     trace_file.set_extension(ext);
     trace_file.into_boxed_path()
 }
+
+/// Path the provider records `trace_file` to: the memfd of `in_memory` if given, otherwise the
+/// trace file itself.
+pub fn output_path(trace_file: &Path, in_memory: Option<&InMemoryTrace>) -> PathBuf {
+    in_memory.map_or_else(|| trace_file.to_path_buf(), |m| m.path().to_path_buf())
+}
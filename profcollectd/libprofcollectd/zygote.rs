@@ -0,0 +1,157 @@
+//
+// Copyright (C) 2026 The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Traces of zygote preloading classes and libraries, and of its children dynamically linking
+//! before they specialize, on hints from zygote. This code runs once per boot, or in the first
+//! moments of each app, so it is nearly absent from periodic traces though it dominates app start.
+
+use anyhow::{ensure, Context, Result};
+use rand::Rng;
+use std::fs::{read_dir, read_link};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::system_server::read_process_info;
+
+/// Annotation giving the zygote phase a trace was hinted for.
+pub const ZYGOTE_PHASE_ANNOTATION: &str = "zygote_phase";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ZygotePhase {
+    Preload,
+    Fork,
+}
+
+impl ZygotePhase {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ZygotePhase::Preload => "preload",
+            ZygotePhase::Fork => "fork",
+        }
+    }
+
+    pub fn tag(&self) -> &'static str {
+        match self {
+            ZygotePhase::Preload => "zygote-preload",
+            ZygotePhase::Fork => "zygote-fork",
+        }
+    }
+
+    /// Sampling period of the trace of the phase, None if the phase is not traced this time.
+    pub fn sampling_period(&self, config: &Config) -> Option<Duration> {
+        let period = match self {
+            ZygotePhase::Preload => config.zygote_preload_trace_period,
+            ZygotePhase::Fork
+                if rand::thread_rng().gen_range(0..100) < config.zygote_fork_trace_freq =>
+            {
+                config.zygote_fork_trace_period
+            }
+            ZygotePhase::Fork => Duration::ZERO,
+        };
+        (!period.is_zero()).then_some(period)
+    }
+}
+
+/// Uid and SELinux context of zygote, the only caller allowed to hint its phases.
+const ZYGOTE_UID: u32 = 0;
+const ZYGOTE_CONTEXT: &str = "u:r:zygote:s0";
+
+/// Pid of init, which starts the zygotes.
+const INIT_PID: u32 = 1;
+
+/// Whether a process running `exe`, child of `ppid`, is a zygote: app_process started by init.
+/// The children of zygote run app_process too, until they exec, but zygote is their parent.
+fn is_zygote(exe: &Path, ppid: u32) -> bool {
+    ppid == INIT_PID
+        && exe.file_name().and_then(|f| f.to_str()).is_some_and(|f| f.starts_with("app_process"))
+}
+
+/// Pids of the running zygotes, as started by init.
+pub fn zygote_pids() -> Vec<u32> {
+    let Ok(entries) = read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().to_str().and_then(|name| name.parse().ok()))
+        .filter_map(read_process_info)
+        .filter(|p| {
+            read_link(format!("/proc/{}/exe", p.pid)).is_ok_and(|exe| is_zygote(&exe, p.ppid))
+        })
+        .map(|p| p.pid)
+        .collect()
+}
+
+/// Check that the binder caller, of `uid` and SELinux `context`, is zygote. The calling pid is
+/// not checked, the hints are oneway calls, which have none.
+pub fn check_caller(uid: u32, context: Option<&str>) -> Result<()> {
+    ensure!(uid == ZYGOTE_UID, "Caller uid {} is not zygote.", uid);
+    ensure!(
+        context == Some(ZYGOTE_CONTEXT),
+        "Caller context {} is not zygote.",
+        context.unwrap_or("unknown")
+    );
+    Ok(())
+}
+
+/// Check that `pid` is in `phase`: one of `zygotes` for the preload phase, or a child of one for
+/// the fork phase, so that hints cannot be used to trace other processes.
+pub fn check_zygote_process(phase: ZygotePhase, pid: u32, zygotes: &[u32]) -> Result<()> {
+    match phase {
+        ZygotePhase::Preload => ensure!(zygotes.contains(&pid), "Process {} is not zygote.", pid),
+        ZygotePhase::Fork => {
+            let process = read_process_info(pid).with_context(|| format!("No process {}.", pid))?;
+            ensure!(zygotes.contains(&process.ppid), "Process {} was not forked from zygote.", pid)
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_caller, check_zygote_process, is_zygote, ZygotePhase, ZYGOTE_CONTEXT};
+    use std::path::Path;
+    use std::process;
+
+    #[test]
+    fn only_zygote_can_hint() {
+        let context = Some(ZYGOTE_CONTEXT);
+        assert!(check_caller(0, context).is_ok());
+        assert!(check_caller(1000, context).is_err());
+        assert!(check_caller(0, Some("u:r:untrusted_app:s0")).is_err());
+        assert!(check_caller(0, None).is_err());
+    }
+
+    #[test]
+    fn zygotes_are_app_process_started_by_init() {
+        assert!(is_zygote(Path::new("/system/bin/app_process64"), 1));
+        assert!(is_zygote(Path::new("/system/bin/app_process32"), 1));
+        // A child of zygote which has not exec'd.
+        assert!(!is_zygote(Path::new("/system/bin/app_process64"), 600));
+        assert!(!is_zygote(Path::new("/system/bin/surfaceflinger"), 1));
+    }
+
+    #[test]
+    fn checks_processes_against_zygote_pids() {
+        let pid = process::id();
+        let parent = std::os::unix::process::parent_id();
+        assert!(check_zygote_process(ZygotePhase::Preload, pid, &[pid]).is_ok());
+        assert!(check_zygote_process(ZygotePhase::Preload, pid, &[parent]).is_err());
+        assert!(check_zygote_process(ZygotePhase::Fork, pid, &[parent]).is_ok());
+        assert!(check_zygote_process(ZygotePhase::Fork, pid, &[pid]).is_err());
+    }
+}
@@ -18,9 +18,11 @@
 
 use anyhow::{bail, Context, Result};
 use std::env;
+use std::io::{self, Write};
+use std::time::Duration;
 
 const HELP_MSG: &str = r#"
-usage: profcollectctl [command]
+usage: profcollectctl [command] [tags]
 
 Command to control profcollectd behaviour.
 
@@ -30,7 +32,17 @@ command:
     trace       Request an one-off system-wide trace.
     process     Convert traces to perf profiles.
     reconfig    Refresh configuration.
-    report      Create a report containing all profiles.
+    report      Create a report containing all profiles, or only those of the comma-separated
+                tags if given.
+    freshness   Show the age of the newest profile and report.
+    status      Show the status of profcollectd.
+    simulate    Show the periodic traces planned for the next 24 hours.
+    summary     Summarize the processed profiles, listing the functions executed the most.
+    diagnostics Write a diagnostics bundle for bug reports to stdout.
+    cleanup     Remove stale files, expired reports and traces over the storage limit.
+    hardware    Show the profiling capabilities of the hardware.
+    arm-boot    Trace the boot on the next start of profcollectd only.
+    disarm-boot Skip the boot trace on the next start of profcollectd only.
     reset       Clear all local data.
     help        Print this message.
 "#;
@@ -39,7 +51,7 @@ fn main() -> Result<()> {
     libprofcollectd::init_logging();
 
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
+    if args.len() != 2 && !(args.len() == 3 && args[1] == "report") {
         bail!("This program only takes one argument{}", &HELP_MSG);
     }
 
@@ -63,9 +75,68 @@ fn main() -> Result<()> {
         }
         "report" => {
             println!("Creating profile report");
-            let path = libprofcollectd::report().context("Failed to create profile report.")?;
+            let path = match args.get(2) {
+                Some(tags) => {
+                    let tags: Vec<String> = tags.split(',').map(str::to_string).collect();
+                    libprofcollectd::report_for_tags(&tags)
+                }
+                None => libprofcollectd::report(),
+            }
+            .context("Failed to create profile report.")?;
             println!("Report created at: {}", &path);
         }
+        "freshness" => {
+            let (profile_age, report_age) = libprofcollectd::get_profile_freshness()
+                .context("Failed to get profile freshness.")?;
+            let format_age = |age: Option<Duration>| match age {
+                Some(age) => format!("{}s", age.as_secs()),
+                None => "none".to_string(),
+            };
+            println!("Newest profile age: {}", format_age(profile_age));
+            println!("Newest report age: {}", format_age(report_age));
+        }
+        "status" => {
+            let status = libprofcollectd::get_status().context("Failed to get status.")?;
+            print!("{}", &status);
+        }
+        "simulate" => {
+            let timeline =
+                libprofcollectd::simulate_schedule().context("Failed to simulate schedule.")?;
+            print!("{}", &timeline);
+        }
+        "summary" => {
+            let summary =
+                libprofcollectd::summarize_profiles().context("Failed to summarize profiles.")?;
+            print!("{}", &summary);
+        }
+        "diagnostics" => {
+            let bundle = libprofcollectd::get_diagnostics_bundle()
+                .context("Failed to get diagnostics bundle.")?;
+            io::stdout().write_all(&bundle).context("Failed to write diagnostics bundle.")?;
+        }
+        "cleanup" => {
+            let freed = libprofcollectd::run_cleanup().context("Failed to clean up.")?;
+            println!("Freed {} bytes.", freed);
+        }
+        "hardware" => {
+            let capabilities = libprofcollectd::get_hardware_capabilities()
+                .context("Failed to get hardware capabilities.")?;
+            println!("ETM: {}", capabilities.etm);
+            println!("ETM features: {}", capabilities.etmFeatures.join(" "));
+            println!("ETM sinks: {}", capabilities.etmSinks.join(" "));
+            println!("SPE: {}", capabilities.spe);
+            println!("SPE features: {}", capabilities.speFeatures.join(" "));
+            println!("LBR: {}", capabilities.lbr);
+            println!("PMU events: {}", capabilities.pmuEvents.join(" "));
+        }
+        "arm-boot" | "disarm-boot" => {
+            let enable = action == "arm-boot";
+            libprofcollectd::arm_boot_trace(enable).context("Failed to arm the boot trace.")?;
+            println!(
+                "Boot trace {} for the next start.",
+                if enable { "armed" } else { "disarmed" }
+            );
+        }
         "reset" => {
             libprofcollectd::reset().context("Failed to reset.")?;
             println!("Reset done.");
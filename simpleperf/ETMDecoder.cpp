@@ -975,6 +975,9 @@ android::base::expected<void, std::string> ConvertETMBranchMapToInstrRanges(
 
         if (b) {
           instr.instr_addr = instr.branch_addr;
+          // In 32-bit binaries, taken branches like blx switch between arm and thumb code. So the
+          // instructions after a branch are decoded in the isa it branches to.
+          instr.isa = instr.next_isa;
         } else {
           instr.instr_addr += instr.instr_size;
         }
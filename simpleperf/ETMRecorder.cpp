@@ -21,6 +21,7 @@
 
 #include <limits>
 #include <memory>
+#include <set>
 #include <string>
 
 #include <android-base/expected.h>
@@ -180,13 +181,57 @@ bool ETMRecorder::ReadEtmInfo() {
   return (etm_info_.size() == online_cpus.size());
 }
 
+std::map<int, uint32_t> FindEtrPerCpu(const std::string& etm_dir) {
+  std::map<std::string, uint32_t> etr_configs;
+  for (const auto& name : GetEntriesInDir(etm_dir + "sinks")) {
+    std::string s;
+    uint32_t config;
+    if (name.find("etr") != -1 &&
+        android::base::ReadFileToString(etm_dir + "sinks/" + name, &s) &&
+        android::base::ParseUint(android::base::Trim(s), &config)) {
+      etr_configs[name] = config;
+    }
+  }
+  std::map<int, uint32_t> result;
+  for (const auto& name : GetEntriesInDir(etm_dir)) {
+    int cpu;
+    if (sscanf(name.c_str(), "cpu%d", &cpu) != 1) {
+      continue;
+    }
+    // Walk the devices downstream of the etm, through funnels and replicators, until an etr.
+    std::vector<std::string> paths = {etm_dir + name};
+    std::set<std::string> visited;
+    while (!paths.empty()) {
+      std::string path;
+      bool is_new = android::base::Realpath(paths.back(), &path) && visited.insert(path).second;
+      paths.pop_back();
+      if (!is_new) {
+        continue;
+      }
+      if (auto it = etr_configs.find(android::base::Basename(path)); it != etr_configs.end()) {
+        result[cpu] = it->second;
+        break;
+      }
+      for (const auto& entry : GetEntriesInDir(path)) {
+        if (android::base::StartsWith(entry, "out:")) {
+          paths.emplace_back(path + "/" + entry);
+        }
+      }
+    }
+  }
+  return result;
+}
+
 bool ETMRecorder::FindSinkConfig() {
-  bool has_etr = false;
+  size_t etr_count = 0;
   bool has_trbe = false;
   for (const auto& name : GetEntriesInDir(ETM_DIR + "sinks")) {
-    if (!has_etr && name.find("etr") != -1) {
-      if (ReadValueInEtmDir("sinks/" + name, &sink_config_)) {
-        has_etr = true;
+    if (name.find("etr") != -1) {
+      uint32_t config;
+      if (ReadValueInEtmDir("sinks/" + name, &config)) {
+        if (etr_count++ == 0) {
+          sink_config_ = config;
+        }
       }
     }
     if (name.find("trbe") != -1) {
@@ -198,8 +243,18 @@ bool ETMRecorder::FindSinkConfig() {
     // When TRBE is present, let the driver choose the most suitable
     // configuration.
     sink_config_ = 0;
+  } else if (etr_count > 1) {
+    // Some SoCs have an ETR per cluster, and a single sink is only reachable from the ETMs of
+    // one cluster, so data from the other clusters would be dropped. Route the ETM of each cpu
+    // to the ETR on its path instead, or let the driver pick the default sink of cpus whose path
+    // isn't known. Each cpu has its own aux buffer, so the data of all clusters is decoded
+    // together when processing.
+    cpu_sink_config_ = FindEtrPerCpu(ETM_DIR);
+    LOG(DEBUG) << "found " << etr_count << " etr devices, routing " << cpu_sink_config_.size()
+               << " cpus to the etr on their path";
+    sink_config_ = 0;
   }
-  return has_trbe || has_etr;
+  return has_trbe || etr_count > 0;
 }
 
 void ETMRecorder::SetEtmPerfEventAttr(perf_event_attr* attr) {
@@ -210,6 +265,12 @@ void ETMRecorder::SetEtmPerfEventAttr(perf_event_attr* attr) {
   attr->config3 = cc_threshold_config_;
 }
 
+void ETMRecorder::SetEtmSinkForCpu(perf_event_attr* attr, int cpu) {
+  if (auto it = cpu_sink_config_.find(cpu); it != cpu_sink_config_.end()) {
+    attr->config2 = it->second;
+  }
+}
+
 void ETMRecorder::BuildEtmConfig() {
   if (etm_event_config_ == 0) {
     if (use_contextid2_) {
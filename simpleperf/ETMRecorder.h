@@ -20,6 +20,7 @@
 
 #include <map>
 #include <memory>
+#include <string>
 
 #include <android-base/expected.h>
 
@@ -47,10 +48,16 @@ struct ETMPerCpu {
 
 // Help recording Coresight ETM data on ARM devices.
 // 1. Get etm event type on device.
-// 2. Get sink config, which selects the ETR device moving etm data to memory.
+// 2. Get sink config, which selects the ETR device moving etm data to memory. On devices with
+//    TRBE, the driver selects the sink of each cpu. On devices with several ETRs, each cpu is
+//    routed to the ETR on its path.
 // 3. Get etm info on each cpu.
 // The etm event type and sink config are used to build perf_event_attr for etm data tracing.
 // The etm info is kept in perf.data to help etm decoding.
+// Find the ETR reachable from the etm of each cpu in etm_dir, following the out:<port> links
+// between coresight devices in sysfs. Returns the sink configs of the ETRs by cpu.
+std::map<int, uint32_t> FindEtrPerCpu(const std::string& etm_dir);
+
 class ETMRecorder {
  public:
   static ETMRecorder& GetInstance();
@@ -61,6 +68,8 @@ class ETMRecorder {
   bool IsETMDriverAvailable();
   android::base::expected<bool, std::string> CheckEtmSupport();
   void SetEtmPerfEventAttr(perf_event_attr* attr);
+  // Select the sink of the etm event of a cpu, on devices with several ETRs.
+  void SetEtmSinkForCpu(perf_event_attr* attr, int cpu);
   AuxTraceInfoRecord CreateAuxTraceInfoRecord();
   size_t GetAddrFilterPairs();
   void SetRecordTimestamp(bool record);
@@ -76,6 +85,8 @@ class ETMRecorder {
   bool etm_supported_ = false;
   // select ETR device, setting in perf_event_attr->config2
   uint32_t sink_config_ = 0;
+  // select the ETR device of each cpu on devices with several ETRs, overriding sink_config_
+  std::map<int, uint32_t> cpu_sink_config_;
   // use EL2 PID tracing or not
   bool use_contextid2_ = false;
   // select etm options (timestamp, context_id, ...), setting in perf_event_attr->config
@@ -0,0 +1,78 @@
+/*
+ * Copyright (C) 2026 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#include "ETMRecorder.h"
+
+#include <gtest/gtest.h>
+#include <unistd.h>
+
+#include <filesystem>
+
+#include <android-base/file.h>
+
+namespace fs = std::filesystem;
+using namespace simpleperf;
+
+// Build a coresight topology with an ETR per cluster: cpu0 and cpu1 reach tmc_etr0 through
+// funnel0, and cpu2 reaches tmc_etr1 through funnel1 and a replicator.
+static void CreateTwoClusterTopology(const std::string& root) {
+  std::string devices = root + "/devices/";
+  for (const char* name : {"etm0", "etm1", "etm2", "funnel0", "funnel1", "replicator0", "tmc_etr0",
+                           "tmc_etr1", "tmc_etf0"}) {
+    ASSERT_TRUE(fs::create_directories(devices + name));
+  }
+  auto connect = [&](const std::string& from, const std::string& to, int port = 0) {
+    std::string link = devices + from + "/out:" + std::to_string(port);
+    ASSERT_EQ(symlink(("../" + to).c_str(), link.c_str()), 0);
+  };
+  connect("etm0", "funnel0");
+  connect("etm1", "funnel0");
+  connect("funnel0", "tmc_etr0");
+  connect("etm2", "funnel1");
+  connect("funnel1", "replicator0");
+  connect("replicator0", "tmc_etf0");
+  connect("replicator0", "tmc_etr1", 1);
+
+  std::string etm_dir = root + "/cs_etm/";
+  ASSERT_TRUE(fs::create_directories(etm_dir + "sinks"));
+  for (int cpu = 0; cpu < 3; cpu++) {
+    std::string cpu_link = etm_dir + "cpu" + std::to_string(cpu);
+    ASSERT_EQ(symlink((devices + "etm" + std::to_string(cpu)).c_str(), cpu_link.c_str()), 0);
+  }
+  ASSERT_TRUE(android::base::WriteStringToFile("0x1234\n", etm_dir + "sinks/tmc_etr0"));
+  ASSERT_TRUE(android::base::WriteStringToFile("0x5678\n", etm_dir + "sinks/tmc_etr1"));
+  ASSERT_TRUE(android::base::WriteStringToFile("0x9abc\n", etm_dir + "sinks/tmc_etf0"));
+}
+
+// @CddTest = 6.1/C-0-2
+TEST(ETMRecorder, FindEtrPerCpu) {
+  TemporaryDir tmpdir;
+  CreateTwoClusterTopology(tmpdir.path);
+  std::map<int, uint32_t> expected = {{0, 0x1234}, {1, 0x1234}, {2, 0x5678}};
+  ASSERT_EQ(FindEtrPerCpu(std::string(tmpdir.path) + "/cs_etm/"), expected);
+}
+
+// @CddTest = 6.1/C-0-2
+TEST(ETMRecorder, FindEtrPerCpu_without_connections) {
+  // Kernels which don't expose the connections between devices leave the choice to the driver.
+  TemporaryDir tmpdir;
+  std::string etm_dir = std::string(tmpdir.path) + "/cs_etm/";
+  ASSERT_TRUE(fs::create_directories(etm_dir + "sinks"));
+  ASSERT_TRUE(fs::create_directories(std::string(tmpdir.path) + "/etm0"));
+  ASSERT_EQ(symlink((std::string(tmpdir.path) + "/etm0").c_str(), (etm_dir + "cpu0").c_str()), 0);
+  ASSERT_TRUE(android::base::WriteStringToFile("0x1234\n", etm_dir + "sinks/tmc_etr0"));
+  ASSERT_TRUE(FindEtrPerCpu(etm_dir).empty());
+}
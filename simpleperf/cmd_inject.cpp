@@ -52,6 +52,7 @@ struct AddrPairHash {
 enum class OutputFormat {
   AutoFDO,
   BranchList,
+  Bolt,
 };
 
 struct AutoFDOBinaryInfo {
@@ -119,6 +120,21 @@ static uint64_t GetFirstLoadSegmentVaddr(const Dso* dso) {
   return 0;
 }
 
+// Raw kernel addresses are rebased onto this address by --hide-kernel-addresses. They keep their
+// offsets into the kernel image, but not its randomized load address.
+constexpr uint64_t kHiddenKernelStartAddr = 0xffffffc008000000ULL;
+
+// Rebase the raw kernel addresses of binary, loaded at key.kernel_start_addr, onto
+// kHiddenKernelStartAddr.
+static void RebaseKernelAddresses(BinaryKey& key, ETMBinary& binary) {
+  UnorderedETMBranchMap branch_map;
+  for (auto& p : binary.branch_map) {
+    branch_map[p.first - key.kernel_start_addr + kHiddenKernelStartAddr] = std::move(p.second);
+  }
+  binary.branch_map = std::move(branch_map);
+  key.kernel_start_addr = kHiddenKernelStartAddr;
+}
+
 // Base class for reading perf.data and generating AutoFDO or branch list data.
 class PerfDataReader {
  public:
@@ -149,6 +165,7 @@ class PerfDataReader {
   void AddCallback(const AutoFDOBinaryCallback& callback) { autofdo_callback_ = callback; }
   void AddCallback(const ETMBinaryCallback& callback) { etm_binary_callback_ = callback; }
   void AddCallback(const LBRDataCallback& callback) { lbr_data_callback_ = callback; }
+  void HideKernelAddresses() { hide_kernel_addresses_ = true; }
 
   virtual bool Read() {
     if (exclude_perf_) {
@@ -197,6 +214,7 @@ class PerfDataReader {
   std::unique_ptr<RecordFileReader> reader_;
   bool exclude_perf_;
   BinaryFilter binary_filter_;
+  bool hide_kernel_addresses_ = false;
 
   std::optional<int> exclude_pid_;
   ThreadTree thread_tree_;
@@ -323,7 +341,11 @@ class ETMPerfDataReader : public PerfDataReader {
       if (!binary_filter_.Filter(key.path)) {
         continue;
       }
-      etm_binary_callback_(key, binary);
+      BinaryKey new_key = key;
+      if (hide_kernel_addresses_ && new_key.kernel_start_addr != 0) {
+        RebaseKernelAddresses(new_key, binary);
+      }
+      etm_binary_callback_(new_key, binary);
     }
     return true;
   }
@@ -361,6 +383,9 @@ class ETMPerfDataReader : public PerfDataReader {
           // vmlinux isn't available. We still use kernel ip addr. Put kernel start addr in proto
           // for address conversion later.
           key.kernel_start_addr = kernel_map_start_addr_;
+          if (hide_kernel_addresses_) {
+            RebaseKernelAddresses(key, binary);
+          }
         }
       }
       etm_binary_callback_(key, binary);
@@ -480,8 +505,15 @@ class LBRPerfDataReader : public PerfDataReader {
     if (thread_tree_.IsUnknownDso(dso) || !binary_filter_.Filter(dso)) {
       return std::make_pair(0, 0);
     }
-    uint32_t binary_id = GetBinaryId(dso);
     uint64_t vaddr_in_file = dso->IpToVaddrInFile(ip, map->start_addr, map->pgoff);
+    if (hide_kernel_addresses_ && dso->type() == DSO_KERNEL && vaddr_in_file == ip) {
+      // The address couldn't be converted to a vaddr in vmlinux, it is still a kernel ip addr.
+      if (map->start_addr == 0) {
+        return std::make_pair(0, 0);
+      }
+      vaddr_in_file = ip - map->start_addr + kHiddenKernelStartAddr;
+    }
+    uint32_t binary_id = GetBinaryId(dso);
     return std::make_pair(binary_id, vaddr_in_file);
   }
 
@@ -670,16 +702,7 @@ class AutoFDOWriter {
       PLOG(ERROR) << "failed to write to " << output_filename;
       return false;
     }
-    // autofdo_binary_map is used to store instruction ranges, which can have a large amount. And
-    // it has a larger access time (instruction ranges * executed time). So it's better to use
-    // unorder_maps to speed up access time. But we also want a stable output here, to compare
-    // output changes result from code changes. So generate a sorted output here.
-    std::vector<BinaryKey> keys;
-    for (auto& p : binary_map_) {
-      keys.emplace_back(p.first);
-    }
-    std::sort(keys.begin(), keys.end(),
-              [](const BinaryKey& key1, const BinaryKey& key2) { return key1.path < key2.path; });
+    std::vector<BinaryKey> keys = GetSortedKeys();
     if (keys.size() > 1) {
       fprintf(output_fp.get(),
               "// Please split this file. AutoFDO only accepts profile for one binary.\n");
@@ -743,7 +766,62 @@ class AutoFDOWriter {
     return true;
   }
 
+  // Write instruction ranges in the pre-aggregated text format accepted by `perf2bolt --pa`.
+  // Unlike AutoFDO, BOLT uses virtual addrs in a binary.
+  bool WriteBolt(const std::string& output_filename) {
+    std::unique_ptr<FILE, decltype(&fclose)> output_fp(fopen(output_filename.c_str(), "w"), fclose);
+    if (!output_fp) {
+      PLOG(ERROR) << "failed to write to " << output_filename;
+      return false;
+    }
+    std::vector<BinaryKey> keys = GetSortedKeys();
+    if (keys.size() > 1) {
+      fprintf(output_fp.get(),
+              "// Please split this file. BOLT only accepts profile for one binary.\n");
+    }
+    for (const auto& key : keys) {
+      const AutoFDOBinaryInfo& binary = binary_map_[key];
+
+      // Write range_count_map as fall-through ranges. Sort the output by addrs.
+      std::vector<std::pair<AddrPair, uint64_t>> range_counts(binary.range_count_map.begin(),
+                                                              binary.range_count_map.end());
+      std::sort(range_counts.begin(), range_counts.end());
+      for (const auto& p : range_counts) {
+        fprintf(output_fp.get(), "F %" PRIx64 " %" PRIx64 " %" PRIu64 "\n", p.first.first,
+                p.first.second, p.second);
+      }
+
+      // Write branch_count_map as taken branches, without mispredictions. Sort the output by addrs.
+      std::vector<std::pair<AddrPair, uint64_t>> branch_counts(binary.branch_count_map.begin(),
+                                                               binary.branch_count_map.end());
+      std::sort(branch_counts.begin(), branch_counts.end());
+      for (const auto& p : branch_counts) {
+        fprintf(output_fp.get(), "B %" PRIx64 " %" PRIx64 " %" PRIu64 " 0\n", p.first.first,
+                p.first.second, p.second);
+      }
+
+      // Write the binary path in comment.
+      fprintf(output_fp.get(), "// build_id: %s\n", key.build_id.ToString().c_str());
+      fprintf(output_fp.get(), "// %s\n\n", key.path.c_str());
+    }
+    return true;
+  }
+
  private:
+  std::vector<BinaryKey> GetSortedKeys() {
+    // autofdo_binary_map is used to store instruction ranges, which can have a large amount. And
+    // it has a larger access time (instruction ranges * executed time). So it's better to use
+    // unorder_maps to speed up access time. But we also want a stable output here, to compare
+    // output changes result from code changes. So generate a sorted output here.
+    std::vector<BinaryKey> keys;
+    for (auto& p : binary_map_) {
+      keys.emplace_back(p.first);
+    }
+    std::sort(keys.begin(), keys.end(),
+              [](const BinaryKey& key1, const BinaryKey& key2) { return key1.path < key2.path; });
+    return keys;
+  }
+
   std::unordered_map<BinaryKey, AutoFDOBinaryInfo, BinaryKeyHash> binary_map_;
 };
 
@@ -840,9 +918,13 @@ class InjectCommand : public Command {
 "                               autofdo      -- text format accepted by TextSampleReader\n"
 "                                               of AutoFDO\n"
 "                               branch-list  -- protobuf file in etm_branch_list.proto\n"
+"                               bolt         -- pre-aggregated text format accepted by\n"
+"                                               perf2bolt --pa\n"
 "                             Default is autofdo.\n"
 "--dump-etm type1,type2,...   Dump etm data. A type is one of raw, packet and element.\n"
 "--exclude-perf               Exclude trace data for the recording process.\n"
+"--hide-kernel-addresses      Rebase kernel ip addresses which can't be converted to vaddrs in\n"
+"                             vmlinux onto a fixed address, hiding kernel address randomization.\n"
 "--symdir <dir>               Look for binaries in a directory recursively.\n"
 "--allow-mismatched-build-id  Allow mismatched build ids when searching for debug binaries.\n"
 "\n"
@@ -866,6 +948,7 @@ class InjectCommand : public Command {
     if (IsPerfDataFile(input_filenames_[0])) {
       switch (output_format_) {
         case OutputFormat::AutoFDO:
+        case OutputFormat::Bolt:
           return ConvertPerfDataToAutoFDO();
         case OutputFormat::BranchList:
           return ConvertPerfDataToBranchList();
@@ -873,6 +956,7 @@ class InjectCommand : public Command {
     } else {
       switch (output_format_) {
         case OutputFormat::AutoFDO:
+        case OutputFormat::Bolt:
           return ConvertBranchListToAutoFDO();
         case OutputFormat::BranchList:
           return ConvertBranchListToBranchList();
@@ -887,6 +971,7 @@ class InjectCommand : public Command {
         {"--binary", {OptionValueType::STRING, OptionType::SINGLE}},
         {"--dump-etm", {OptionValueType::STRING, OptionType::SINGLE}},
         {"--exclude-perf", {OptionValueType::NONE, OptionType::SINGLE}},
+        {"--hide-kernel-addresses", {OptionValueType::NONE, OptionType::SINGLE}},
         {"-i", {OptionValueType::STRING, OptionType::MULTIPLE}},
         {"-o", {OptionValueType::STRING, OptionType::SINGLE}},
         {"--output", {OptionValueType::STRING, OptionType::SINGLE}},
@@ -913,6 +998,7 @@ class InjectCommand : public Command {
       }
     }
     exclude_perf_ = options.PullBoolValue("--exclude-perf");
+    hide_kernel_addresses_ = options.PullBoolValue("--hide-kernel-addresses");
 
     for (const OptionValue& value : options.PullValues("-i")) {
       std::vector<std::string> files = android::base::Split(value.str_value, ",");
@@ -936,6 +1022,8 @@ class InjectCommand : public Command {
         output_format_ = OutputFormat::AutoFDO;
       } else if (output == "branch-list") {
         output_format_ = OutputFormat::BranchList;
+      } else if (output == "bolt") {
+        output_format_ = OutputFormat::Bolt;
       } else {
         LOG(ERROR) << "unknown format in --output option: " << output;
         return false;
@@ -994,6 +1082,9 @@ class InjectCommand : public Command {
         LOG(ERROR) << "unsupported data type " << data_type << " in " << filename;
         return false;
       }
+      if (hide_kernel_addresses_) {
+        reader->HideKernelAddresses();
+      }
       reader_callback(*reader);
       if (!reader->Read()) {
         return false;
@@ -1011,7 +1102,7 @@ class InjectCommand : public Command {
     if (!ReadPerfDataFiles(reader_callback)) {
       return false;
     }
-    return autofdo_writer.Write(output_filename_);
+    return WriteAutoFDOBinaries(autofdo_writer);
   }
 
   bool ConvertPerfDataToBranchList() {
@@ -1072,6 +1163,13 @@ class InjectCommand : public Command {
     }
 
     // Step3: Write AutoFDOBinaryInfo.
+    return WriteAutoFDOBinaries(autofdo_writer);
+  }
+
+  bool WriteAutoFDOBinaries(AutoFDOWriter& autofdo_writer) {
+    if (output_format_ == OutputFormat::Bolt) {
+      return autofdo_writer.WriteBolt(output_filename_);
+    }
     return autofdo_writer.Write(output_filename_);
   }
 
@@ -1096,6 +1194,7 @@ class InjectCommand : public Command {
 
   std::unique_ptr<RegEx> binary_name_regex_;
   bool exclude_perf_ = false;
+  bool hide_kernel_addresses_ = false;
   std::vector<std::string> input_filenames_;
   std::string output_filename_ = "perf_inject.data";
   OutputFormat output_format_ = OutputFormat::AutoFDO;
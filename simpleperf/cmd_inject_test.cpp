@@ -138,6 +138,23 @@ TEST(cmd_inject, inject_kernel_data) {
   ASSERT_EQ(output, autofdo_output);
 }
 
+// @CddTest = 6.1/C-0-2
+TEST(cmd_inject, hide_kernel_addresses_option) {
+  const std::string recording_file =
+      GetTestData(std::string("etm") + OS_PATH_SEPARATOR + "perf_kernel.data");
+  TemporaryFile tmpfile;
+  close(tmpfile.release());
+  ASSERT_TRUE(RunInjectCmd({"-i", recording_file, "-o", tmpfile.path, "--output", "branch-list",
+                            "--hide-kernel-addresses"}));
+  // Kernel addresses keep their offsets into the kernel image, so they convert to the same vaddrs.
+  std::string output;
+  ASSERT_TRUE(RunInjectCmd({"-i", tmpfile.path}, &output));
+  std::string expected_output;
+  ASSERT_TRUE(RunInjectCmd({"-i", recording_file}, &expected_output));
+  ASSERT_NE(output.find("rq_stats.ko"), std::string::npos);
+  ASSERT_EQ(output, expected_output);
+}
+
 // @CddTest = 6.1/C-0-2
 TEST(cmd_inject, unformatted_trace) {
   std::string data;
@@ -276,6 +293,28 @@ TEST(cmd_inject, read_lbr_data) {
   ASSERT_NE(data.find("194d->1940:706"), data.npos);
 }
 
+// @CddTest = 6.1/C-0-2
+TEST(cmd_inject, output_bolt_format) {
+  std::string perf_data_path = GetTestData("lbr/perf_lbr.data");
+  std::string data;
+  ASSERT_TRUE(RunInjectCmd({"-i", perf_data_path, "--output", "bolt"}, &data));
+  ASSERT_NE(data.find("// build_id: "), data.npos);
+  ASSERT_NE(data.find("lbr_test_loop"), data.npos);
+  // BOLT lines use vaddrs, not the file offsets in AutoFDO output.
+  ASSERT_EQ(data.find("->"), data.npos);
+  ASSERT_NE(data.find("F "), data.npos);
+  ASSERT_NE(data.find("B "), data.npos);
+
+  // Converting a branch list gives the same BOLT output.
+  TemporaryFile branch_list_file;
+  close(branch_list_file.release());
+  ASSERT_TRUE(
+      RunInjectCmd({"-i", perf_data_path, "--output", "branch-list", "-o", branch_list_file.path}));
+  std::string branch_list_data;
+  ASSERT_TRUE(RunInjectCmd({"-i", branch_list_file.path, "--output", "bolt"}, &branch_list_data));
+  ASSERT_EQ(branch_list_data, data);
+}
+
 // @CddTest = 6.1/C-0-2
 TEST(cmd_inject, inject_small_binary) {
   // etm_test_loop_small, a binary compiled with
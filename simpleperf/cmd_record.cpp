@@ -2187,10 +2187,12 @@ bool RecordCommand::DumpMetaInfoFeature(bool kernel_symbols_available) {
   info_map["record_stat"] = android::base::StringPrintf(
       "sample_record_count=%" PRIu64
       ",kernelspace_lost_records=%zu,userspace_lost_samples=%zu,"
-      "userspace_lost_non_samples=%zu,userspace_truncated_stack_samples=%zu",
+      "userspace_lost_non_samples=%zu,userspace_truncated_stack_samples=%zu,"
+      "aux_data_size=%" PRIu64 ",lost_aux_data_size=%" PRIu64,
       sample_record_count_, record_stat.kernelspace_lost_records,
       record_stat.userspace_lost_samples, record_stat.userspace_lost_non_samples,
-      record_stat.userspace_truncated_stack_samples);
+      record_stat.userspace_truncated_stack_samples, record_stat.aux_data_size,
+      record_stat.lost_aux_data_size);
 
   return record_file_writer_->WriteMetaInfoFeature(info_map);
 }
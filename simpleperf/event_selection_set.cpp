@@ -663,8 +663,12 @@ bool EventSelectionSet::OpenEventFilesOnGroup(EventSelectionGroup& group, pid_t
   // successfully or all failed to open.
   EventFd* group_fd = nullptr;
   for (auto& selection : group.selections) {
+    perf_event_attr attr = selection.event_attr;
+    if (IsEtmEventType(attr.type) && cpu != -1) {
+      ETMRecorder::GetInstance().SetEtmSinkForCpu(&attr, cpu);
+    }
     std::unique_ptr<EventFd> event_fd = EventFd::OpenEventFile(
-        selection.event_attr, tid, cpu, group_fd, selection.event_type_modifier.name, false);
+        attr, tid, cpu, group_fd, selection.event_type_modifier.name, false);
     if (!event_fd) {
       *failed_event_type = selection.event_type_modifier.name;
       return false;
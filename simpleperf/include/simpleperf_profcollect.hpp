@@ -14,6 +14,9 @@
  * limitations under the License.
  */
 
+#include <stddef.h>
+#include <stdint.h>
+
 extern "C" {
 
 bool IsETMDriverAvailable();
@@ -21,6 +24,18 @@ bool IsETMDeviceAvailable();
 bool IsLBRAvailable();
 bool RunRecordCmd(const char** args, int arg_count);
 bool RunInjectCmd(const char** args, int arg_count);
+bool GetAuxDataStat(const char* trace_file, uint64_t* aux_data_size,
+                    uint64_t* lost_aux_data_size);
 void SetLogFile(const char* filename);
 void ResetLogFile();
+// Capture what is logged from now on, whether or not a log file is set, up to the last 64KB.
+void StartLogCapture();
+// Stop capturing the log, and copy up to `size` bytes of the end of the captured log to `buf`.
+// Returns the number of bytes copied.
+size_t StopLogCapture(char* buf, size_t size);
+// Find the functions of `binary` at `count` offsets of its text AutoFDO profile, as written by the
+// inject command. Copies up to `size` bytes of their names to `buf`, one per line, with an empty
+// line for offsets without a symbol. Returns the number of bytes copied.
+size_t SymbolizeAutoFDOOffsets(const char* binary, const uint64_t* offsets, size_t count,
+                               char* buf, size_t size);
 }
@@ -14,9 +14,14 @@
  * limitations under the License.
  */
 
+#include <string.h>
 #include <time.h>
 
+#include <algorithm>
+#include <mutex>
+
 #include <android-base/file.h>
+#include <android-base/parseint.h>
 #include <android-base/properties.h>
 #include <android-base/stringprintf.h>
 #include <android-base/strings.h>
@@ -26,10 +31,13 @@
 
 #include "ETMRecorder.h"
 #include "command.h"
+#include "dso.h"
 #include "event_attr.h"
 #include "event_fd.h"
 #include "event_selection_set.h"
 #include "event_type.h"
+#include "read_elf.h"
+#include "record_file.h"
 
 using namespace simpleperf;
 
@@ -104,26 +112,81 @@ bool RunInjectCmd(const char** args, int arg_count) {
   return result;
 }
 
+bool GetAuxDataStat(const char* trace_file, uint64_t* aux_data_size,
+                    uint64_t* lost_aux_data_size) {
+  auto reader = RecordFileReader::CreateInstance(trace_file);
+  if (!reader) {
+    return false;
+  }
+  const auto& meta_info = reader->GetMetaInfoFeature();
+  auto it = meta_info.find("record_stat");
+  if (it == meta_info.end()) {
+    return false;
+  }
+  bool has_aux_data_size = false;
+  bool has_lost_aux_data_size = false;
+  for (const auto& item : android::base::Split(it->second, ",")) {
+    std::vector<std::string> kv = android::base::Split(item, "=");
+    if (kv.size() != 2) {
+      continue;
+    }
+    if (kv[0] == "aux_data_size") {
+      has_aux_data_size = android::base::ParseUint(kv[1], aux_data_size);
+    } else if (kv[0] == "lost_aux_data_size") {
+      has_lost_aux_data_size = android::base::ParseUint(kv[1], lost_aux_data_size);
+    }
+  }
+  return has_aux_data_size && has_lost_aux_data_size;
+}
+
 static android::base::unique_fd log_fd;
 static android::base::LogFunction saved_log_func;
+static bool logger_installed = false;
+// The log of the running command, see StartLogCapture().
+static std::mutex captured_log_mutex;
+static bool capturing_log = false;
+static std::string captured_log;
+static constexpr size_t kMaxCapturedLogSize = 64 * 1024;
 
 static void FileLogger(android::base::LogId id, android::base::LogSeverity severity,
                        const char* tag, const char* file, unsigned int line, const char* message) {
+  static const char log_characters[] = "VDIWEFF";
+  char severity_char = log_characters[severity];
+  struct tm now;
+  time_t t = time(nullptr);
+  // Use UTC, so that the log of a report orders consistently across timezone changes.
+  gmtime_r(&t, &now);
+  char timestamp[32];
+  strftime(timestamp, sizeof(timestamp), "%Y-%m-%dT%H:%M:%SZ", &now);
+  std::string s = android::base::StringPrintf("%s %c %s %s:%u] %s\n", tag, severity_char,
+                                              timestamp, file, line, message);
   if (log_fd.ok()) {
-    static const char log_characters[] = "VDIWEFF";
-    char severity_char = log_characters[severity];
-    struct tm now;
-    time_t t = time(nullptr);
-    localtime_r(&t, &now);
-    char timestamp[32];
-    strftime(timestamp, sizeof(timestamp), "%m-%d %H:%M:%S", &now);
-    std::string s = android::base::StringPrintf("%s %c %s %s:%u] %s\n", tag, severity_char,
-                                                timestamp, file, line, message);
     WriteStringToFd(s, log_fd);
   }
+  {
+    std::lock_guard<std::mutex> lock(captured_log_mutex);
+    if (capturing_log) {
+      // Keep the end of the log, where failures are reported.
+      captured_log += s;
+      if (captured_log.size() > kMaxCapturedLogSize) {
+        captured_log.erase(0, captured_log.size() - kMaxCapturedLogSize);
+      }
+    }
+  }
   saved_log_func(id, severity, tag, file, line, message);
 }
 
+static void UpdateLogger() {
+  bool needed = log_fd.ok() || capturing_log;
+  if (needed && !logger_installed) {
+    saved_log_func = SetLogger(FileLogger);
+    logger_installed = true;
+  } else if (!needed && logger_installed) {
+    SetLogger(std::move(saved_log_func));
+    logger_installed = false;
+  }
+}
+
 void SetLogFile(const char* filename) {
   int fd = TEMP_FAILURE_RETRY(open(filename, O_APPEND | O_CREAT | O_WRONLY | O_CLOEXEC, 0600));
   if (fd == -1) {
@@ -131,10 +194,61 @@ void SetLogFile(const char* filename) {
     return;
   }
   log_fd.reset(fd);
-  saved_log_func = SetLogger(FileLogger);
+  UpdateLogger();
 }
 
 void ResetLogFile() {
   log_fd.reset();
-  SetLogger(std::move(saved_log_func));
+  UpdateLogger();
+}
+
+void StartLogCapture() {
+  {
+    std::lock_guard<std::mutex> lock(captured_log_mutex);
+    captured_log.clear();
+    capturing_log = true;
+  }
+  UpdateLogger();
+}
+
+size_t StopLogCapture(char* buf, size_t size) {
+  size_t captured_size;
+  {
+    std::lock_guard<std::mutex> lock(captured_log_mutex);
+    captured_size = std::min(size, captured_log.size());
+    // Keep the end of the log if it does not fit.
+    memcpy(buf, captured_log.data() + captured_log.size() - captured_size, captured_size);
+    captured_log.clear();
+    capturing_log = false;
+  }
+  UpdateLogger();
+  return captured_size;
+}
+
+size_t SymbolizeAutoFDOOffsets(const char* binary, const uint64_t* offsets, size_t count,
+                               char* buf, size_t size) {
+  // Offsets of text AutoFDO profiles are relative to the first load segment of the binary.
+  uint64_t base_addr = 0;
+  ElfStatus status;
+  if (auto elf = ElfFile::Open(binary, &status); elf) {
+    for (const auto& segment : elf->GetProgramHeader()) {
+      if (segment.is_load) {
+        base_addr = segment.vaddr;
+        break;
+      }
+    }
+  }
+  // Stripped binaries usually keep the symbols of their functions in .gnu_debugdata, which is
+  // read along with the symbol table.
+  std::unique_ptr<Dso> dso = Dso::CreateDso(DSO_ELF_FILE, binary);
+  std::string names;
+  for (size_t i = 0; i < count; ++i) {
+    if (const Symbol* symbol = dso->FindSymbol(offsets[i] + base_addr); symbol != nullptr) {
+      names += symbol->DemangledName();
+    }
+    names += '\n';
+  }
+  size_t copied = std::min(names.size(), size);
+  memcpy(buf, names.data(), copied);
+  return copied;
 }
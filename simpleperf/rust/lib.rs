@@ -62,6 +62,24 @@ pub fn run_inject_cmd(args: &[&str]) -> bool {
     unsafe { simpleperf_profcollect_bindgen::RunInjectCmd(pointer_args.as_mut_ptr(), arg_count) }
 }
 
+/// Returns the size of the aux data recorded in a trace file, and the size of the aux data lost
+/// while recording it, in bytes. Returns None if the trace file has no aux data statistics.
+pub fn get_aux_data_stat(trace_file: &Path) -> Option<(u64, u64)> {
+    let trace_file = path_to_cstr(trace_file);
+    let mut aux_data_size: u64 = 0;
+    let mut lost_aux_data_size: u64 = 0;
+    // SAFETY: The pointer is a valid C string, and isn't retained after the function call returns.
+    // The output pointers point to valid u64s.
+    unsafe {
+        simpleperf_profcollect_bindgen::GetAuxDataStat(
+            trace_file.as_ptr(),
+            &mut aux_data_size,
+            &mut lost_aux_data_size,
+        )
+    }
+    .then_some((aux_data_size, lost_aux_data_size))
+}
+
 /// Save logs in file.
 pub fn set_log_file(filename: &Path) {
     let log_file = path_to_cstr(filename);
@@ -78,3 +96,52 @@ pub fn reset_log_file() {
         simpleperf_profcollect_bindgen::ResetLogFile();
     }
 }
+
+/// Most bytes of the log captured by `run_capturing_log`, from the end of the log.
+const MAX_CAPTURED_LOG_SIZE: usize = 64 * 1024;
+
+/// Run `run`, capturing what simpleperf logs meanwhile whether or not a log file is set. Returns
+/// the result of `run` and the end of the captured log.
+pub fn run_capturing_log(run: impl FnOnce() -> bool) -> (bool, String) {
+    // SAFETY: This is always safe to call.
+    unsafe { simpleperf_profcollect_bindgen::StartLogCapture() };
+    let result = run();
+    let mut log = vec![0u8; MAX_CAPTURED_LOG_SIZE];
+    // SAFETY: The buffer is valid for writes of its length, and isn't retained after the function
+    // call returns.
+    let size = unsafe {
+        simpleperf_profcollect_bindgen::StopLogCapture(log.as_mut_ptr() as *mut c_char, log.len())
+    };
+    log.truncate(size);
+    (result, String::from_utf8_lossy(&log).into_owned())
+}
+
+/// Most bytes of a function name returned by `symbolize_autofdo_offsets`, longer names may leave
+/// the names of the last offsets out.
+const MAX_SYMBOL_NAME_SIZE: usize = 256;
+
+/// Returns the names of the functions of `binary` at `offsets` of its text AutoFDO profile, None
+/// for the offsets without a symbol.
+pub fn symbolize_autofdo_offsets(binary: &Path, offsets: &[u64]) -> Vec<Option<String>> {
+    let binary = path_to_cstr(binary);
+    let mut names = vec![0u8; offsets.len() * MAX_SYMBOL_NAME_SIZE];
+    // SAFETY: The pointer is a valid C string, the offsets and the buffer are valid for reads and
+    // writes of their lengths, and none is retained after the function call returns.
+    let size = unsafe {
+        simpleperf_profcollect_bindgen::SymbolizeAutoFDOOffsets(
+            binary.as_ptr(),
+            offsets.as_ptr(),
+            offsets.len(),
+            names.as_mut_ptr() as *mut c_char,
+            names.len(),
+        )
+    };
+    names.truncate(size);
+    let names = String::from_utf8_lossy(&names);
+    // Only complete lines, the last one may have been cut.
+    let complete = &names[..names.rfind('\n').map_or(0, |end| end + 1)];
+    let mut names: Vec<Option<String>> =
+        complete.lines().map(|name| (!name.is_empty()).then(|| name.to_string())).collect();
+    names.resize(offsets.len(), None);
+    names
+}